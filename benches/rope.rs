@@ -0,0 +1,117 @@
+//! Benchmarks for the `Rope` backing each `Buffer`.
+//!
+//! These run against a generated file several hundred megabytes in size, to
+//! make sure line lookups and edits don't depend on the size of the text.
+//! Set `IOTA_BENCH_MB` to change the size of the file (default: 256).
+//!
+//! Run with `cargo bench`.
+
+#![feature(test)]
+
+extern crate test;
+extern crate iota;
+extern crate tempdir;
+#[macro_use] extern crate lazy_static;
+
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::sync::Mutex;
+
+use iota::Rope;
+use tempdir::TempDir;
+use test::{Bencher, black_box};
+
+const LINE: &'static str = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, ünïcödé 日本語\n";
+
+lazy_static! {
+    static ref ROPE: Mutex<Rope> = Mutex::new(load_file());
+}
+
+/// Write a large file to disk and read it back in, the same way a `Buffer` would.
+fn load_file() -> Rope {
+    let size = env::var("IOTA_BENCH_MB").ok()
+                                        .and_then(|s| s.parse::<usize>().ok())
+                                        .unwrap_or(256) * 1024 * 1024;
+    let dir = TempDir::new("iota-bench").unwrap();
+    let path = dir.path().join("large.txt");
+
+    {
+        let mut file = File::create(&path).unwrap();
+        let mut written = 0;
+        while written < size {
+            file.write_all(LINE.as_bytes()).unwrap();
+            written += LINE.len();
+        }
+    }
+
+    let mut contents = String::new();
+    File::open(&path).unwrap().read_to_string(&mut contents).unwrap();
+    Rope::from(&*contents)
+}
+
+#[bench]
+fn line_of_middle(b: &mut Bencher) {
+    let rope = ROPE.lock().unwrap();
+    let idx = rope.len() / 2;
+    b.iter(|| black_box(rope.line_of(idx)));
+}
+
+#[bench]
+fn line_start_middle(b: &mut Bencher) {
+    let rope = ROPE.lock().unwrap();
+    let line = rope.line_count() / 2;
+    b.iter(|| black_box(rope.line_start(line)));
+}
+
+#[bench]
+fn line_start_last(b: &mut Bencher) {
+    let rope = ROPE.lock().unwrap();
+    let line = rope.line_count() - 1;
+    b.iter(|| black_box(rope.line_start(line)));
+}
+
+#[bench]
+fn char_at_end(b: &mut Bencher) {
+    let rope = ROPE.lock().unwrap();
+    let idx = rope.len() - 1;
+    b.iter(|| black_box(rope.char_at(idx)));
+}
+
+/// What `Buffer::set_mark` does to place a mark.
+#[bench]
+fn mark_position_middle(b: &mut Bencher) {
+    let rope = ROPE.lock().unwrap();
+    let idx = rope.len() / 2 + 17;
+    b.iter(|| {
+        let line = rope.line_of(idx);
+        black_box((line, rope.line_start(line)))
+    });
+}
+
+#[bench]
+fn insert_remove_char_middle(b: &mut Bencher) {
+    let mut rope = ROPE.lock().unwrap();
+    let idx = rope.len() / 2;
+    b.iter(|| {
+        rope.insert(idx, 'x');
+        black_box(rope.remove(idx))
+    });
+}
+
+#[bench]
+fn insert_remove_line_start(b: &mut Bencher) {
+    let mut rope = ROPE.lock().unwrap();
+    b.iter(|| {
+        rope.insert_str(0, LINE);
+        black_box(rope.remove_range(0, LINE.chars().count()))
+    });
+}
+
+#[bench]
+fn read_screen_from_middle(b: &mut Bencher) {
+    let rope = ROPE.lock().unwrap();
+    let start = rope.line_start(rope.line_count() / 2).unwrap();
+    let screen = LINE.chars().count() * 50;
+    b.iter(|| black_box(rope.chars_at(start).take(screen).count()));
+}
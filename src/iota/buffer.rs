@@ -1,27 +1,48 @@
-//FIXME: Check unicode support
 // stdlib dependencies
+use std::cell::RefCell;
 use std::cmp;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::fs::File;
-use std::io::{Stdin, Read};
+use std::io::{self, Read};
 use std::convert::From;
+use std::time::Duration;
 
 // external dependencies
-use gapbuffer::GapBuffer;
+use unicode_width::UnicodeWidthChar;
+use regex::{Captures, Regex};
 
 // local dependencies
 use log::{Log, Change, LogEntry};
-use input::Input;
 use iterators::Lines;
+use rope::Rope;
+use search::{self, CaseMatching};
 use textobject::{TextObject, Kind, Offset, Anchor};
+use undofile;
+use utils;
 
 
+/// Which way a mark moves when text is inserted exactly at its position.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Gravity {
+    /// The mark stays where it is, before the inserted text.
+    Left,
+    /// The mark moves along to the end of the inserted text.
+    Right,
+}
+
+impl Default for Gravity {
+    fn default() -> Gravity {
+        Gravity::Left
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub struct MarkPosition {
     pub absolute: usize,
     absolute_line_start: usize,
     line_number: usize,
+    gravity: Gravity,
 }
 
 impl MarkPosition {
@@ -30,6 +51,15 @@ impl MarkPosition {
             absolute: 0,
             line_number: 0,
             absolute_line_start: 0,
+            gravity: Gravity::default(),
+        }
+    }
+
+    /// Recalculate the line information after `absolute` has changed.
+    fn update_line_info(&mut self, text: &Rope) {
+        if let Some(pos) = get_line_info(self.absolute, text) {
+            self.absolute_line_start = pos.absolute_line_start;
+            self.line_number = pos.line_number;
         }
     }
 }
@@ -54,6 +84,9 @@ pub enum Mark {
 
     /// For using in determining some display of characters
     DisplayMark(usize),
+
+    /// For the other end of a selection, which runs from here to the cursor.
+    Anchor(usize),
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
@@ -63,7 +96,7 @@ pub enum WordEdgeMatch {
 
 pub struct Buffer {
     /// Current buffers text
-    text: GapBuffer<u8>,
+    text: Rope,
 
     /// Table of marked indices in the text
     marks: HashMap<Mark, MarkPosition>,
@@ -76,6 +109,10 @@ pub struct Buffer {
 
     /// Whether or not the Buffer has unsaved changes
     pub dirty: bool,
+
+    /// The pattern of the last match object looked for, and its regex, so
+    /// repeating it doesn't compile it again
+    match_regex: RefCell<Option<(String, Regex)>>,
 }
 
 #[cfg_attr(feature="clippy", allow(len_without_is_empty))]
@@ -84,22 +121,67 @@ impl Buffer {
     pub fn new() -> Buffer {
         Buffer {
             file_path: None,
-            text: GapBuffer::new(),
+            text: Rope::new(),
             marks: HashMap::new(),
             log: Log::new(),
             dirty: false,
+            match_regex: RefCell::new(None),
+        }
+    }
+
+    /// Open the file at `path`, or start a new one if there is no such file.
+    ///
+    /// Files that are not UTF-8 are refused rather than opened with their
+    /// undecodable bytes replaced, since saving them again would lose those bytes.
+    pub fn open(path: PathBuf) -> io::Result<Buffer> {
+        match File::open(&path) {
+            Ok(mut file) => {
+                let mut buf = Buffer::read(&mut file)
+                    .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+                if let Some(log) = undofile::load(&path, buf.text_hash()) {
+                    buf.log = log;
+                }
+                buf.file_path = Some(path);
+                Ok(buf)
+            }
+            // a new file, which will be created when the buffer is saved
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                let mut buf = Buffer::new();
+                buf.file_path = Some(path);
+                Ok(buf)
+            }
+            Err(e) => Err(io::Error::new(e.kind(), format!("{}: {}", path.display(), e))),
         }
     }
 
+    /// A buffer holding everything `reader` gives, which must be UTF-8.
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<Buffer> {
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents)?;
+        let text = String::from_utf8(contents)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "not UTF-8 text"))?;
+        let mut buff = Buffer::new();
+        buff.text = Rope::from(&*text);
+        Ok(buff)
+    }
+
     /// Length of the text stored in this buffer.
     pub fn len(&self) -> usize {
         self.text.len() + 1
     }
 
     /// The x,y coordinates of a mark within the file. None if not a valid mark.
+    ///
+    /// The x coordinate is a display column, so wide characters and tabs
+    /// count for as many cells as they take up on screen.
     pub fn get_mark_display_coords(&self, mark: Mark) -> Option<(usize, usize)> {
         if let Some(mark_pos) = self.marks.get(&mark) {
-            return Some((mark_pos.absolute - mark_pos.absolute_line_start, mark_pos.line_number))
+            let end = cmp::min(mark_pos.absolute, self.text.len());
+            let mut x = 0;
+            for ch in self.text.chars_at(mark_pos.absolute_line_start).take(end - mark_pos.absolute_line_start) {
+                x += utils::char_width(ch, false, 4, x).unwrap_or(1);
+            }
+            return Some((x, mark_pos.line_number))
         }
 
         None
@@ -142,9 +224,94 @@ impl Buffer {
     pub fn get_object_index(&self, obj: TextObject) -> Option<MarkPosition> {
         match obj.kind {
             Kind::Char => self.get_char_index(obj.offset),
+            Kind::LineChar => self.get_line_char_index(obj.offset),
             Kind::Line(anchor) => self.get_line_index(obj.offset, anchor),
             Kind::Word(anchor) => self.get_word_index(obj.offset, anchor),
+            Kind::Match(ref pattern, anchor) => self.get_match_index(obj.offset, pattern, anchor),
+        }
+    }
+
+    /// Get the position of a match of the regular expression `pattern`
+    ///
+    /// Matches are counted from a mark, not including one starting at the mark
+    /// itself, or from the start of the buffer.  Case is matched exactly, unless
+    /// the pattern says otherwise with `(?i)`.
+    ///
+    /// ie: get the index of the start of the 2nd match after the cursor
+    /// or: get the index of the end of the 1st match before the cursor
+    fn get_match_index(&self, offset: Offset, pattern: &str, anchor: Anchor) -> Option<MarkPosition> {
+        let regex = self.match_regex(pattern)?;
+        let (start, end) = match offset {
+            Offset::Forward(n, from_mark) => {
+                let from = self.marks.get(&from_mark)?.absolute;
+                self.find_match(&regex, Some(from), n, false)?
+            }
+            Offset::Backward(n, from_mark) => {
+                let from = self.marks.get(&from_mark)?.absolute;
+                self.find_match(&regex, Some(from), n, true)?
+            }
+            Offset::Absolute(n) => self.find_match(&regex, None, n, false)?,
+        };
+        let idx = match anchor {
+            Anchor::Before => start.saturating_sub(1),
+            Anchor::Start | Anchor::Same => start,
+            // an empty match has no last char, so ends where it starts
+            Anchor::End => cmp::max(end, start + 1) - 1,
+            Anchor::After => end,
+        };
+        get_line_info(idx, &self.text)
+    }
+
+    /// The regex for the match object `pattern`, compiled again only when the
+    /// pattern has changed.
+    fn match_regex(&self, pattern: &str) -> Option<Regex> {
+        let mut cached = self.match_regex.borrow_mut();
+        match *cached {
+            Some((ref last, ref regex)) if last == pattern => return Some(regex.clone()),
+            _ => {}
         }
+        let regex = search::search_regex(pattern, CaseMatching::Sensitive).ok()?;
+        *cached = Some((pattern.to_string(), regex.clone()));
+        Some(regex)
+    }
+
+    /// The `n`th match of `regex` starting after the char at `from`, or the
+    /// `n`th starting before it when `backward` is set, as its start and end.
+    /// Without `from`, matches are counted from the start of the buffer, or
+    /// from its end.
+    ///
+    /// The buffer is searched a line at a time going out from `from`, so no
+    /// more of it is looked at than needed, and matches don't take in newlines.
+    pub fn find_match(&self, regex: &Regex, from: Option<usize>, n: usize, backward: bool) -> Option<(usize, usize)> {
+        let nth = cmp::max(n, 1) - 1;
+        let last_line = self.text.line_of(self.text.len());
+        if backward {
+            let first = from.map_or(last_line, |from| self.text.line_of(from));
+            (0..first + 1).rev()
+                .flat_map(|line| self.line_matches(regex, line).into_iter().rev())
+                .filter(|&(start, _)| from.map_or(true, |from| start < from))
+                .nth(nth)
+        } else {
+            let first = from.map_or(0, |from| self.text.line_of(from));
+            (first..last_line + 1)
+                .flat_map(|line| self.line_matches(regex, line))
+                .filter(|&(start, _)| from.map_or(true, |from| start > from))
+                .nth(nth)
+        }
+    }
+
+    /// Every match of `regex` on the (zero based) `line`, leaving out the
+    /// newline ending it.
+    fn line_matches(&self, regex: &Regex, line: usize) -> Vec<(usize, usize)> {
+        let start = match self.text.line_start(line) {
+            Some(start) => start,
+            None => return Vec::new(),
+        };
+        let end = self.text.nth_newline(line).unwrap_or_else(|| self.text.len());
+        let text: String = self.text.chars_at(start).take(end - start).collect();
+        search::find_all(regex, &text).into_iter()
+            .map(|(match_start, match_end)| (start + match_start, start + match_end))
+            .collect()
     }
 
     /// Get the position of a specific character in the buffer
@@ -165,13 +332,15 @@ impl Buffer {
             Offset::Forward(offset, from_mark) => {
                 let last = self.len() - 1;
                 if let Some(mark_pos) = self.marks.get(&from_mark) {
-                    let new_absolute_position = mark_pos.absolute + offset;
+                    let mut new_absolute_position = mark_pos.absolute + offset;
+                    // don't land between a character and its combining marks
+                    while new_absolute_position < last && is_combining_at(new_absolute_position, text) {
+                        new_absolute_position += 1;
+                    }
                     if new_absolute_position < last {
-                        // FIXME: it would be nice if we could avoid using get_line_info here...
                         let new_mark_pos = get_line_info(new_absolute_position, text).unwrap();
                         return Some(new_mark_pos)
                     } else {
-                        // FIXME: it would be nice if we could avoid using get_line_info here...
                         let new_mark_pos = get_line_info(last, text).unwrap();
                         return Some(new_mark_pos)
                     }
@@ -187,8 +356,10 @@ impl Buffer {
             Offset::Backward(offset, from_mark) => {
                 if let Some(mark_pos) = self.marks.get(&from_mark) {
                     if mark_pos.absolute >= offset {
-                        let new_absolute_position = mark_pos.absolute - offset;
-                        // FIXME: it would be nice if we could avoid using get_line_info here...
+                        let mut new_absolute_position = mark_pos.absolute - offset;
+                        while new_absolute_position > 0 && is_combining_at(new_absolute_position, text) {
+                            new_absolute_position -= 1;
+                        }
                         let new_mark_pos = get_line_info(new_absolute_position, text).unwrap();
                         return Some(new_mark_pos);
                     } else {
@@ -210,6 +381,25 @@ impl Buffer {
         }
     }
 
+    /// Get the index of a char `offset` chars from a mark, like `get_char_index`,
+    /// but going no further than the start of the mark's line, or the newline
+    /// ending it.
+    ///
+    /// ie: what `x` deletes up to, so it never joins lines
+    fn get_line_char_index(&self, offset: Offset) -> Option<MarkPosition> {
+        let idx = self.get_char_index(offset)?.absolute;
+        let idx = match offset {
+            Offset::Forward(_, mark) | Offset::Backward(_, mark) => {
+                let line = self.text.line_of(self.marks.get(&mark)?.absolute);
+                let start = self.text.line_start(line)?;
+                let end = self.text.nth_newline(line).unwrap_or_else(|| self.text.len());
+                cmp::max(start, cmp::min(idx, end))
+            }
+            Offset::Absolute(_) => idx,
+        };
+        get_line_info(idx, &self.text)
+    }
+
     /// Get the position of a specific line in the buffer
     ///
     /// This line can be at an absolute position, or a postion relative
@@ -237,14 +427,15 @@ impl Buffer {
     /// or: Get the index of the start of the 23th line
     fn get_line_index_absolute(&self, anchor: Anchor, line_number: usize) -> Option<MarkPosition> {
         let text = &self.text;
+        if line_number == 0 { return None }
 
-        let nlines = (0..text.len()).filter(|i| text[*i] == b'\n')
-                                    .take(line_number + 1)
-                                    .collect::<Vec<usize>>();
         match anchor {
             Anchor::Start => {
                 let mut mark_pos = MarkPosition::start();
-                let line_start = nlines[0] + 1;
+                let line_start = match text.line_start(line_number - 1) {
+                    Some(idx) => idx,
+                    None => return None,
+                };
 
                 mark_pos.absolute = line_start;
                 mark_pos.absolute_line_start = line_start;
@@ -255,7 +446,7 @@ impl Buffer {
 
             Anchor::End => {
                 let mut mark_pos = MarkPosition::start();
-                let end_offset = nlines[line_number - 1];
+                let end_offset = text.nth_newline(line_number - 1).unwrap_or_else(|| text.len());
 
                 mark_pos.absolute = end_offset;
 
@@ -273,9 +464,7 @@ impl Buffer {
     fn get_line_index_backward(&self, anchor: Anchor, offset: usize, from_mark: Mark) -> Option<MarkPosition> {
         let text = &self.text;
         if let Some(mark_pos) = self.marks.get(&from_mark) {
-            let nlines = (0..mark_pos.absolute).rev().filter(|i| text[*i] == b'\n')
-                                         .take(offset + 1)
-                                         .collect::<Vec<usize>>();
+            let nlines = newlines_before(mark_pos.absolute, offset + 1, text);
 
             match anchor {
                 // Get the index of the start of the desired line
@@ -329,9 +518,7 @@ impl Buffer {
         let text = &self.text;
         let last = self.len() - 1;
         if let Some(mark_pos) = self.marks.get(&from_mark) {
-            let nlines = (mark_pos.absolute..text.len()).filter(|i| text[*i] == b'\n')
-                                            .take(offset + 1)
-                                            .collect::<Vec<usize>>();
+            let nlines = newlines_after(mark_pos.absolute, offset + 1, text);
             if nlines.is_empty() { return None }
 
             match anchor {
@@ -500,6 +687,19 @@ impl Buffer {
         }
     }
 
+    /// Hash of the text, used to tell whether a saved history still applies to it.
+    pub fn text_hash(&self) -> u64 {
+        undofile::hash_text(self.text.chars_at(0))
+    }
+
+    /// Write the undo history out, so it can be restored the next time the file is opened.
+    pub fn save_history(&self) -> io::Result<()> {
+        match self.file_path {
+            Some(ref path) => undofile::save(path, self.text_hash(), &self.log),
+            None => Ok(()),
+        }
+    }
+
     /// Sets the mark to the location of a given TextObject, if it exists.
     /// Adds a new mark or overwrites an existing mark.
     pub fn set_mark_to_object(&mut self, mark: Mark, obj: TextObject) {
@@ -508,6 +708,15 @@ impl Buffer {
         }
     }
 
+    /// Sets which way an existing mark moves when text is inserted at its position.
+    ///
+    /// Marks have left gravity unless told otherwise.
+    pub fn set_mark_gravity(&mut self, mark: Mark, gravity: Gravity) {
+        if let Some(mark_pos) = self.marks.get_mut(&mark) {
+            mark_pos.gravity = gravity;
+        }
+    }
+
     /// Sets the mark to a given absolute index. Adds a new mark or overwrites an existing mark.
     pub fn set_mark(&mut self, mark: Mark, idx: usize) {
         if let Some(mark_pos) = get_line_info(idx, &self.text) {
@@ -522,22 +731,139 @@ impl Buffer {
     }
 
     // Remove the chars in the range from start to end
-    pub fn remove_range(&mut self, start: usize, end: usize) -> Option<Vec<u8>> {
+    pub fn remove_range(&mut self, start: usize, end: usize) -> Option<Vec<char>> {
+        // an empty range changes nothing, so there is nothing to undo
+        if end <= start { return None }
         self.dirty = true;
-        let text = &mut self.text;
+        let removed = self.text.remove_range(start, end);
+        let chars = removed.chars().collect::<Vec<char>>();
+        shift_marks_for_remove(&mut self.marks, &self.text, start, start + chars.len());
         let mut transaction = self.log.start(start);
-        let mut vec = (start..end)
-            .rev()
-            .filter_map(|idx| text.remove(idx).map(|ch| (idx, ch)))
-            .inspect(|&(idx, ch)| transaction.log(Change::Remove(idx, ch), idx))
-            .map(|(_, ch)| ch)
-            .collect::<Vec<u8>>();
-        vec.reverse();
-        Some(vec)
+        transaction.log(Change::RemoveRange(start, removed), start);
+        Some(chars)
+    }
+
+    /// Remove the lines from `first` to `last`, counting from 1 and including
+    /// both, along with their newlines.
+    pub fn remove_lines(&mut self, first: usize, last: usize) -> Option<Vec<char>> {
+        if first == 0 || last < first { return None }
+        let mut start = match self.text.line_start(first - 1) {
+            Some(idx) => idx,
+            None => return None,
+        };
+        let end = match self.text.line_start(last) {
+            Some(idx) => idx,
+            // the last line has no newline after it, so take the one before
+            None => {
+                if start > 0 { start -= 1; }
+                self.text.len()
+            }
+        };
+        self.remove_range(start, end)
+    }
+
+    /// Replace matches of `regex` in the lines from `first` to `last` (counting
+    /// from 1 and including both) with `replacement`, as a single change.
+    ///
+    /// Only the first match on each line is replaced unless `global` is set.
+    /// Returns how many matches were replaced.
+    pub fn substitute(&mut self, first: usize, last: usize, regex: &Regex, replacement: &str, global: bool) -> usize {
+        let mut count = 0;
+        self.log.open_transaction();
+        // work backwards, so that replacing text doesn't move the lines still to do
+        for line in (cmp::max(first, 1)..last + 1).rev() {
+            let start = match self.text.line_start(line - 1) {
+                Some(idx) => idx,
+                None => continue,
+            };
+            let end = self.text.nth_newline(line - 1).unwrap_or_else(|| self.text.len());
+            let text: String = self.text.chars_at(start).take(end - start).collect();
+
+            let matches = regex.find_iter(&text).count();
+            if matches == 0 { continue }
+            let expand = |captures: &Captures| search::expand(captures, replacement);
+            let replaced = if global {
+                count += matches;
+                regex.replace_all(&text, expand)
+            } else {
+                count += 1;
+                regex.replace(&text, expand)
+            };
+            if replaced != text {
+                self.replace_range(start, end, &replaced);
+            }
+        }
+        self.log.commit_transaction();
+        count
+    }
+
+    /// The first match of `regex` starting at or after `from`, on the lines up
+    /// to `last` (counting from 1), as its start and end and the text
+    /// `replacement` stands for in its place.
+    pub fn find_substitution(&self, from: usize, last: usize, regex: &Regex, replacement: &str) -> Option<(usize, usize, String)> {
+        for line in self.text.line_of(from)..last {
+            let start = self.text.line_start(line)?;
+            let end = self.text.nth_newline(line).unwrap_or_else(|| self.text.len());
+            let text: String = self.text.chars_at(start).take(end - start).collect();
+
+            for captures in regex.captures_iter(&text) {
+                let (byte_start, byte_end) = captures.pos(0)?;
+                let match_start = start + text[..byte_start].chars().count();
+                if match_start < from { continue }
+                let match_end = match_start + text[byte_start..byte_end].chars().count();
+                return Some((match_start, match_end, search::expand(&captures, replacement)));
+            }
+        }
+        None
+    }
+
+    /// The (zero based) line containing the char at `idx`.
+    pub fn line_of(&self, idx: usize) -> usize {
+        self.text.line_of(idx)
+    }
+
+    /// The span of the (zero based) lines from `first` to `last`, including the
+    /// newline ending the last of them if it has one.
+    pub fn line_span(&self, first: usize, last: usize) -> (usize, usize) {
+        let start = self.text.line_start(first).unwrap_or_else(|| self.text.len());
+        let end = self.text.nth_newline(last).map_or(self.text.len(), |idx| idx + 1);
+        (start, end)
+    }
+
+    /// The char at `idx`, if there is one.
+    pub fn char_at(&self, idx: usize) -> Option<char> {
+        self.text.char_at(idx)
+    }
+
+    /// The text from `start` to `end`.
+    pub fn text_range(&self, start: usize, end: usize) -> String {
+        self.text.chars_at(start).take(end.saturating_sub(start)).collect()
+    }
+
+    /// Number of lines in the buffer, not counting the empty one after a
+    /// final newline.
+    pub fn line_count(&self) -> usize {
+        let len = self.text.len();
+        if len > 0 && self.text.char_at(len - 1) == Some('\n') {
+            self.text.line_count() - 1
+        } else {
+            self.text.line_count()
+        }
+    }
+
+    /// Replace the chars in the range from start to end with `text`, as a single change.
+    ///
+    /// Returns the chars that were removed.
+    pub fn replace_range(&mut self, start: usize, end: usize, text: &str) -> Option<Vec<char>> {
+        self.log.open_transaction();
+        let removed = self.remove_range(start, end);
+        self.insert_string_at(start, text);
+        self.log.commit_transaction();
+        removed
     }
 
     // Remove the chars between mark and object
-    pub fn remove_from_mark_to_object(&mut self, mark: Mark, object: TextObject) -> Option<Vec<u8>> {
+    pub fn remove_from_mark_to_object(&mut self, mark: Mark, object: TextObject) -> Option<Vec<char>> {
 
         let (start, end) = {
             let mark_pos = &self.marks[&mark];
@@ -552,7 +878,7 @@ impl Buffer {
         self.remove_range(start, end)
     }
 
-    pub fn remove_object(&mut self, object: TextObject) -> Option<Vec<u8>> {
+    pub fn remove_object(&mut self, object: TextObject) -> Option<Vec<char>> {
         let object_start = TextObject { kind: object.kind.with_anchor(Anchor::Start), offset: object.offset };
         let object_end = TextObject { kind: object.kind.with_anchor(Anchor::End), offset: object.offset };
 
@@ -566,94 +892,105 @@ impl Buffer {
     }
 
     /// Insert a char at the mark.
-    pub fn insert_char(&mut self, mark: Mark, ch: u8) {
-        if let Some(mark_pos) = self.marks.get(&mark) {
-            self.text.insert(mark_pos.absolute, ch);
-            let mut transaction = self.log.start(mark_pos.absolute);
-            transaction.log(Change::Insert(mark_pos.absolute, ch), mark_pos.absolute);
+    pub fn insert_char(&mut self, mark: Mark, ch: char) {
+        if let Some(idx) = self.marks.get(&mark).map(|mark_pos| mark_pos.absolute) {
+            self.text.insert(idx, ch);
+            shift_marks_for_insert(&mut self.marks, &self.text, idx, 1);
+            let mut transaction = self.log.start(idx);
+            transaction.log(Change::Insert(idx, ch), idx);
             self.dirty = true;
         }
     }
 
+    /// Insert a string at `idx`, as a single change.
+    pub fn insert_string_at(&mut self, idx: usize, text: &str) {
+        if text.is_empty() { return }
+        let len = text.chars().count();
+        self.text.insert_str(idx, text);
+        shift_marks_for_insert(&mut self.marks, &self.text, idx, len);
+        let mut transaction = self.log.start(idx);
+        transaction.log(Change::InsertRange(idx, text.to_string()), idx + len);
+        self.dirty = true;
+    }
+
+    /// Start grouping edits into a single undoable transaction.
+    ///
+    /// Every edit made until the matching `commit_transaction` is undone in one step.
+    pub fn start_transaction(&mut self) {
+        self.log.open_transaction();
+    }
+
+    /// Commit the transaction opened by `start_transaction`.
+    pub fn commit_transaction(&mut self) {
+        self.log.commit_transaction();
+    }
+
     /// Redo most recently undone action.
-    pub fn redo(&mut self) -> Option<&LogEntry> {
+    pub fn redo(&mut self) -> Option<LogEntry> {
         if let Some(transaction) = self.log.redo() {
-            commit(transaction, &mut self.text);
+            commit(&transaction, &mut self.text, &mut self.marks);
             Some(transaction)
         } else { None }
     }
 
     /// Undo most recently performed action.
-    pub fn undo(&mut self) -> Option<&LogEntry> {
+    pub fn undo(&mut self) -> Option<LogEntry> {
         if let Some(transaction) = self.log.undo() {
-            commit(transaction, &mut self.text);
+            commit(&transaction, &mut self.text, &mut self.marks);
             Some(transaction)
         } else { None }
     }
 
-}
+    /// Switch to the next branch of the undo tree. Returns the point of the last change made.
+    pub fn undo_branch_next(&mut self) -> Option<usize> {
+        let entries = self.log.goto_branch(true);
+        self.replay(entries)
+    }
 
+    /// Switch to the previous branch of the undo tree. Returns the point of the last change made.
+    pub fn undo_branch_prev(&mut self) -> Option<usize> {
+        let entries = self.log.goto_branch(false);
+        self.replay(entries)
+    }
 
-// This is a bit of a hack to get around an error I was getting when
-// implementing From<R: Read> for Buffer with From<PathBuf> for Buffer.
-// The compiler was telling me this was a conflicting implementation even
-// though Read is not implemented for PathBuf. Changing R: Read to
-// R: Read + BufferFrom fixes the error.
-//
-// TODO: investigate this further - possible compiler bug?
-pub trait BufferFrom {}
-impl BufferFrom for Stdin {}
-impl BufferFrom for File {}
+    /// Go back to the state the buffer was in `duration` ago. Returns the point of the last
+    /// change made.
+    pub fn undo_earlier(&mut self, duration: Duration) -> Option<usize> {
+        let entries = self.log.goto_earlier(duration);
+        self.replay(entries)
+    }
 
-impl From<PathBuf> for Buffer {
-    fn from(path: PathBuf) -> Buffer {
-        match File::open(&path) {
-            Ok(file) => {
-                let mut buf = Buffer::from(file);
-                buf.file_path = Some(path);
-                buf
-            }
-            Err(_) => {
-                Buffer::new()
-            }
-        }
+    /// Go forward to the state the buffer was in `duration` later. Returns the point of the last
+    /// change made.
+    pub fn undo_later(&mut self, duration: Duration) -> Option<usize> {
+        let entries = self.log.goto_later(duration);
+        self.replay(entries)
     }
-}
 
-impl<R: Read + BufferFrom> From<R> for Buffer {
-    fn from(mut reader: R) -> Buffer {
-        let mut buff = Buffer::new();
-        let mut contents = String::new();
-        if reader.read_to_string(&mut contents).is_ok() {
-            buff.text.extend(contents.bytes());
-        }
-        buff
+    /// Go to a state in the undo tree. Returns the point of the last change made.
+    pub fn undo_goto(&mut self, state: usize) -> Option<usize> {
+        let entries = self.log.goto(state);
+        self.replay(entries)
     }
-}
 
-impl From<Input> for Buffer {
-    fn from(input: Input) -> Buffer {
-        match input {
-            Input::Filename(path) => {
-                match path {
-                    Some(path) => Buffer::from(PathBuf::from(path)),
-                    None       => Buffer::new(),
-                }
-            },
-            Input::Stdin(reader) => {
-                Buffer::from(reader)
-            }
+    /// Perform entries from the log in order, returning the point of the last one.
+    fn replay(&mut self, entries: Vec<LogEntry>) -> Option<usize> {
+        let mut point = None;
+        for entry in &entries {
+            commit(entry, &mut self.text, &mut self.marks);
+            point = Some(entry.end_point);
         }
+        point
     }
+
 }
 
 
 impl WordEdgeMatch {
     /// If c1 -> c2 is the start of a word.
     /// If end of word matching is wanted then pass the chars in reversed.
-    fn is_word_edge(&self, c1: &u8, c2: &u8) -> bool {
-        // FIXME: unicode support - issue #69
-        match (self, *c1 as char, *c2 as char) {
+    fn is_word_edge(&self, c1: &char, c2: &char) -> bool {
+        match (self, *c1, *c2) {
             (_, '\n', '\n') => true, // Blank lines are always counted as a word
             (&WordEdgeMatch::Whitespace, c1, c2) => c1.is_whitespace() && !c2.is_whitespace(),
             // (&WordEdgeMatch::Alphabet, c1, c2) if c1.is_whitespace() => !c2.is_whitespace(),
@@ -664,50 +1001,125 @@ impl WordEdgeMatch {
     }
 }
 
-fn get_words(mark: usize, n_words: usize, edger: WordEdgeMatch, text: &GapBuffer<u8>) -> Option<usize> {
+/// Whether `ch` combines with the character before it, rather than
+/// standing on its own (accents, variation selectors, joiners, etc).
+fn is_combining(ch: char) -> bool {
+    ch != '\n' && UnicodeWidthChar::width(ch) == Some(0)
+}
+
+fn is_combining_at(idx: usize, text: &Rope) -> bool {
+    text.char_at(idx).map_or(false, is_combining)
+}
+
+fn get_words(mark: usize, n_words: usize, edger: WordEdgeMatch, text: &Rope) -> Option<usize> {
     let text_len = text.len();
     if text_len == 0 { return None; }
 
+    let mut chars = text.chars_at(mark);
+    let mut prev = match chars.next() {
+        Some(ch) => ch,
+        None => return None,
+    };
     (mark + 1..text_len - 1)
-        .filter(|idx| edger.is_word_edge(&text[*idx - 1], &text[*idx]))
+        .zip(chars)
+        .filter(|&(_, ch)| {
+            let edge = edger.is_word_edge(&prev, &ch);
+            prev = ch;
+            edge
+        })
+        .map(|(idx, _)| idx)
         .take(n_words)
         .last()
 }
 
-fn get_words_rev(mark: usize, n_words: usize, edger: WordEdgeMatch, text: &GapBuffer<u8>) -> Option<usize> {
+fn get_words_rev(mark: usize, n_words: usize, edger: WordEdgeMatch, text: &Rope) -> Option<usize> {
+    let mut chars = text.chars_before(mark);
+    let mut next = match chars.next() {
+        Some(ch) => ch,
+        None => return None,
+    };
     (1..mark)
         .rev()
-        .filter(|idx| edger.is_word_edge(&text[*idx - 1], &text[*idx]))
+        .zip(chars)
+        .filter(|&(_, ch)| {
+            let edge = edger.is_word_edge(&ch, &next);
+            next = ch;
+            edge
+        })
+        .map(|(idx, _)| idx)
         .take(n_words)
         .last()
 }
 
-fn get_line_info(mark: usize, text: &GapBuffer<u8>) -> Option<MarkPosition> {
-    let val = cmp::min(mark, text.len());
-    let line_starts: Vec<usize> = (0..val + 1).rev().filter(|idx| *idx == 0 || text[*idx - 1] == b'\n').collect();
+/// Indices of up to `n` newlines before `idx`, nearest first.
+fn newlines_before(idx: usize, n: usize, text: &Rope) -> Vec<usize> {
+    let line = text.line_of(idx);
+    (0..cmp::min(n, line)).filter_map(|k| text.nth_newline(line - 1 - k)).collect()
+}
+
+/// Indices of up to `n` newlines at or after `idx`, nearest first.
+fn newlines_after(idx: usize, n: usize, text: &Rope) -> Vec<usize> {
+    let line = text.line_of(idx);
+    (line..cmp::min(line + n, text.line_count() - 1)).filter_map(|k| text.nth_newline(k)).collect()
+}
 
+fn get_line_info(mark: usize, text: &Rope) -> Option<MarkPosition> {
+    let line_number = text.line_of(mark);
 
-    if line_starts.is_empty() {
-        None
-    } else {
+    text.line_start(line_number).map(|line_start| {
         let mut mark_pos = MarkPosition::start();
-        mark_pos.absolute_line_start = line_starts[0];
-        mark_pos.line_number = line_starts.len() - 1;
+        mark_pos.absolute_line_start = line_start;
+        mark_pos.line_number = line_number;
         mark_pos.absolute = mark;
-        Some(mark_pos)
+        mark_pos
+    })
+}
+
+/// Move marks along to account for `len` chars inserted at `idx`.
+///
+/// Marks sitting exactly at `idx` only move if they have right gravity.
+fn shift_marks_for_insert(marks: &mut HashMap<Mark, MarkPosition>, text: &Rope, idx: usize, len: usize) {
+    for mark_pos in marks.values_mut() {
+        if mark_pos.absolute > idx || (mark_pos.absolute == idx && mark_pos.gravity == Gravity::Right) {
+            mark_pos.absolute += len;
+            mark_pos.update_line_info(text);
+        }
     }
+}
 
+/// Move marks back to account for the chars from `start` to `end` being removed.
+///
+/// Marks inside the removed range end up at `start`.
+fn shift_marks_for_remove(marks: &mut HashMap<Mark, MarkPosition>, text: &Rope, start: usize, end: usize) {
+    for mark_pos in marks.values_mut() {
+        if mark_pos.absolute > start {
+            mark_pos.absolute -= cmp::min(mark_pos.absolute, end) - start;
+            mark_pos.update_line_info(text);
+        }
+    }
 }
 
 /// Performs a transaction on the passed in buffer.
-fn commit(transaction: &LogEntry, text: &mut GapBuffer<u8>) {
+fn commit(transaction: &LogEntry, text: &mut Rope, marks: &mut HashMap<Mark, MarkPosition>) {
     for change in &transaction.changes {
         match *change {
             Change::Insert(idx, ch) => {
                 text.insert(idx, ch);
+                shift_marks_for_insert(marks, text, idx, 1);
             }
             Change::Remove(idx, _) => {
-                text.remove(idx);
+                if text.remove(idx).is_some() {
+                    shift_marks_for_remove(marks, text, idx, idx + 1);
+                }
+            }
+            Change::InsertRange(idx, ref s) => {
+                text.insert_str(idx, s);
+                shift_marks_for_insert(marks, text, idx, s.chars().count());
+            }
+            Change::RemoveRange(idx, ref s) => {
+                let end = cmp::min(idx + s.chars().count(), text.len());
+                text.remove_range(idx, end);
+                shift_marks_for_remove(marks, text, idx, end);
             }
         }
     }
@@ -716,13 +1128,15 @@ fn commit(transaction: &LogEntry, text: &mut GapBuffer<u8>) {
 #[cfg(test)]
 mod test {
 
-    use buffer::{Buffer, Mark, MarkPosition};
+    use buffer::{Buffer, Mark, MarkPosition, Gravity};
+    use rope::Rope;
+    use search::{self, CaseMatching};
     use textobject::{TextObject, Offset, Kind, Anchor};
     use super::get_line_info;
 
     fn setup_buffer(testcase: &'static str) -> Buffer {
         let mut buffer = Buffer::new();
-        buffer.text.extend(testcase.bytes());
+        buffer.text = Rope::from(testcase);
         buffer.set_mark(Mark::Cursor(0), 0);
         buffer
     }
@@ -847,6 +1261,68 @@ mod test {
         assert_eq!(buffer.get_mark_display_coords(mark).unwrap(), (15, 0));
     }
 
+    #[test]
+    fn matches_are_objects() {
+        let mut buffer = setup_buffer("one two one\ntwo one");
+        let mark = Mark::Cursor(0);
+        let index = |buffer: &Buffer, anchor, offset| {
+            let obj = TextObject { kind: Kind::Match(String::from("o[a-z]e"), anchor), offset: offset };
+            buffer.get_object_index(obj).map(|pos| pos.absolute)
+        };
+
+        // a match at the mark itself isn't counted
+        assert_eq!(index(&buffer, Anchor::Start, Offset::Forward(1, mark)), Some(8));
+        assert_eq!(index(&buffer, Anchor::Start, Offset::Forward(2, mark)), Some(16));
+        assert_eq!(index(&buffer, Anchor::End, Offset::Forward(1, mark)), Some(10));
+        assert_eq!(index(&buffer, Anchor::After, Offset::Forward(1, mark)), Some(11));
+        assert_eq!(index(&buffer, Anchor::Start, Offset::Forward(3, mark)), None);
+        assert_eq!(index(&buffer, Anchor::Start, Offset::Absolute(3)), Some(16));
+
+        buffer.set_mark(mark, 16);
+        assert_eq!(index(&buffer, Anchor::Start, Offset::Backward(1, mark)), Some(8));
+        assert_eq!(index(&buffer, Anchor::Before, Offset::Backward(2, mark)), Some(0));
+        assert_eq!(index(&buffer, Anchor::Start, Offset::Backward(3, mark)), None);
+
+        let obj = TextObject { kind: Kind::Match(String::from("o("), Anchor::Start), offset: Offset::Forward(1, mark) };
+        assert!(buffer.get_object_index(obj).is_none());
+    }
+
+    #[test]
+    fn empty_matches_end_where_they_start() {
+        let mut buffer = setup_buffer("\nab");
+        let mark = Mark::Cursor(0);
+        let index = |buffer: &Buffer, pattern: &str, offset| {
+            let obj = TextObject { kind: Kind::Match(String::from(pattern), Anchor::End), offset: offset };
+            buffer.get_object_index(obj).map(|pos| pos.absolute)
+        };
+
+        assert_eq!(index(&buffer, "^$", Offset::Absolute(1)), Some(0));
+        assert_eq!(index(&buffer, "^", Offset::Absolute(1)), Some(0));
+        assert_eq!(index(&buffer, "^", Offset::Absolute(2)), Some(1));
+
+        buffer.set_mark(mark, 1);
+        assert_eq!(index(&buffer, "^", Offset::Backward(1, mark)), Some(0));
+    }
+
+    #[test]
+    fn matches_are_found_a_line_at_a_time() {
+        let buffer = setup_buffer("ab ab\n\nab");
+        let regex = search::search_regex("ab", CaseMatching::Sensitive).unwrap();
+        assert_eq!(buffer.find_match(&regex, Some(0), 1, false), Some((3, 5)));
+        assert_eq!(buffer.find_match(&regex, Some(0), 2, false), Some((7, 9)));
+        assert_eq!(buffer.find_match(&regex, Some(7), 1, false), None);
+        assert_eq!(buffer.find_match(&regex, Some(7), 2, true), Some((0, 2)));
+        assert_eq!(buffer.find_match(&regex, None, 1, false), Some((0, 2)));
+        assert_eq!(buffer.find_match(&regex, None, 1, true), Some((7, 9)));
+
+        let regex = search::search_regex("^$", CaseMatching::Sensitive).unwrap();
+        assert_eq!(buffer.find_match(&regex, Some(0), 1, false), Some((6, 6)));
+        // a match can't run over the end of a line
+        let regex = search::search_regex("b\\s", CaseMatching::Sensitive).unwrap();
+        assert_eq!(buffer.find_match(&regex, None, 1, false), Some((1, 3)));
+        assert_eq!(buffer.find_match(&regex, Some(1), 1, false), None);
+    }
+
     #[test]
     fn move_mark_two_words_right() {
         let mut buffer = setup_buffer("Some test content\nwith new\nlines!");
@@ -1054,9 +1530,9 @@ mod test {
     #[test]
     fn test_insert() {
         let mut buffer = setup_buffer("");
-        buffer.insert_char(Mark::Cursor(0), b'A');
+        buffer.insert_char(Mark::Cursor(0), 'A');
         assert_eq!(buffer.len(), 2);
-        assert_eq!(buffer.lines().next().unwrap(), [b'A']);
+        assert_eq!(buffer.lines().next().unwrap(), ['A']);
     }
 
     #[test]
@@ -1070,7 +1546,254 @@ mod test {
         buffer.remove_from_mark_to_object(mark, obj);
 
         assert_eq!(buffer.len(), 4);
-        assert_eq!(buffer.lines().next().unwrap(), [b'B', b'C', b'D']);
+        assert_eq!(buffer.lines().next().unwrap(), ['B', 'C', 'D']);
+    }
+
+    #[test]
+    fn test_insert_multibyte() {
+        let mut buffer = setup_buffer("");
+        buffer.insert_char(Mark::Cursor(0), 'ß');
+        buffer.insert_char(Mark::Cursor(0), '日');
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.lines().next().unwrap(), ['日', 'ß']);
+    }
+
+    #[test]
+    fn test_remove_multibyte() {
+        let mut buffer = setup_buffer("éa");
+        let mark = Mark::Cursor(0);
+        let obj = TextObject {
+            kind: Kind::Char,
+            offset: Offset::Forward(1, mark)
+        };
+        let removed = buffer.remove_from_mark_to_object(mark, obj);
+
+        assert_eq!(removed.unwrap(), ['é']);
+        assert_eq!(buffer.lines().next().unwrap(), ['a']);
+    }
+
+    #[test]
+    fn move_mark_char_right_over_combining_mark() {
+        let mut buffer = setup_buffer("e\u{301}tude");
+        let mark = Mark::Cursor(0);
+        let obj = TextObject {
+            kind: Kind::Char,
+            offset: Offset::Forward(1, mark),
+        };
+
+        buffer.set_mark_to_object(mark, obj);
+
+        assert_eq!(*buffer.marks.get(&mark).unwrap(), MarkPosition::from((2, 0, 0)));
+        assert_eq!(buffer.get_mark_display_coords(mark).unwrap(), (1, 0));
+    }
+
+    #[test]
+    fn move_mark_char_left_over_combining_mark() {
+        let mut buffer = setup_buffer("e\u{301}tude");
+        let mark = Mark::Cursor(0);
+        let obj = TextObject {
+            kind: Kind::Char,
+            offset: Offset::Backward(1, mark),
+        };
+
+        buffer.set_mark(mark, 2);
+        buffer.set_mark_to_object(mark, obj);
+
+        assert_eq!(*buffer.marks.get(&mark).unwrap(), MarkPosition::from((0, 0, 0)));
+    }
+
+    #[test]
+    fn display_coords_of_wide_chars() {
+        let mut buffer = setup_buffer("日本語\nabc");
+        let mark = Mark::Cursor(0);
+
+        buffer.set_mark(mark, 2);
+        assert_eq!(buffer.get_mark_display_coords(mark).unwrap(), (4, 0));
+
+        buffer.set_mark(mark, 6);
+        assert_eq!(buffer.get_mark_display_coords(mark).unwrap(), (2, 1));
+    }
+
+    #[test]
+    fn move_mark_word_right_unicode() {
+        let mut buffer = setup_buffer("größe straße");
+        let mark = Mark::Cursor(0);
+        let obj = TextObject {
+            kind: Kind::Word(Anchor::Start),
+            offset: Offset::Forward(1, mark),
+        };
+
+        buffer.set_mark_to_object(mark, obj);
+
+        assert_eq!(*buffer.marks.get(&mark).unwrap(), MarkPosition::from((6, 0, 0)));
+    }
+
+    #[test]
+    fn marks_follow_insert() {
+        let mut buffer = setup_buffer("Test\nA\nTest");
+        buffer.set_mark(Mark::DisplayMark(0), 7);
+        buffer.insert_char(Mark::Cursor(0), '\n');
+
+        assert_eq!(*buffer.marks.get(&Mark::DisplayMark(0)).unwrap(), MarkPosition::from((8, 8, 3)));
+        // the mark at the insertion point has left gravity, so stays put
+        assert_eq!(buffer.get_mark_idx(Mark::Cursor(0)), Some(0));
+    }
+
+    #[test]
+    fn marks_with_right_gravity_move_on_insert() {
+        let mut buffer = setup_buffer("Test");
+        buffer.set_mark(Mark::Cursor(1), 2);
+        buffer.set_mark_gravity(Mark::Cursor(1), Gravity::Right);
+        buffer.set_mark(Mark::Cursor(0), 2);
+        buffer.insert_char(Mark::Cursor(0), 'x');
+
+        assert_eq!(buffer.get_mark_idx(Mark::Cursor(0)), Some(2));
+        assert_eq!(buffer.get_mark_idx(Mark::Cursor(1)), Some(3));
+    }
+
+    #[test]
+    fn marks_follow_remove() {
+        let mut buffer = setup_buffer("Some\ntest\ncontent");
+        buffer.set_mark(Mark::Cursor(1), 7);
+        buffer.set_mark(Mark::DisplayMark(0), 10);
+        buffer.remove_range(2, 9);
+
+        // marks inside the removed range collapse to its start
+        assert_eq!(*buffer.marks.get(&Mark::Cursor(1)).unwrap(), MarkPosition::from((2, 0, 0)));
+        assert_eq!(*buffer.marks.get(&Mark::DisplayMark(0)).unwrap(), MarkPosition::from((3, 3, 1)));
+    }
+
+    #[test]
+    fn marks_follow_undo_and_redo() {
+        let mut buffer = setup_buffer("Test");
+        buffer.set_mark(Mark::DisplayMark(0), 3);
+        buffer.insert_char(Mark::Cursor(0), 'x');
+        assert_eq!(buffer.get_mark_idx(Mark::DisplayMark(0)), Some(4));
+
+        buffer.undo();
+        assert_eq!(buffer.get_mark_idx(Mark::DisplayMark(0)), Some(3));
+
+        buffer.redo();
+        assert_eq!(buffer.get_mark_idx(Mark::DisplayMark(0)), Some(4));
+    }
+
+    #[test]
+    fn transaction_undoes_in_one_step() {
+        let mut buffer = setup_buffer("");
+        buffer.start_transaction();
+        for ch in "abc".chars() {
+            buffer.insert_char(Mark::Cursor(0), ch);
+        }
+        buffer.commit_transaction();
+        buffer.insert_char(Mark::Cursor(0), 'd');
+
+        buffer.undo();
+        assert_eq!(buffer.lines().next().unwrap(), ['c', 'b', 'a']);
+        let end_point = buffer.undo().unwrap().end_point;
+        assert_eq!(end_point, 0);
+        assert_eq!(buffer.len(), 1);
+
+        buffer.redo();
+        assert_eq!(buffer.lines().next().unwrap(), ['c', 'b', 'a']);
+    }
+
+    #[test]
+    fn nested_transactions_commit_with_outermost() {
+        let mut buffer = setup_buffer("");
+        buffer.start_transaction();
+        buffer.insert_char(Mark::Cursor(0), 'a');
+        buffer.start_transaction();
+        buffer.insert_char(Mark::Cursor(0), 'b');
+        buffer.commit_transaction();
+        buffer.insert_char(Mark::Cursor(0), 'c');
+        buffer.commit_transaction();
+
+        assert!(buffer.undo().is_some());
+        assert_eq!(buffer.len(), 1);
+        assert!(buffer.undo().is_none());
+    }
+
+    #[test]
+    fn undo_branches_keep_undone_changes() {
+        let mut buffer = setup_buffer("");
+        buffer.insert_char(Mark::Cursor(0), 'a');
+        buffer.undo();
+        buffer.insert_char(Mark::Cursor(0), 'b');
+        assert_eq!(buffer.lines().next().unwrap(), ['b']);
+
+        assert_eq!(buffer.undo_branch_prev(), Some(0));
+        assert_eq!(buffer.lines().next().unwrap(), ['a']);
+
+        buffer.undo_branch_next();
+        assert_eq!(buffer.lines().next().unwrap(), ['b']);
+
+        buffer.undo_goto(0);
+        assert_eq!(buffer.len(), 1);
+    }
+
+    fn buffer_text(buffer: &Buffer) -> String {
+        buffer.text.chars_at(0).collect()
+    }
+
+    #[test]
+    fn large_removal_round_trips_through_undo() {
+        let original: String = (0..20000).map(|i| format!("line {} ✓\n", i)).collect();
+        let mut buffer = Buffer::new();
+        buffer.text = Rope::from(&*original);
+        buffer.set_mark(Mark::Cursor(0), 10);
+        buffer.set_mark(Mark::DisplayMark(0), 200000);
+
+        let len = buffer.len() - 1;
+        let removed = buffer.remove_range(5, len - 5).unwrap();
+        assert_eq!(removed.len(), len - 10);
+        assert_eq!(buffer.get_mark_idx(Mark::DisplayMark(0)), Some(5));
+
+        let entry = buffer.undo().unwrap();
+        assert_eq!(entry.changes.len(), 1);
+        assert_eq!(buffer_text(&buffer), original);
+
+        buffer.redo();
+        assert_eq!(buffer.len(), 11);
+        buffer.undo();
+        assert_eq!(buffer_text(&buffer), original);
+    }
+
+    #[test]
+    fn pasted_string_round_trips_through_undo() {
+        let pasted: String = (0..5000).map(|i| format!("pasted {}\n", i)).collect();
+        let mut buffer = setup_buffer("before after");
+        buffer.insert_string_at(7, &pasted);
+        assert_eq!(buffer_text(&buffer), format!("before {}after", pasted));
+
+        let entry = buffer.undo().unwrap();
+        assert_eq!(entry.changes.len(), 1);
+        assert_eq!(buffer_text(&buffer), "before after");
+
+        buffer.redo();
+        assert_eq!(buffer_text(&buffer), format!("before {}after", pasted));
+    }
+
+    #[test]
+    fn removing_nothing_leaves_the_buffer_alone() {
+        let mut buffer = setup_buffer("text");
+        assert!(buffer.remove_range(2, 2).is_none());
+        assert!(!buffer.dirty);
+        assert!(buffer.undo().is_none());
+    }
+
+    #[test]
+    fn replace_undoes_in_one_step() {
+        let mut buffer = setup_buffer("one two three");
+        let removed = buffer.replace_range(4, 7, "2️⃣ and more");
+        assert_eq!(removed.unwrap(), ['t', 'w', 'o']);
+        assert_eq!(buffer_text(&buffer), "one 2️⃣ and more three");
+
+        buffer.undo();
+        assert_eq!(buffer_text(&buffer), "one two three");
+        assert!(buffer.undo().is_none());
+
+        buffer.redo();
+        assert_eq!(buffer_text(&buffer), "one 2️⃣ and more three");
     }
 
     #[test]
@@ -1087,9 +1810,9 @@ mod test {
         let buffer = setup_buffer("Test\nA\nTest");
         let mut lines = buffer.lines();
 
-        assert_eq!(lines.next().unwrap(), [b'T',b'e',b's',b't',b'\n']);
-        assert_eq!(lines.next().unwrap(), [b'A',b'\n']);
-        assert_eq!(lines.next().unwrap(), [b'T',b'e',b's',b't']);
+        assert_eq!(lines.next().unwrap(), ['T','e','s','t','\n']);
+        assert_eq!(lines.next().unwrap(), ['A','\n']);
+        assert_eq!(lines.next().unwrap(), ['T','e','s','t']);
     }
 
     #[test]
@@ -1098,8 +1821,8 @@ mod test {
         buffer.set_mark(Mark::Cursor(0), 6);
         let mut lines = buffer.lines_from(Mark::Cursor(0)).unwrap();
 
-        assert_eq!(lines.next().unwrap(), [b'\n']);
-        assert_eq!(lines.next().unwrap(), [b'T',b'e',b's',b't']);
+        assert_eq!(lines.next().unwrap(), ['\n']);
+        assert_eq!(lines.next().unwrap(), ['T','e','s','t']);
     }
 
     #[test]
@@ -1154,4 +1877,79 @@ mod test {
         assert_eq!(buffer.get_mark_display_coords(mark).unwrap(), (0, 0));
     }
 
+    #[test]
+    fn remove_lines_takes_whole_lines() {
+        let mut buffer = setup_buffer("one\ntwo\nthree\nfour\n");
+        buffer.remove_lines(2, 3);
+        assert_eq!(buffer.text.chars_at(0).collect::<String>(), "one\nfour\n");
+
+        // the last line has no newline of its own, so the one before it goes
+        let mut buffer = setup_buffer("one\ntwo");
+        buffer.remove_lines(2, 2);
+        assert_eq!(buffer.text.chars_at(0).collect::<String>(), "one");
+    }
+
+    #[test]
+    fn substitute_undoes_in_one_step() {
+        use regex::Regex;
+
+        let mut buffer = setup_buffer("a a\nb\na a a\n");
+        let regex = Regex::new("a").unwrap();
+        assert_eq!(buffer.substitute(1, 3, &regex, "xy", true), 5);
+        assert_eq!(buffer.text.chars_at(0).collect::<String>(), "xy xy\nb\nxy xy xy\n");
+
+        buffer.undo();
+        assert_eq!(buffer.text.chars_at(0).collect::<String>(), "a a\nb\na a a\n");
+
+        assert_eq!(buffer.substitute(3, 3, &regex, "x", false), 1);
+        assert_eq!(buffer.text.chars_at(0).collect::<String>(), "a a\nb\nx a a\n");
+
+        let regex = Regex::new("(a) (a)").unwrap();
+        assert_eq!(buffer.substitute(1, 1, &regex, "${2}-$1", false), 1);
+        assert_eq!(buffer.text.chars_at(0).collect::<String>(), "a-a\nb\nx a a\n");
+    }
+
+    #[test]
+    fn substitutions_are_found_from_a_point() {
+        use regex::Regex;
+
+        let buffer = setup_buffer("ab\nxab ab\nab");
+        let regex = Regex::new("a(b)").unwrap();
+        assert_eq!(buffer.find_substitution(0, 3, &regex, "$1"), Some((0, 2, String::from("b"))));
+        assert_eq!(buffer.find_substitution(1, 3, &regex, "$1"), Some((4, 6, String::from("b"))));
+        assert_eq!(buffer.find_substitution(5, 3, &regex, "$1"), Some((7, 9, String::from("b"))));
+        // only the lines up to the last are searched
+        assert_eq!(buffer.find_substitution(8, 2, &regex, "$1"), None);
+        assert_eq!(buffer.find_substitution(8, 3, &regex, "$1"), Some((10, 12, String::from("b"))));
+    }
+
+    #[test]
+    fn line_count_ignores_final_newline() {
+        assert_eq!(setup_buffer("one\ntwo\n").line_count(), 2);
+        assert_eq!(setup_buffer("one\ntwo").line_count(), 2);
+    }
+
+    #[test]
+    fn files_that_are_not_utf8_are_refused() {
+        use std::fs::File;
+        use std::io::{self, Write};
+        use tempdir::TempDir;
+
+        let dir = TempDir::new("iota").unwrap();
+        let path = dir.path().join("latin1.txt");
+        File::create(&path).unwrap().write_all(b"caf\xe9\n").unwrap();
+        let err = Buffer::open(path.clone()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("latin1.txt"));
+
+        File::create(&path).unwrap().write_all("café\n".as_bytes()).unwrap();
+        let buffer = Buffer::open(path).unwrap();
+        assert_eq!(buffer.text.chars_at(0).collect::<String>(), "café\n");
+
+        // a file that doesn't exist yet is opened empty, to be created on saving
+        let buffer = Buffer::open(dir.path().join("new.txt")).unwrap();
+        assert_eq!(buffer.file_path, Some(dir.path().join("new.txt")));
+        assert_eq!(buffer.len(), 1);
+    }
+
 }
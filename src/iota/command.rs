@@ -1,7 +1,14 @@
+use std::cmp;
+use std::fmt;
+use std::path::PathBuf;
+
+use regex::Regex;
+
 use buffer::Mark;
-use textobject::{ TextObject, Offset, Kind };
+use textobject::{ TextObject, Offset, Kind, Anchor };
 use overlay::OverlayType;
 use modes::ModeType;
+use search::CaseMatching;
 use keymap::CommandInfo;
 
 /// Instructions for the Editor.
@@ -9,14 +16,38 @@ use keymap::CommandInfo;
 #[derive(Clone)]
 pub enum Instruction {
     SaveBuffer,
-    //FindFile,
+    SaveAs(PathBuf),
+    OpenFile(PathBuf),
     ExitEditor,
+    ForceExitEditor,
+    SaveAndExit(bool, Option<PathBuf>),  // save (only if modified, when true), to a new file if given, then exit
 
     SetMark(Mark),
+    GotoLine(LineAddress),
     SetOverlay(OverlayType),
     SetMode(ModeType),
     ShowMessage(String),
+    SwapSelectionEnds,
+    UseRegister(char),
+    SetRegionMark,
+    ClearSelection,
+    ClickAt(usize, usize),        // move the cursor to the char at a column and row of the screen
+    DragTo(usize, usize),         // select from where the mouse was pressed to a column and row
+    SelectWordAt(usize, usize),   // select the word at a column and row of the screen
+    Scroll(isize),                // scroll the view by some lines, without moving the cursor
+    Search(String, bool),          // search for a pattern, backwards when true, ending a search being typed
+    SearchPreview(String, bool),   // move to the match of a search as it is typed
+    CancelSearch,                  // give up a search being typed, going back to where it started
+    SearchNext(bool),              // repeat the last search, the other way when true
+    SearchAgain(bool),             // repeat the last search backwards when true, and forwards otherwise, from now on
+    ClearSearch,                   // stop highlighting the matches of the last search
+    SetSearchCase(CaseMatching),   // set how searches match the case of letters
+    SearchMotion(CommandInfo, bool),   // read a pattern for an operator to apply up to, backwards when true
     SwitchToLastBuffer,
+    SwitchToBuffer(usize),
+    NextBuffer,
+    PreviousBuffer,
+    CloseBuffer,
     None,
 }
 
@@ -28,11 +59,193 @@ pub enum Instruction {
 #[derive(Clone)]
 pub enum Operation {
     Insert(char), // insert text
+    Paste(String),   // insert text pasted in all at once, as it is
     DeleteObject,         // delete some object
     DeleteFromMark(Mark), // delete from some mark to an object
 
     Undo,         // rewind buffer transaction log
     Redo,         // replay buffer transaction log
+    UndoBranchNext,    // switch to the next branch of the undo tree
+    UndoBranchPrev,    // switch to the previous branch of the undo tree
+    UndoEarlier(u64),  // go back to the state some number of seconds ago
+    UndoLater(u64),    // go forward to the state some number of seconds later
+    UndoGoto(usize),   // go to a state in the undo tree
+
+    DeleteLines(LineRange),                // delete whole lines
+    Substitute(LineRange, Substitution),   // replace pattern matches in some lines
+    AnswerReplace(ReplaceAnswer),          // say whether to replace the match a substitution stopped at
+
+    Operate(Operator, Mark),           // apply an operator from some mark to an object
+    OperateOnLines(Operator, usize),   // apply an operator to some number of lines from the cursor
+    Put(bool),        // put back the last yanked or deleted text, before the cursor when true
+    OpenLine(bool),   // start a new line below the cursor, or above it when true
+
+    OperateOnSelection(Operator),   // apply an operator to the selected text
+    InsertInBlock(bool),   // type into every line of the selected block, after its right edge when true
+
+    KillRegion,   // cut the text between the mark and the cursor into the kill ring
+    CopyRegion,   // copy the text between the mark and the cursor into the kill ring
+    KillLine,     // cut from the cursor to the end of the line into the kill ring
+    YankKill,     // put back the most recent kill
+    YankPop,      // replace the text just yanked with the kill before it
+}
+
+/// The vi operators, which act on the text a motion moves over.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Operator {
+    Delete,
+    Change,       // delete, then insert in its place
+    Yank,         // copy, to be put back later
+    Indent,       // indent the lines covered
+    Outdent,      // unindent the lines covered
+    ToggleCase,   // swap upper and lower case letters
+}
+
+/// A line, as written in a range at the command prompt.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LineAddress {
+    /// A line number, counting from 1.
+    Number(usize),
+    /// The line the cursor is on.
+    Current,
+    /// The last line of the buffer.
+    Last,
+    /// The first line of the selection, or of the last one made.
+    SelectionStart,
+    /// The last line of the selection, or of the last one made.
+    SelectionEnd,
+}
+
+impl LineAddress {
+    fn parse(s: &str) -> Option<LineAddress> {
+        match s {
+            "." => Some(LineAddress::Current),
+            "$" => Some(LineAddress::Last),
+            "'<" => Some(LineAddress::SelectionStart),
+            "'>" => Some(LineAddress::SelectionEnd),
+            _ => s.parse().ok().map(LineAddress::Number),
+        }
+    }
+}
+
+/// An inclusive range of lines.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: LineAddress,
+    pub end: LineAddress,
+}
+
+impl LineRange {
+    /// The range of just the line the cursor is on.
+    pub fn current() -> LineRange {
+        LineRange { start: LineAddress::Current, end: LineAddress::Current }
+    }
+
+    /// Parse a range written as `10,20`, `.,$`, `%` (every line) or a single
+    /// address.
+    pub fn parse(s: &str) -> Option<LineRange> {
+        if s == "%" {
+            return Some(LineRange { start: LineAddress::Number(1), end: LineAddress::Last });
+        }
+        let mut parts = s.splitn(2, ',');
+        let start = parts.next().and_then(LineAddress::parse);
+        let end = match parts.next() {
+            Some(end) => LineAddress::parse(end),
+            None => start,
+        };
+        match (start, end) {
+            (Some(start), Some(end)) => Some(LineRange { start: start, end: end }),
+            _ => None,
+        }
+    }
+}
+
+/// A substitution, as written `/pattern/replacement/flags`.
+///
+/// Any character can stand in for the `/`s, and a backslash escapes it.  In the
+/// replacement `&` stands for the whole match and `\1` to `\9` for the
+/// pattern's groups.  The `g` flag replaces every match on a line rather than
+/// just the first, and the `c` flag asks before replacing each one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Substitution {
+    pub pattern: String,
+    /// The replacement, in the syntax `search::expand` expands.
+    pub replacement: String,
+    pub global: bool,
+    pub confirm: bool,
+}
+
+/// The answers to a substitution asking whether to replace a match.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReplaceAnswer {
+    /// Replace this match, and go on to the next.
+    Yes,
+    /// Leave this match, and go on to the next.
+    No,
+    /// Replace this match and every one after it.
+    All,
+    /// Stop, leaving this match and the rest.
+    Quit,
+}
+
+impl Substitution {
+    pub fn parse(s: &str) -> Option<Substitution> {
+        let mut chars = s.chars();
+        let delimiter = match chars.next() {
+            Some(c) if !c.is_alphanumeric() && !c.is_whitespace() && c != '\\' => c,
+            _ => return None,
+        };
+
+        // split into pattern, replacement and flags at unescaped delimiters
+        let mut parts = vec![String::new()];
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                match chars.next() {
+                    Some(next) if next == delimiter => parts.last_mut().unwrap().push(next),
+                    Some(next) => { parts.last_mut().unwrap().push(c); parts.last_mut().unwrap().push(next) }
+                    None => parts.last_mut().unwrap().push(c),
+                }
+            } else if c == delimiter && parts.len() < 3 {
+                parts.push(String::new());
+            } else {
+                parts.last_mut().unwrap().push(c);
+            }
+        }
+
+        let pattern = parts[0].clone();
+        let replacement = parts.get(1).map_or(String::new(), |r| expand_replacement(r));
+        let flags = parts.get(2).cloned().unwrap_or_default();
+        if pattern.is_empty() || flags.chars().any(|f| f != 'g' && f != 'c') {
+            return None;
+        }
+        Some(Substitution {
+            pattern: pattern,
+            replacement: replacement,
+            global: flags.contains('g'),
+            confirm: flags.contains('c'),
+        })
+    }
+}
+
+/// Turn a vi style replacement into the regex crate's syntax.
+fn expand_replacement(replacement: &str) -> String {
+    let mut expanded = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => expanded.push_str("${0}"),
+            '$' => expanded.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(d) if d.is_digit(10) => expanded.push_str(&format!("${{{}}}", d)),
+                Some('n') => expanded.push('\n'),
+                Some('t') => expanded.push('\t'),
+                Some(other) => expanded.push(other),
+                None => expanded.push('\\'),
+            },
+            c => expanded.push(c),
+        }
+    }
+    expanded
 }
 
 #[derive(Clone)]
@@ -49,11 +262,66 @@ pub struct Command {
     pub object: Option<TextObject>, // where to do it
 }
 
+/// Why a command could not be built.
+#[derive(Clone, Debug)]
+pub enum CommandError {
+    /// No command has the given name.
+    Unknown(String),
+    /// The command was not given an argument it needs.
+    MissingArgument(&'static str),
+    /// The arguments typed at the command prompt don't fit the command.
+    InvalidArgument(String),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CommandError::Unknown(ref name) => write!(f, "Unknown command: {}", name),
+            CommandError::MissingArgument(message) => write!(f, "{}", message),
+            CommandError::InvalidArgument(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// The pattern and direction of a search, which goes backward when given a
+/// backward offset.
+fn search_args(args: Option<BuilderArgs>) -> Result<(String, bool), CommandError> {
+    let args = require(args, "no arguments given to search")?;
+    let backward = match args.offset {
+        Some(Offset::Backward(..)) => true,
+        _ => false,
+    };
+    Ok((args.pattern.unwrap_or_default(), backward))
+}
+
+fn require<T>(value: Option<T>, message: &'static str) -> Result<T, CommandError> {
+    value.ok_or(CommandError::MissingArgument(message))
+}
+
+/// The kind of object given in `args`.  A `match` named from the command
+/// prompt is put together here with the pattern given for it.
+fn require_kind(args: &BuilderArgs) -> Result<Kind, CommandError> {
+    let anchor = match args.match_anchor {
+        Some(anchor) => anchor,
+        None => return require(args.kind.clone(), "no kind provided"),
+    };
+    let pattern = require(args.pattern.clone(), "no pattern given")?;
+    if let Err(e) = Regex::new(&pattern) {
+        return Err(CommandError::InvalidArgument(format!("Bad pattern: {}", e)));
+    }
+    Ok(Kind::Match(pattern, anchor))
+}
+
 impl Command {
     /// Display a message
-    pub fn show_message(args: Option<BuilderArgs>) -> Command {
-        let args = args.expect("no arguments given to show_message");
-        let message = args.str_args.expect("no message provided");
+    pub fn show_message(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let args = require(args, "no arguments given to show_message")?;
+        let message = require(args.str_args, "no message provided")?;
+        Ok(Command::message(message))
+    }
+
+    /// A command displaying `message`, which can't fail to be built
+    pub fn message(message: String) -> Command {
         Command {
             action: Action::Instruction(Instruction::ShowMessage(message)),
             number: 0,
@@ -61,137 +329,756 @@ impl Command {
         }
     }
 
-    /// Shortcut to create an ExitEditor command
-    pub fn exit_editor(_args: Option<BuilderArgs>) -> Command {
+    /// A command carrying out something done with the mouse
+    pub fn mouse(instruction: Instruction) -> Command {
         Command {
-            action: Action::Instruction(Instruction::ExitEditor),
+            action: Action::Instruction(instruction),
             number: 0,
             object: None,
         }
     }
 
-    /// Shortcut to create a SaveBuffer command
-    pub fn save_buffer(_args: Option<BuilderArgs>) -> Command {
+    /// A command inserting text pasted in at the terminal
+    pub fn paste(text: String) -> Command {
         Command {
+            action: Action::Operation(Operation::Paste(text)),
+            number: 1,
+            object: None,
+        }
+    }
+
+    /// Shortcut to create an ExitEditor command
+    pub fn exit_editor(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            action: Action::Instruction(Instruction::ExitEditor),
+            number: 0,
+            object: None,
+        })
+    }
+
+    /// Shortcut to exit the editor, discarding any unsaved changes
+    pub fn force_quit(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            action: Action::Instruction(Instruction::ForceExitEditor),
+            number: 0,
+            object: None,
+        })
+    }
+
+    /// Shortcut to save the buffer, to a new file if a path is given, and then exit
+    pub fn save_and_quit(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let path = args.and_then(|args| args.str_args).map(PathBuf::from);
+        Ok(Command {
+            action: Action::Instruction(Instruction::SaveAndExit(false, path)),
+            number: 0,
+            object: None,
+        })
+    }
+
+    /// Shortcut to save the buffer if it has been modified, to a new file if a
+    /// path is given, and then exit
+    pub fn save_if_modified_and_quit(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let path = args.and_then(|args| args.str_args).map(PathBuf::from);
+        Ok(Command {
+            action: Action::Instruction(Instruction::SaveAndExit(true, path)),
+            number: 0,
+            object: None,
+        })
+    }
+
+    /// Shortcut to create a SaveBuffer command
+    pub fn save_buffer(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
             action: Action::Instruction(Instruction::SaveBuffer),
             number: 0,
             object: None,
-        }
+        })
+    }
+
+    /// Shortcut to show the history of displayed messages
+    pub fn message_history(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            action: Action::Instruction(Instruction::SetOverlay(OverlayType::MessageHistory)),
+            number: 0,
+            object: None,
+        })
+    }
+
+    /// Shortcut to save the buffer to a new file, which it is then associated with
+    pub fn save_as(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let args = require(args, "no arguments given to save_as")?;
+        let path = require(args.str_args, "no path given")?;
+        Ok(Command {
+            action: Action::Instruction(Instruction::SaveAs(PathBuf::from(path))),
+            number: 0,
+            object: None,
+        })
+    }
+
+    /// Shortcut to show the find file prompt
+    pub fn find_file(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            action: Action::Instruction(Instruction::SetOverlay(OverlayType::FindFile)),
+            number: 0,
+            object: None,
+        })
+    }
+
+    /// Shortcut to open a file in a new buffer, or switch to it if it is already open
+    pub fn open_file(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let args = require(args, "no arguments given to open_file")?;
+        let path = require(args.str_args, "no path given")?;
+        Ok(Command {
+            action: Action::Instruction(Instruction::OpenFile(PathBuf::from(path))),
+            number: 0,
+            object: None,
+        })
     }
 
     /// Shortcut to create SetMode command
-    pub fn set_mode(args: Option<BuilderArgs>) -> Command {
-        let args = args.expect("no arguments given to set_mode");
-        let mode_type = args.mode_args.expect("no mode type given");
-        Command {
+    pub fn set_mode(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let args = require(args, "no arguments given to set_mode")?;
+        let mode_type = require(args.mode_args, "no mode type given")?;
+        // the cursor can be moved on the way into the mode, as with vi's `A`
+        let object = match (args.kind, args.offset) {
+            (Some(kind), Some(offset)) => Some(TextObject { kind: kind, offset: offset }),
+            _ => None,
+        };
+        Ok(Command {
             action: Action::Instruction(Instruction::SetMode(mode_type)),
             number: 0,
-            object: None,
-        }
+            object: object,
+        })
     }
 
     /// Shortcut to change the active overlay
-    pub fn set_overlay(args: Option<BuilderArgs>) -> Command {
-        let args = args.expect("no arguments given to set_overlay");
-        let overlay = args.overlay_args.expect("no overlay type given");
-        Command {
+    pub fn set_overlay(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let args = require(args, "no arguments given to set_overlay")?;
+        let overlay = require(args.overlay_args, "no overlay type given")?;
+        Ok(Command {
             action: Action::Instruction(Instruction::SetOverlay(overlay)),
             number: 0,
             object: None,
-        }
+        })
     }
 
     /// Shortcut to create a Delete command
-    pub fn delete_char(args: Option<BuilderArgs>) -> Command {
-        let args = args.expect("no arguments given to delete_char");
-        let kind = args.kind.expect("no kind provided");
-        let offset = args.offset.expect("no offset provided");
-        Command {
+    pub fn delete_char(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let args = require(args, "no arguments given to delete_char")?;
+        let kind = require_kind(&args)?;
+        let offset = require(args.offset, "no offset provided")?;
+        Ok(Command {
             number: 1,
             action: Action::Operation(Operation::DeleteFromMark(Mark::Cursor(0))),
             object: Some(TextObject {
                 kind: kind,
                 offset: offset
             })
-        }
+        })
     }
 
     /// Shortcut to create an Insert command
-    pub fn insert_char(args: Option<BuilderArgs>) -> Command {
-        let args = args.expect("no arguments given to insert_char");
-        let c = args.char_args.expect("no char given");
-        Command {
+    pub fn insert_char(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let args = require(args, "no arguments given to insert_char")?;
+        let c = require(args.char_args, "no char given")?;
+        Ok(Command {
             number: 1,
             action: Action::Operation(Operation::Insert(c)),
             object: None,
-        }
+        })
     }
 
-    /// Shortcut to create a Delete command
-    pub fn delete(args: Option<BuilderArgs>) -> Command {
-        let args = args.expect("no arguments given to insert_char");
-        let kind = args.kind.expect("no kind provided");
-        let offset = args.offset.expect("no offset provided");
-        let repeat = args.number.unwrap_or(1);
-        Command {
-            number: repeat,
-            action: Action::Operation(Operation::DeleteFromMark(Mark::Cursor(0))),
+    /// Shortcut to delete from the cursor to an object
+    pub fn delete(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Command::operate(Operator::Delete, args)
+    }
+
+    /// Shortcut to delete from the cursor to an object, and insert in its place
+    pub fn change(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Command::operate(Operator::Change, args)
+    }
+
+    /// Shortcut to copy from the cursor to an object
+    pub fn yank(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Command::operate(Operator::Yank, args)
+    }
+
+    /// Shortcut to delete whole lines from the cursor
+    pub fn delete_line(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Command::operate_on_lines(Operator::Delete, args)
+    }
+
+    /// Shortcut to empty whole lines from the cursor, and insert in their place
+    pub fn change_line(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Command::operate_on_lines(Operator::Change, args)
+    }
+
+    /// Shortcut to copy whole lines from the cursor
+    pub fn yank_line(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Command::operate_on_lines(Operator::Yank, args)
+    }
+
+    fn operate(operator: Operator, args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let args = require(args, "no arguments given to operator")?;
+        let kind = require_kind(&args)?;
+        let offset = require(args.offset, "no offset provided")?;
+        // the count goes into the object, so that `y3w` copies three words at once
+        let count = cmp::max(args.number.unwrap_or(1), 1) as usize;
+        let offset = match offset {
+            Offset::Forward(n, mark) => Offset::Forward(n * count, mark),
+            Offset::Backward(n, mark) => Offset::Backward(n * count, mark),
+            Offset::Absolute(n) => Offset::Absolute(n),
+        };
+        Ok(Command {
+            number: 1,
+            action: Action::Operation(Operation::Operate(operator, Mark::Cursor(0))),
             object: Some(TextObject {
                 kind: kind,
                 offset: offset,
             }),
+        })
+    }
+
+    fn operate_on_lines(operator: Operator, args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let count = args.and_then(|args| args.number).unwrap_or(1);
+        Ok(Command {
+            number: 1,
+            action: Action::Operation(Operation::OperateOnLines(operator, cmp::max(count, 1) as usize)),
+            object: None,
+        })
+    }
+
+    /// Shortcut to indent lines from the cursor to an object
+    pub fn indent(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Command::operate(Operator::Indent, args)
+    }
+
+    /// Shortcut to unindent lines from the cursor to an object
+    pub fn outdent(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Command::operate(Operator::Outdent, args)
+    }
+
+    /// Shortcut to indent whole lines from the cursor
+    pub fn indent_line(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Command::operate_on_lines(Operator::Indent, args)
+    }
+
+    /// Shortcut to unindent whole lines from the cursor
+    pub fn outdent_line(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Command::operate_on_lines(Operator::Outdent, args)
+    }
+
+    /// Shortcut to delete the selection
+    pub fn delete_selection(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command::operate_on_selection(Operator::Delete))
+    }
+
+    /// Shortcut to delete the selection, and insert in its place
+    pub fn change_selection(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command::operate_on_selection(Operator::Change))
+    }
+
+    /// Shortcut to copy the selection
+    pub fn yank_selection(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command::operate_on_selection(Operator::Yank))
+    }
+
+    /// Shortcut to indent the selected lines
+    pub fn indent_selection(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command::operate_on_selection(Operator::Indent))
+    }
+
+    /// Shortcut to unindent the selected lines
+    pub fn outdent_selection(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command::operate_on_selection(Operator::Outdent))
+    }
+
+    /// Shortcut to swap the case of the selected letters
+    pub fn toggle_case_selection(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command::operate_on_selection(Operator::ToggleCase))
+    }
+
+    fn operate_on_selection(operator: Operator) -> Command {
+        Command {
+            number: 1,
+            action: Action::Operation(Operation::OperateOnSelection(operator)),
+            object: None,
         }
     }
 
+    /// Shortcut to insert the same text at the left edge of every line in the selected block
+    pub fn insert_block(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            number: 1,
+            action: Action::Operation(Operation::InsertInBlock(false)),
+            object: None,
+        })
+    }
+
+    /// Shortcut to insert the same text after the right edge of every line in the selected block
+    pub fn append_block(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            number: 1,
+            action: Action::Operation(Operation::InsertInBlock(true)),
+            object: None,
+        })
+    }
+
+    /// Shortcut to move the cursor to the other end of the selection
+    pub fn swap_selection_ends(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            number: 0,
+            action: Action::Instruction(Instruction::SwapSelectionEnds),
+            object: None,
+        })
+    }
+
+    /// Shortcut to choose the register for the next yank, delete or put
+    pub fn use_register(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let args = require(args, "no arguments given to use_register")?;
+        let name = require(args.char_args, "no register given")?;
+        Ok(Command {
+            number: 0,
+            action: Action::Instruction(Instruction::UseRegister(name)),
+            object: None,
+        })
+    }
+
+    /// Shortcut to set the mark at the cursor, starting a region
+    pub fn set_mark(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            number: 0,
+            action: Action::Instruction(Instruction::SetRegionMark),
+            object: None,
+        })
+    }
+
+    /// Shortcut to stop selecting, dropping the region or visual selection
+    pub fn clear_mark(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            number: 0,
+            action: Action::Instruction(Instruction::ClearSelection),
+            object: None,
+        })
+    }
+
+    /// Shortcut to cut the region into the kill ring
+    pub fn kill_region(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command::kill(Operation::KillRegion))
+    }
+
+    /// Shortcut to copy the region into the kill ring
+    pub fn copy_region(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command::kill(Operation::CopyRegion))
+    }
+
+    /// Shortcut to cut the rest of the line into the kill ring
+    pub fn kill_line(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let mut command = Command::kill(Operation::KillLine);
+        command.number = args.and_then(|args| args.number).unwrap_or(1);
+        Ok(command)
+    }
+
+    /// Shortcut to put back the most recent kill
+    pub fn yank_kill(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command::kill(Operation::YankKill))
+    }
+
+    /// Shortcut to replace the text just yanked with the kill before it
+    pub fn yank_pop(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command::kill(Operation::YankPop))
+    }
+
+    fn kill(operation: Operation) -> Command {
+        Command {
+            number: 1,
+            action: Action::Operation(operation),
+            object: None,
+        }
+    }
+
+    /// Shortcut to put back yanked text after the cursor
+    pub fn put(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            number: args.and_then(|args| args.number).unwrap_or(1),
+            action: Action::Operation(Operation::Put(false)),
+            object: None,
+        })
+    }
+
+    /// Shortcut to put back yanked text before the cursor
+    pub fn put_before(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            number: args.and_then(|args| args.number).unwrap_or(1),
+            action: Action::Operation(Operation::Put(true)),
+            object: None,
+        })
+    }
+
+    /// Shortcut to start a new line below the cursor, in insert mode
+    pub fn open_line_below(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            number: 1,
+            action: Action::Operation(Operation::OpenLine(false)),
+            object: None,
+        })
+    }
+
+    /// Shortcut to start a new line above the cursor, in insert mode
+    pub fn open_line_above(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            number: 1,
+            action: Action::Operation(Operation::OpenLine(true)),
+            object: None,
+        })
+    }
+
     /// Shortcut to create an Insert command
     // FIXME: shouldn't need this method
-    pub fn insert_tab(_args: Option<BuilderArgs>) -> Command {
-        Command {
+    pub fn insert_tab(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
             number: 4,
             action: Action::Operation(Operation::Insert(' ')),
             object: None,
-        }
+        })
     }
 
     /// Shortcut to create Undo command
-    pub fn undo(_args: Option<BuilderArgs>) -> Command {
-        Command {
+    pub fn undo(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
             number: 1,
             action: Action::Operation(Operation::Undo),
             object: None
-        }
+        })
     }
 
     /// Shortcut to create Redo command
-    pub fn redo(_args: Option<BuilderArgs>) -> Command {
-        Command {
+    pub fn redo(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
             number: 1,
             action: Action::Operation(Operation::Redo),
             object: None
+        })
+    }
+
+    /// Shortcut to create a command switching to the next branch of the undo tree
+    pub fn undo_branch_next(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            number: 1,
+            action: Action::Operation(Operation::UndoBranchNext),
+            object: None
+        })
+    }
+
+    /// Shortcut to create a command switching to the previous branch of the undo tree
+    pub fn undo_branch_prev(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            number: 1,
+            action: Action::Operation(Operation::UndoBranchPrev),
+            object: None
+        })
+    }
+
+    /// Shortcut to create a command going back a number of minutes (default 1) in the undo history
+    pub fn undo_earlier(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let minutes = args.and_then(|a| a.number).unwrap_or(1);
+        Ok(Command {
+            number: 1,
+            action: Action::Operation(Operation::UndoEarlier(cmp::max(minutes, 1) as u64 * 60)),
+            object: None
+        })
+    }
+
+    /// Shortcut to create a command going forward a number of minutes (default 1) in the undo history
+    pub fn undo_later(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let minutes = args.and_then(|a| a.number).unwrap_or(1);
+        Ok(Command {
+            number: 1,
+            action: Action::Operation(Operation::UndoLater(cmp::max(minutes, 1) as u64 * 60)),
+            object: None
+        })
+    }
+
+    /// Shortcut to create a command jumping to a state in the undo tree
+    pub fn undo_goto(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let args = require(args, "no arguments given to undo_goto")?;
+        let state = require(args.number, "no state given")?;
+        Ok(Command {
+            number: 1,
+            action: Action::Operation(Operation::UndoGoto(state as usize)),
+            object: None
+        })
+    }
+
+    /// Shortcut to show the undo tree overlay
+    pub fn undo_tree(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            action: Action::Instruction(Instruction::SetOverlay(OverlayType::UndoTree)),
+            number: 0,
+            object: None,
+        })
+    }
+
+    /// Shortcut to switch to the next open buffer
+    pub fn next_buffer(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            action: Action::Instruction(Instruction::NextBuffer),
+            number: 0,
+            object: None,
+        })
+    }
+
+    /// Shortcut to switch to the previous open buffer
+    pub fn previous_buffer(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            action: Action::Instruction(Instruction::PreviousBuffer),
+            number: 0,
+            object: None,
+        })
+    }
+
+    /// Shortcut to switch to an open buffer, by its position in the buffer list
+    /// (counting from 1)
+    pub fn switch_to_buffer(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let args = require(args, "no arguments given to switch_to_buffer")?;
+        let number = require(args.number, "no buffer given")?;
+        if number < 1 {
+            return Err(CommandError::InvalidArgument(format!("No buffer {}", number)));
         }
+        Ok(Command {
+            action: Action::Instruction(Instruction::SwitchToBuffer(number as usize - 1)),
+            number: 0,
+            object: None,
+        })
+    }
+
+    /// Shortcut to close the current buffer
+    pub fn close_buffer(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            action: Action::Instruction(Instruction::CloseBuffer),
+            number: 0,
+            object: None,
+        })
+    }
+
+    /// Shortcut to show the list of open buffers
+    pub fn list_buffers(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            action: Action::Instruction(Instruction::SetOverlay(OverlayType::BufferList)),
+            number: 0,
+            object: None,
+        })
+    }
+
+    /// Shortcut to search for a pattern, going backward when given a backward
+    /// offset.  With no pattern the last search is repeated.
+    pub fn search(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let (pattern, backward) = search_args(args)?;
+        Ok(Command {
+            action: Action::Instruction(Instruction::Search(pattern, backward)),
+            number: 0,
+            object: None,
+        })
     }
 
-    pub fn move_cursor(args: Option<BuilderArgs>) -> Command {
-        let args = args.expect("no args given to movement");
-        let kind = args.kind.expect("no kind provided");
-        let offset = args.offset.expect("no offset provided");
+    /// Shortcut to move to the match of a search as it is being typed
+    pub fn search_preview(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let (pattern, backward) = search_args(args)?;
+        Ok(Command {
+            action: Action::Instruction(Instruction::SearchPreview(pattern, backward)),
+            number: 0,
+            object: None,
+        })
+    }
+
+    /// Shortcut to give up a search being typed
+    pub fn cancel_search(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            action: Action::Instruction(Instruction::CancelSearch),
+            number: 0,
+            object: None,
+        })
+    }
+
+    /// Shortcut to move to the next match of the last search
+    pub fn search_next(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            number: args.and_then(|args| args.number).unwrap_or(1),
+            action: Action::Instruction(Instruction::SearchNext(false)),
+            object: None,
+        })
+    }
+
+    /// Shortcut to move to the next match of the last search, going the other way
+    pub fn search_previous(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            number: args.and_then(|args| args.number).unwrap_or(1),
+            action: Action::Instruction(Instruction::SearchNext(true)),
+            object: None,
+        })
+    }
+
+    /// Shortcut to repeat the last search the way an offset gives, which later
+    /// repeats go as well
+    pub fn search_again(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let args = require(args, "no arguments given to search_again")?;
+        let backward = match require(args.offset, "no direction given")? {
+            Offset::Backward(..) => true,
+            _ => false,
+        };
+        Ok(Command {
+            number: args.number.unwrap_or(1),
+            action: Action::Instruction(Instruction::SearchAgain(backward)),
+            object: None,
+        })
+    }
+
+    /// Shortcut to read a pattern for an operator to apply up to its next
+    /// match, or its previous one when given a backward offset
+    pub fn search_motion(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let args = require(args, "no arguments given to search_motion")?;
+        let operator = require(args.str_args.clone(), "no operator given")?;
+        let backward = match args.offset {
+            Some(Offset::Backward(..)) => true,
+            _ => false,
+        };
+        // the count is the operator's, so `2d/x` deletes up to the second x
+        let operator_args = match args.number {
+            Some(number) => BuilderArgs::new().with_number(number),
+            None => BuilderArgs::new(),
+        };
+        let operator = CommandInfo {
+            command_name: operator,
+            args: Some(operator_args),
+        };
+        Ok(Command {
+            action: Action::Instruction(Instruction::SearchMotion(operator, backward)),
+            number: 0,
+            object: None,
+        })
+    }
+
+    /// Shortcut to stop highlighting the matches of the last search
+    pub fn clear_search(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
+            action: Action::Instruction(Instruction::ClearSearch),
+            number: 0,
+            object: None,
+        })
+    }
+
+    /// Shortcut to set how searches match the case of letters
+    pub fn search_case(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let args = require(args, "no arguments given to search_case")?;
+        let case = require(args.str_args, "no case matching given")?;
+        let case = match &*case {
+            "sensitive" => CaseMatching::Sensitive,
+            "insensitive" => CaseMatching::Insensitive,
+            "smart" => CaseMatching::Smart,
+            _ => return Err(CommandError::InvalidArgument(format!("Unknown case matching: {}", case))),
+        };
+        Ok(Command {
+            action: Action::Instruction(Instruction::SetSearchCase(case)),
+            number: 0,
+            object: None,
+        })
+    }
+
+    pub fn move_cursor(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let args = require(args, "no args given to movement")?;
+        let kind = require_kind(&args)?;
+        let offset = require(args.offset, "no offset provided")?;
         let repeat = args.number.unwrap_or(1);
-        Command {
+        Ok(Command {
             number: repeat,
             action: Action::Instruction(Instruction::SetMark(Mark::Cursor(0))),
             object: Some(TextObject {
                 kind: kind,
                 offset: offset
             })
+        })
+    }
+
+    /// Shortcut to move the cursor to the start of a line
+    pub fn goto_line(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let args = require(args, "no arguments given to goto_line")?;
+        let range = require(args.range, "no line given")?;
+        if range.end == LineAddress::Number(0) {
+            return Err(CommandError::InvalidArgument(String::from("No line 0")));
         }
+        Ok(Command {
+            number: 0,
+            action: Action::Instruction(Instruction::GotoLine(range.end)),
+            object: None,
+        })
     }
 
-    pub fn noop(_args: Option<BuilderArgs>) -> Command {
+    /// Shortcut to delete whole lines
+    pub fn delete_lines(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let range = args.and_then(|args| args.range).unwrap_or_else(LineRange::current);
+        Ok(Command {
+            number: 1,
+            action: Action::Operation(Operation::DeleteLines(range)),
+            object: None,
+        })
+    }
+
+    /// Shortcut to replace matches of a pattern in some lines
+    pub fn substitute(args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        let args = require(args, "no arguments given to substitute")?;
+        let range = args.range.unwrap_or_else(LineRange::current);
+        let expression = require(args.str_args, "no substitution given")?;
+        let substitution = match Substitution::parse(&expression) {
+            Some(substitution) => substitution,
+            None => {
+                let message = format!("Substitutions are written /pattern/replacement/gc, not {}", expression);
+                return Err(CommandError::InvalidArgument(message));
+            }
+        };
+        if let Err(e) = Regex::new(&substitution.pattern) {
+            return Err(CommandError::InvalidArgument(format!("Bad pattern: {}", e)));
+        }
+        Ok(Command {
+            number: 1,
+            action: Action::Operation(Operation::Substitute(range, substitution)),
+            object: None,
+        })
+    }
+
+    /// Shortcut to replace the match a substitution stopped at
+    pub fn replace_match(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command::answer_replace(ReplaceAnswer::Yes))
+    }
+
+    /// Shortcut to leave the match a substitution stopped at
+    pub fn skip_match(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command::answer_replace(ReplaceAnswer::No))
+    }
+
+    /// Shortcut to replace the match a substitution stopped at, and every one after it
+    pub fn replace_remaining(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command::answer_replace(ReplaceAnswer::All))
+    }
+
+    /// Shortcut to stop a substitution asking about each match
+    pub fn stop_replacing(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command::answer_replace(ReplaceAnswer::Quit))
+    }
+
+    fn answer_replace(answer: ReplaceAnswer) -> Command {
         Command {
+            number: 1,
+            action: Action::Operation(Operation::AnswerReplace(answer)),
+            object: None,
+        }
+    }
+
+    pub fn noop(_args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+        Ok(Command {
             number: 0,
             action: Action::Instruction(Instruction::None),
             object: None,
-        }
+        })
     }
 }
 
@@ -203,7 +1090,12 @@ pub struct BuilderArgs {
     pub mode_args: Option<ModeType>,
     pub overlay_args: Option<OverlayType>,
     pub kind: Option<Kind>,
+    /// The anchor of a `match` kind given by name, which is only made into a
+    /// `Kind` along with `pattern` when the command is built.
+    pub match_anchor: Option<Anchor>,
+    pub pattern: Option<String>,
     pub offset: Option<Offset>,
+    pub range: Option<LineRange>,
 }
 
 impl BuilderArgs {
@@ -215,7 +1107,10 @@ impl BuilderArgs {
             mode_args: None,
             overlay_args: None,
             kind: None,
+            match_anchor: None,
+            pattern: None,
             offset: None,
+            range: None,
         }
     }
 
@@ -243,6 +1138,12 @@ impl BuilderArgs {
         self
     }
 
+    pub fn with_pattern(mut self, pattern: String) -> BuilderArgs {
+        self.pattern = Some(pattern);
+
+        self
+    }
+
     pub fn with_offset(mut self, offset: Offset) -> BuilderArgs {
         self.offset = Some(offset);
 
@@ -260,11 +1161,402 @@ impl BuilderArgs {
 
         self
     }
+
+    pub fn with_range(mut self, range: LineRange) -> BuilderArgs {
+        self.range = Some(range);
+
+        self
+    }
+}
+
+
+/// The kinds of value a command can take from the command prompt.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParamType {
+    /// A whole number, filling in `BuilderArgs::number`.
+    Number,
+    /// A single character, filling in `BuilderArgs::char_args`.
+    Char,
+    /// Some text, filling in `BuilderArgs::str_args`.  When it is the last
+    /// parameter it takes the rest of the line.
+    Text,
+    /// A path, filling in `BuilderArgs::str_args`.
+    Path,
+    /// `normal` or `insert`, filling in `BuilderArgs::mode_args`.
+    Mode,
+    /// The name of an overlay, filling in `BuilderArgs::overlay_args`.
+    Overlay,
+    /// `char`, `word` or `line`, optionally followed by an anchor such as
+    /// `line:start`, filling in `BuilderArgs::kind`.  `match` fills in
+    /// `BuilderArgs::match_anchor` instead, as it needs a pattern as well.
+    Kind,
+    /// `forward`, `backward` or an absolute number, filling in
+    /// `BuilderArgs::offset`.
+    Offset,
+    /// A line or range of lines such as `10,20`, `.,$` or `%`, filling in
+    /// `BuilderArgs::range`.
+    Range,
+    /// `sensitive`, `insensitive` or `smart`, how searches match the case of
+    /// letters, filling in `BuilderArgs::str_args`.
+    Case,
+    /// A regular expression, filling in `BuilderArgs::pattern`.  When it is
+    /// the last parameter it takes the rest of the line.
+    Pattern,
+}
+
+/// A parameter which a command accepts from the command prompt.
+#[derive(Copy, Clone, Debug)]
+pub struct Param {
+    pub name: &'static str,
+    pub kind: ParamType,
+    pub optional: bool,
+}
+
+impl Param {
+    /// A parameter which must be given.
+    pub fn required(name: &'static str, kind: ParamType) -> Param {
+        Param { name: name, kind: kind, optional: false }
+    }
+
+    /// A parameter which may be left out, along with any after it.
+    pub fn optional(name: &'static str, kind: ParamType) -> Param {
+        Param { name: name, kind: kind, optional: true }
+    }
+}
+
+const MODES: &'static [&'static str] = &["insert", "normal", "visual", "visual_line", "visual_block"];
+const OVERLAYS: &'static [&'static str] = &[
+    "buffer_list", "command_prompt", "ex_command", "find_file", "message_history", "search",
+    "search_backward", "undo_tree",
+];
+const KINDS: &'static [&'static str] = &[
+    "char", "line", "line:after", "line:before", "line:end", "line:start", "linechar",
+    "match", "match:after", "match:before", "match:end", "match:start",
+    "word", "word:after", "word:before", "word:end", "word:start",
+];
+const OFFSETS: &'static [&'static str] = &["backward", "forward"];
+const CASES: &'static [&'static str] = &["insensitive", "sensitive", "smart"];
+
+impl ParamType {
+    /// Every value the parameter can take, for types which have a fixed set.
+    pub fn values(&self) -> &'static [&'static str] {
+        match *self {
+            ParamType::Mode => MODES,
+            ParamType::Overlay => OVERLAYS,
+            ParamType::Kind => KINDS,
+            ParamType::Offset => OFFSETS,
+            ParamType::Case => CASES,
+            _ => &[],
+        }
+    }
+
+    /// Parse `word` into the matching field of `args`.
+    fn fill(&self, word: &str, args: &mut BuilderArgs) -> Result<(), ()> {
+        match *self {
+            ParamType::Number => { args.number = Some(word.parse().map_err(|_| ())?) }
+            ParamType::Char => {
+                let mut chars = word.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(ch), None) => args.char_args = Some(ch),
+                    _ => return Err(()),
+                }
+            }
+            ParamType::Text | ParamType::Path => { args.str_args = Some(word.to_string()) }
+            ParamType::Pattern => { args.pattern = Some(word.to_string()) }
+            ParamType::Mode => {
+                args.mode_args = Some(match word {
+                    "insert" => ModeType::Insert,
+                    "normal" => ModeType::Normal,
+                    "visual" => ModeType::Visual,
+                    "visual_line" => ModeType::VisualLine,
+                    "visual_block" => ModeType::VisualBlock,
+                    _ => return Err(()),
+                })
+            }
+            ParamType::Overlay => {
+                args.overlay_args = Some(match word {
+                    "buffer_list" => OverlayType::BufferList,
+                    "command_prompt" => OverlayType::CommandPrompt,
+                    "ex_command" => OverlayType::ExCommand,
+                    "find_file" => OverlayType::FindFile,
+                    "message_history" => OverlayType::MessageHistory,
+                    "search" => OverlayType::Search,
+                    "search_backward" => OverlayType::SearchBackward,
+                    "undo_tree" => OverlayType::UndoTree,
+                    _ => return Err(()),
+                })
+            }
+            ParamType::Kind => {
+                let (kind, anchor) = match word.find(':') {
+                    Some(idx) => (&word[..idx], Some(&word[idx + 1..])),
+                    None => (word, None),
+                };
+                let anchor = match anchor {
+                    None | Some("same") => Anchor::Same,
+                    Some("start") => Anchor::Start,
+                    Some("end") => Anchor::End,
+                    Some("before") => Anchor::Before,
+                    Some("after") => Anchor::After,
+                    Some(_) => return Err(()),
+                };
+                if kind == "match" {
+                    args.match_anchor = Some(anchor);
+                    return Ok(());
+                }
+                args.kind = Some(match kind {
+                    "char" => Kind::Char,
+                    "linechar" => Kind::LineChar,
+                    "line" => Kind::Line(anchor),
+                    "word" => Kind::Word(anchor),
+                    _ => return Err(()),
+                })
+            }
+            ParamType::Offset => {
+                args.offset = Some(match word {
+                    "forward" => Offset::Forward(1, Mark::Cursor(0)),
+                    "backward" => Offset::Backward(1, Mark::Cursor(0)),
+                    _ => Offset::Absolute(word.parse().map_err(|_| ())?),
+                })
+            }
+            ParamType::Range => { args.range = Some(LineRange::parse(word).ok_or(())?) }
+            ParamType::Case => {
+                if !CASES.contains(&word) {
+                    return Err(());
+                }
+                args.str_args = Some(word.to_string())
+            }
+        }
+        Ok(())
+    }
+
+    fn describe(&self) -> &'static str {
+        match *self {
+            ParamType::Number => "a number",
+            ParamType::Char => "a single character",
+            ParamType::Text => "some text",
+            ParamType::Path => "a path",
+            ParamType::Mode => "a mode",
+            ParamType::Overlay => "an overlay",
+            ParamType::Kind => "a text object kind",
+            ParamType::Offset => "forward, backward or a number",
+            ParamType::Range => "a line or range of lines",
+            ParamType::Case => "sensitive, insensitive or smart",
+            ParamType::Pattern => "a pattern",
+        }
+    }
+}
+
+/// Split a command line into words at whitespace.  Words can be wrapped in
+/// double quotes to include whitespace, and a backslash includes the
+/// character after it as it is.
+pub fn split_words(line: &str) -> Vec<String> {
+    split_words_at(line).into_iter().map(|(_, word)| word).collect()
 }
 
+/// The words of `line`, as `split_words` gives them, along with where in
+/// `line` each one starts.
+fn split_words_at(line: &str) -> Vec<(usize, String)> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut start = None;
+    let mut quoted = false;
+    let mut chars = line.char_indices();
+
+    while let Some((idx, ch)) = chars.next() {
+        match ch {
+            '\\' => {
+                word.extend(chars.next().map(|(_, c)| c));
+                start = start.or(Some(idx));
+            }
+            '"' => {
+                quoted = !quoted;
+                start = start.or(Some(idx));
+            }
+            c if c.is_whitespace() && !quoted => {
+                if let Some(start) = start.take() {
+                    words.push((start, word.clone()));
+                    word.clear();
+                }
+            }
+            c => {
+                word.push(c);
+                start = start.or(Some(idx));
+            }
+        }
+    }
+    if let Some(start) = start {
+        words.push((start, word));
+    }
+    words
+}
+
+/// Fill in `BuilderArgs` for the command `name` from the `line` typed after
+/// it, checking its words against the command's parameters.
+pub fn parse_args(name: &str, params: &[Param], line: &str) -> Result<BuilderArgs, CommandError> {
+    let mut args = BuilderArgs::new();
+    let words = split_words_at(line);
+    let mut words = words.into_iter();
+
+    for (i, param) in params.iter().enumerate() {
+        let (start, word) = match words.next() {
+            Some(word) => word,
+            None if param.optional => break,
+            None => {
+                let message = format!("{} needs a {} ({})", name, param.name, param.kind.describe());
+                return Err(CommandError::InvalidArgument(message));
+            }
+        };
+        // text at the end of the line takes all of it, just as it was typed
+        let takes_rest = param.kind == ParamType::Text || param.kind == ParamType::Pattern;
+        let word = if takes_rest && i == params.len() - 1 {
+            // and so the words after this one are part of it
+            words.by_ref().count();
+            line[start..].trim_end().to_string()
+        } else {
+            word
+        };
+        if param.kind.fill(&word, &mut args).is_err() {
+            let message = format!("{} should be {}, not '{}'", param.name, param.kind.describe(), word);
+            return Err(CommandError::InvalidArgument(message));
+        }
+    }
+
+    if words.next().is_some() {
+        let message = format!("{} takes at most {} argument(s)", name, params.len());
+        return Err(CommandError::InvalidArgument(message));
+    }
+    Ok(args)
+}
 
 pub enum BuilderEvent {
     Invalid,            // cannot find a valid interpretation
     Incomplete,         // needs more information
     Complete(CommandInfo),  // command is finished
+    Preview(CommandInfo),   // command to run now, while more information is given
+}
+
+#[cfg(test)]
+mod tests {
+
+    use command::{Command, BuilderArgs, CommandError, Param, ParamType, parse_args, split_words};
+    use command::{LineAddress, LineRange, Substitution};
+    use textobject::{Kind, Anchor, Offset};
+
+    #[test]
+    fn missing_arguments_are_errors() {
+        match Command::insert_char(None) {
+            Err(CommandError::MissingArgument(_)) => {}
+            _ => panic!("expected insert_char to need arguments"),
+        }
+        assert!(Command::insert_char(Some(BuilderArgs::new())).is_err());
+        assert!(Command::insert_char(Some(BuilderArgs::new().with_char_arg('a'))).is_ok());
+    }
+
+    fn words(line: &str) -> Vec<String> {
+        split_words(line)
+    }
+
+    #[test]
+    fn split_words_handles_quotes_and_escapes() {
+        assert_eq!(words("  a  b\tc "), vec!["a", "b", "c"]);
+        assert_eq!(words("open \"my file.txt\" x"), vec!["open", "my file.txt", "x"]);
+        assert_eq!(words("a\\ b \"\""), vec!["a b", ""]);
+    }
+
+    #[test]
+    fn parse_args_fills_builder_args() {
+        let params = vec![
+            Param::required("kind", ParamType::Kind),
+            Param::required("offset", ParamType::Offset),
+            Param::optional("count", ParamType::Number),
+        ];
+        let args = parse_args("buffer::move_cursor", &params, "line:start forward 3").unwrap();
+        match (args.kind, args.offset, args.number) {
+            (Some(Kind::Line(Anchor::Start)), Some(Offset::Forward(1, _)), Some(3)) => {}
+            _ => panic!("arguments were not filled in"),
+        }
+
+        let args = parse_args("buffer::move_cursor", &params, "word 12").unwrap();
+        match (args.offset, args.number) {
+            (Some(Offset::Absolute(12)), None) => {}
+            _ => panic!("optional argument should be left out"),
+        }
+    }
+
+    #[test]
+    fn match_kinds_take_their_pattern_when_built() {
+        let params = vec![
+            Param::required("kind", ParamType::Kind),
+            Param::required("offset", ParamType::Offset),
+            Param::optional("count", ParamType::Number),
+            Param::optional("pattern", ParamType::Pattern),
+        ];
+        let args = parse_args("buffer::delete", &params, "match:end forward 2 fo+").unwrap();
+        assert!(args.kind.is_none());
+        match Command::delete(Some(args)).unwrap().object.map(|object| object.kind) {
+            Some(Kind::Match(ref pattern, Anchor::End)) if pattern == "fo+" => {}
+            other => panic!("expected a match of fo+, got {:?}", other),
+        }
+
+        let args = parse_args("buffer::delete", &params, "match forward").unwrap();
+        match Command::delete(Some(args)) {
+            Err(CommandError::MissingArgument(_)) => {}
+            _ => panic!("a match needs a pattern"),
+        }
+        let args = parse_args("buffer::delete", &params, "match forward 1 fo(").unwrap();
+        assert!(Command::delete(Some(args)).is_err());
+    }
+
+    #[test]
+    fn parse_args_rejects_bad_arguments() {
+        let params = vec![Param::required("line", ParamType::Number)];
+        assert!(parse_args("buffer::goto_line", &params, "").is_err());
+        assert!(parse_args("buffer::goto_line", &params, "ten").is_err());
+        assert!(parse_args("buffer::goto_line", &params, "10 20").is_err());
+    }
+
+    #[test]
+    fn text_takes_the_rest_of_the_line() {
+        let params = vec![Param::required("message", ParamType::Text)];
+        let args = parse_args("editor::show_message", &params, "hello  \"there\" wo\\rld ").unwrap();
+        assert_eq!(args.str_args.unwrap(), "hello  \"there\" wo\\rld");
+    }
+
+    #[test]
+    fn parses_line_ranges() {
+        assert_eq!(LineRange::parse("%"), Some(LineRange { start: LineAddress::Number(1), end: LineAddress::Last }));
+        assert_eq!(LineRange::parse(".,$"), Some(LineRange { start: LineAddress::Current, end: LineAddress::Last }));
+        assert_eq!(LineRange::parse("12"), Some(LineRange { start: LineAddress::Number(12), end: LineAddress::Number(12) }));
+        assert_eq!(LineRange::parse("'<,'>"), Some(LineRange { start: LineAddress::SelectionStart, end: LineAddress::SelectionEnd }));
+        assert_eq!(LineRange::parse("3,x"), None);
+        assert_eq!(LineRange::parse(""), None);
+    }
+
+    #[test]
+    fn parses_substitutions() {
+        let sub = Substitution::parse("/foo/bar/g").unwrap();
+        assert_eq!(sub.pattern, "foo");
+        assert_eq!(sub.replacement, "bar");
+        assert!(sub.global);
+
+        let sub = Substitution::parse("#a/b#c#").unwrap();
+        assert_eq!(sub.pattern, "a/b");
+        assert!(!sub.global);
+        assert!(!sub.confirm);
+
+        let sub = Substitution::parse("/a/b/cg").unwrap();
+        assert!(sub.global && sub.confirm);
+        assert!(Substitution::parse("/a/b/x").is_none());
+
+        let sub = Substitution::parse(r"/x\/y/z").unwrap();
+        assert_eq!(sub.pattern, "x/y");
+
+        assert!(Substitution::parse("foo").is_none());
+    }
+
+    #[test]
+    fn substitution_replacements_use_vi_syntax() {
+        let sub = Substitution::parse(r"/(a)(b)/\2\1 & $1/").unwrap();
+        assert_eq!(sub.replacement, "${2}${1} ${0} $$1");
+    }
 }
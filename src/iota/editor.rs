@@ -1,45 +1,312 @@
-use std::path::PathBuf;
+use std::cmp;
+use std::fs;
+use std::io::{self, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
 use std::sync::{Mutex, Arc};
 use std::sync::mpsc::{Sender, Receiver};
 use std::sync::mpsc::channel;
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
-use rustbox::{RustBox, Event};
+use rustbox::{RustBox, Event, Mouse};
 
 use input::Input;
-use keyboard::Key;
-use view::View;
-use modes::{Mode, ModeType, InsertMode, NormalMode};
-use buffer::Buffer;
+use keyboard::{Key, KeyEvent, BRACKETED_PASTE_ON, BRACKETED_PASTE_OFF};
+use view::{Selection, View};
+use overlay::{BufferList, OverlayType, SearchPrompt};
+use modes::{Mode, ModeType, InsertMode, NormalMode, VisualMode};
+use buffer::{Buffer, Mark};
 use command::Command;
-use command::{Action, BuilderEvent, BuilderArgs, Operation, Instruction};
+use command::{Action, BuilderEvent, BuilderArgs, CommandError, Operation, Operator, Instruction};
+use command::{Param, ParamType, parse_args};
+use keymap::CommandInfo;
 
+/// How long, in milliseconds, to wait after an escape for the rest of a key
+/// sequence, unless told otherwise.
+const ESCAPE_TIMEOUT_MS: u64 = 50;
 
-type EditorCommand = fn(Option<BuilderArgs>) -> Command;
-lazy_static! {
-    pub static ref ALL_COMMANDS: HashMap<&'static str, EditorCommand> = {
-        let mut map: HashMap<&'static str, EditorCommand> = HashMap::new();
+/// How soon a second click has to follow the first to be a double click.
+const DOUBLE_CLICK_MS: u64 = 400;
 
-        map.insert("editor::quit", Command::exit_editor);
-        map.insert("editor::save_buffer", Command::save_buffer);
-        map.insert("editor::noop", Command::noop);
+/// How many lines a turn of the mouse wheel scrolls by.
+const SCROLL_LINES: isize = 3;
 
-        map.insert("editor::undo", Command::undo);
-        map.insert("editor::redo", Command::redo);
-        map.insert("editor::set_mode", Command::set_mode);
 
-        map.insert("editor::set_overlay", Command::set_overlay);
+type EditorCommand = fn(Option<BuilderArgs>) -> Result<Command, CommandError>;
 
-        map.insert("buffer::move_cursor", Command::move_cursor);
-        map.insert("buffer::insert_char", Command::insert_char);
-        map.insert("buffer::insert_tab", Command::insert_tab);
-        map.insert("buffer::delete_char", Command::delete_char);
+/// A command which can be run by name, and the parameters it takes from the
+/// command prompt.
+pub struct CommandSpec {
+    pub build: EditorCommand,
+    pub params: Vec<Param>,
+}
+
+fn spec(build: EditorCommand, params: Vec<Param>) -> CommandSpec {
+    CommandSpec {
+        build: build,
+        params: params,
+    }
+}
+
+lazy_static! {
+    pub static ref ALL_COMMANDS: HashMap<&'static str, CommandSpec> = {
+        let mut map: HashMap<&'static str, CommandSpec> = HashMap::new();
+
+        map.insert("editor::quit", spec(Command::exit_editor, vec![]));
+        map.insert("editor::force_quit", spec(Command::force_quit, vec![]));
+        map.insert("editor::save_and_quit", spec(Command::save_and_quit, vec![
+            Param::optional("path", ParamType::Path),
+        ]));
+        map.insert("editor::save_if_modified_and_quit", spec(Command::save_if_modified_and_quit, vec![
+            Param::optional("path", ParamType::Path),
+        ]));
+        map.insert("editor::save_buffer", spec(Command::save_buffer, vec![]));
+        map.insert("editor::save_as", spec(Command::save_as, vec![
+            Param::required("path", ParamType::Path),
+        ]));
+        map.insert("editor::find_file", spec(Command::find_file, vec![]));
+        map.insert("editor::open_file", spec(Command::open_file, vec![
+            Param::required("path", ParamType::Path),
+        ]));
+        map.insert("editor::noop", spec(Command::noop, vec![]));
+        map.insert("editor::messages", spec(Command::message_history, vec![]));
+        map.insert("editor::show_message", spec(Command::show_message, vec![
+            Param::required("message", ParamType::Text),
+        ]));
+
+        map.insert("editor::undo", spec(Command::undo, vec![]));
+        map.insert("editor::redo", spec(Command::redo, vec![]));
+        map.insert("editor::undo_branch_next", spec(Command::undo_branch_next, vec![]));
+        map.insert("editor::undo_branch_prev", spec(Command::undo_branch_prev, vec![]));
+        map.insert("editor::undo_earlier", spec(Command::undo_earlier, vec![
+            Param::optional("minutes", ParamType::Number),
+        ]));
+        map.insert("editor::undo_later", spec(Command::undo_later, vec![
+            Param::optional("minutes", ParamType::Number),
+        ]));
+        map.insert("editor::undo_goto", spec(Command::undo_goto, vec![
+            Param::required("state", ParamType::Number),
+        ]));
+        map.insert("editor::undo_tree", spec(Command::undo_tree, vec![]));
+        map.insert("editor::set_mode", spec(Command::set_mode, vec![
+            Param::required("mode", ParamType::Mode),
+        ]));
+
+        map.insert("editor::set_overlay", spec(Command::set_overlay, vec![
+            Param::required("overlay", ParamType::Overlay),
+        ]));
+
+        map.insert("buffer::move_cursor", spec(Command::move_cursor, vec![
+            Param::required("kind", ParamType::Kind),
+            Param::required("offset", ParamType::Offset),
+            Param::optional("count", ParamType::Number),
+            Param::optional("pattern", ParamType::Pattern),
+        ]));
+        map.insert("buffer::goto_line", spec(Command::goto_line, vec![
+            Param::required("line", ParamType::Range),
+        ]));
+        map.insert("buffer::delete_lines", spec(Command::delete_lines, vec![
+            Param::optional("lines", ParamType::Range),
+        ]));
+        map.insert("buffer::substitute", spec(Command::substitute, vec![
+            Param::required("lines", ParamType::Range),
+            Param::required("expression", ParamType::Text),
+        ]));
+        map.insert("buffer::replace_match", spec(Command::replace_match, vec![]));
+        map.insert("buffer::skip_match", spec(Command::skip_match, vec![]));
+        map.insert("buffer::replace_remaining", spec(Command::replace_remaining, vec![]));
+        map.insert("buffer::stop_replacing", spec(Command::stop_replacing, vec![]));
+        map.insert("buffer::insert_char", spec(Command::insert_char, vec![
+            Param::required("char", ParamType::Char),
+        ]));
+        map.insert("buffer::insert_tab", spec(Command::insert_tab, vec![]));
+        map.insert("buffer::delete", spec(Command::delete, vec![
+            Param::required("kind", ParamType::Kind),
+            Param::required("offset", ParamType::Offset),
+            Param::optional("count", ParamType::Number),
+            Param::optional("pattern", ParamType::Pattern),
+        ]));
+        map.insert("buffer::change", spec(Command::change, vec![
+            Param::required("kind", ParamType::Kind),
+            Param::required("offset", ParamType::Offset),
+            Param::optional("count", ParamType::Number),
+            Param::optional("pattern", ParamType::Pattern),
+        ]));
+        map.insert("buffer::yank", spec(Command::yank, vec![
+            Param::required("kind", ParamType::Kind),
+            Param::required("offset", ParamType::Offset),
+            Param::optional("count", ParamType::Number),
+            Param::optional("pattern", ParamType::Pattern),
+        ]));
+        map.insert("buffer::delete_line", spec(Command::delete_line, vec![
+            Param::optional("count", ParamType::Number),
+        ]));
+        map.insert("buffer::change_line", spec(Command::change_line, vec![
+            Param::optional("count", ParamType::Number),
+        ]));
+        map.insert("buffer::yank_line", spec(Command::yank_line, vec![
+            Param::optional("count", ParamType::Number),
+        ]));
+        map.insert("buffer::set_mark", spec(Command::set_mark, vec![]));
+        map.insert("buffer::clear_mark", spec(Command::clear_mark, vec![]));
+        map.insert("buffer::kill_region", spec(Command::kill_region, vec![]));
+        map.insert("buffer::copy_region", spec(Command::copy_region, vec![]));
+        map.insert("buffer::kill_line", spec(Command::kill_line, vec![
+            Param::optional("count", ParamType::Number),
+        ]));
+        map.insert("buffer::yank_kill", spec(Command::yank_kill, vec![]));
+        map.insert("buffer::yank_pop", spec(Command::yank_pop, vec![]));
+        map.insert("buffer::search", spec(Command::search, vec![
+            Param::required("direction", ParamType::Offset),
+            Param::optional("pattern", ParamType::Pattern),
+        ]));
+        map.insert("buffer::search_preview", spec(Command::search_preview, vec![
+            Param::required("direction", ParamType::Offset),
+            Param::optional("pattern", ParamType::Pattern),
+        ]));
+        map.insert("buffer::cancel_search", spec(Command::cancel_search, vec![]));
+        map.insert("buffer::search_next", spec(Command::search_next, vec![
+            Param::optional("count", ParamType::Number),
+        ]));
+        map.insert("buffer::search_previous", spec(Command::search_previous, vec![
+            Param::optional("count", ParamType::Number),
+        ]));
+        map.insert("buffer::search_again", spec(Command::search_again, vec![
+            Param::required("direction", ParamType::Offset),
+            Param::optional("count", ParamType::Number),
+        ]));
+        map.insert("buffer::clear_search", spec(Command::clear_search, vec![]));
+        map.insert("buffer::search_case", spec(Command::search_case, vec![
+            Param::required("case", ParamType::Case),
+        ]));
+        map.insert("buffer::search_motion", spec(Command::search_motion, vec![
+            Param::required("direction", ParamType::Offset),
+            Param::required("operator", ParamType::Text),
+            Param::optional("count", ParamType::Number),
+        ]));
+        map.insert("buffer::use_register", spec(Command::use_register, vec![
+            Param::required("register", ParamType::Char),
+        ]));
+        map.insert("buffer::put", spec(Command::put, vec![
+            Param::optional("count", ParamType::Number),
+        ]));
+        map.insert("buffer::put_before", spec(Command::put_before, vec![
+            Param::optional("count", ParamType::Number),
+        ]));
+        map.insert("buffer::indent", spec(Command::indent, vec![
+            Param::required("kind", ParamType::Kind),
+            Param::required("offset", ParamType::Offset),
+            Param::optional("count", ParamType::Number),
+            Param::optional("pattern", ParamType::Pattern),
+        ]));
+        map.insert("buffer::outdent", spec(Command::outdent, vec![
+            Param::required("kind", ParamType::Kind),
+            Param::required("offset", ParamType::Offset),
+            Param::optional("count", ParamType::Number),
+            Param::optional("pattern", ParamType::Pattern),
+        ]));
+        map.insert("buffer::indent_line", spec(Command::indent_line, vec![
+            Param::optional("count", ParamType::Number),
+        ]));
+        map.insert("buffer::outdent_line", spec(Command::outdent_line, vec![
+            Param::optional("count", ParamType::Number),
+        ]));
+        map.insert("buffer::delete_selection", spec(Command::delete_selection, vec![]));
+        map.insert("buffer::change_selection", spec(Command::change_selection, vec![]));
+        map.insert("buffer::yank_selection", spec(Command::yank_selection, vec![]));
+        map.insert("buffer::indent_selection", spec(Command::indent_selection, vec![]));
+        map.insert("buffer::outdent_selection", spec(Command::outdent_selection, vec![]));
+        map.insert("buffer::toggle_case_selection", spec(Command::toggle_case_selection, vec![]));
+        map.insert("buffer::insert_block", spec(Command::insert_block, vec![]));
+        map.insert("buffer::append_block", spec(Command::append_block, vec![]));
+        map.insert("buffer::swap_selection_ends", spec(Command::swap_selection_ends, vec![]));
+        map.insert("buffer::open_line_below", spec(Command::open_line_below, vec![]));
+        map.insert("buffer::open_line_above", spec(Command::open_line_above, vec![]));
+        map.insert("buffer::delete_char", spec(Command::delete_char, vec![
+            Param::required("kind", ParamType::Kind),
+            Param::required("offset", ParamType::Offset),
+        ]));
+
+        map.insert("buffer::next", spec(Command::next_buffer, vec![]));
+        map.insert("buffer::previous", spec(Command::previous_buffer, vec![]));
+        map.insert("buffer::switch_to", spec(Command::switch_to_buffer, vec![
+            Param::required("buffer", ParamType::Number),
+        ]));
+        map.insert("buffer::close", spec(Command::close_buffer, vec![]));
+        map.insert("buffer::list", spec(Command::list_buffers, vec![]));
 
 
         map
     };
 }
 
+/// Look up the command called `name` and build it with `args`.
+fn build_command(name: &str, args: Option<BuilderArgs>) -> Result<Command, CommandError> {
+    match ALL_COMMANDS.get(name) {
+        Some(spec) => (spec.build)(args),
+        None => Err(CommandError::Unknown(name.to_string())),
+    }
+}
+
+/// Turn a line typed at the command prompt, a command name followed by its
+/// arguments, into the command to run.
+pub fn parse_command_line(line: &str) -> Result<CommandInfo, CommandError> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(CommandInfo { command_name: String::from("editor::noop"), args: None });
+    }
+    let name_len = line.find(char::is_whitespace).unwrap_or(line.len());
+    let (name, rest) = line.split_at(name_len);
+    let spec = match ALL_COMMANDS.get(name) {
+        Some(spec) => spec,
+        None => return Err(CommandError::Unknown(name.to_string())),
+    };
+
+    let args = if spec.params.is_empty() && rest.trim().is_empty() {
+        None
+    } else {
+        Some(parse_args(name, &spec.params, rest)?)
+    };
+    Ok(CommandInfo {
+        command_name: name.to_string(),
+        args: args,
+    })
+}
+
+/// Send the terminal an escape sequence switching one of its modes, which
+/// termbox has no way of doing itself.
+fn set_terminal_mode(sequence: &str) {
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(sequence.as_bytes());
+    let _ = stdout.flush();
+}
+
+/// Bracketed paste, switched on for as long as this is held, and switched
+/// off again however the editor stops, even by a panic.
+struct BracketedPaste;
+
+impl BracketedPaste {
+    fn on() -> BracketedPaste {
+        set_terminal_mode(BRACKETED_PASTE_ON);
+        BracketedPaste
+    }
+}
+
+impl Drop for BracketedPaste {
+    fn drop(&mut self) {
+        set_terminal_mode(BRACKETED_PASTE_OFF);
+    }
+}
+
+/// Whether two paths lead to the same file.
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
 /// The main Editor structure
 ///
 /// This is the top-most structure in Iota.
@@ -48,8 +315,21 @@ pub struct Editor<'e> {
     view: View<'e>,
     running: bool,
     force_quit: bool,
+    /// Whether closing the current buffer has been asked for once already,
+    /// despite its unsaved changes.
+    force_close: bool,
+    /// Whether a run of edits is being grouped into a single undo transaction.
+    editing: bool,
     rb: RustBox,
     mode: Box<Mode + 'e>,
+    /// How long to wait after an escape for the rest of a key sequence, such
+    /// as the key pressed with meta, before taking it as the escape key.
+    escape_timeout: Duration,
+    /// Where the left mouse button was pressed, while it is held down.
+    mouse_down: Option<(usize, usize)>,
+    /// When and where the left mouse button was last pressed, to tell a
+    /// double click.
+    last_click: Option<(Instant, usize, usize)>,
 
     command_queue: Receiver<Command>,
     command_sender: Sender<Command>,
@@ -64,20 +344,25 @@ impl<'e> Editor<'e> {
 
         let (snd, recv) = channel();
 
-        let mut buffers = Vec::new();
-
-        let buffer = match source {
-            Input::Filename(path) => {
-                match path {
-                    Some(path) => Buffer::from(PathBuf::from(path)),
-                    None       => Buffer::new(),
-                }
+        let opened = match source {
+            Input::Filenames(paths) => {
+                paths.into_iter().map(|path| Buffer::open(PathBuf::from(path))).collect()
             },
-            Input::Stdin(reader) => {
-                Buffer::from(reader)
+            Input::Stdin(mut reader) => {
+                vec![Buffer::read(&mut reader)]
             }
         };
-        buffers.push(Arc::new(Mutex::new(buffer)));
+        // one buffer per file, with any that can't be opened said so in the status bar
+        let mut buffers: Vec<Arc<Mutex<Buffer>>> = Vec::new();
+        for result in opened {
+            match result {
+                Ok(buffer) => buffers.push(Arc::new(Mutex::new(buffer))),
+                Err(e) => { let _ = snd.send(Command::message(e.to_string())); }
+            }
+        }
+        if buffers.is_empty() {
+            buffers.push(Arc::new(Mutex::new(Buffer::new())));
+        }
 
         let view = View::new(buffers[0].clone(), width, height);
 
@@ -86,14 +371,28 @@ impl<'e> Editor<'e> {
             view: view,
             running: true,
             force_quit: false,
+            force_close: false,
+            editing: false,
             rb: rb,
             mode: mode,
+            escape_timeout: Duration::from_millis(ESCAPE_TIMEOUT_MS),
+            mouse_down: None,
+            last_click: None,
 
             command_queue: recv,
             command_sender: snd,
         }
     }
 
+    /// Set how long to wait after an escape for the rest of a key sequence.
+    ///
+    /// A longer wait lets meta keys through over slow connections, while a
+    /// shorter one means a lone escape, such as leaving insert mode, is acted
+    /// on sooner.
+    pub fn set_escape_timeout(&mut self, timeout: Duration) {
+        self.escape_timeout = timeout;
+    }
+
     /// Handle key events
     ///
     /// Key events can be handled in an Overlay, OR in the current Mode.
@@ -106,10 +405,12 @@ impl<'e> Editor<'e> {
     /// If there is no active Overlay, the key event is sent to the current
     /// Mode, which returns a Command which we dispatch to handle_command.
     fn handle_key_event(&mut self, event: Event) {
-        let key = Key::from_event(&mut self.rb, event);
- 
-        let key = match key {
-            Some(k) => k,
+        self.mouse_down = None;
+        let input = KeyEvent::from_event(&mut self.rb, event, self.escape_timeout);
+
+        let key = match input {
+            Some(KeyEvent::Key(k)) => k,
+            Some(KeyEvent::Paste(text)) => return self.handle_paste(text),
             None => return
         };
 
@@ -117,22 +418,114 @@ impl<'e> Editor<'e> {
             None                  => self.mode.handle_key_event(key),
             Some(ref mut overlay) => overlay.handle_key_event(key),
         };
+        self.handle_builder_event(command);
+    }
 
-        if let BuilderEvent::Complete(c) = command {
-            self.view.overlay = None;
-            self.view.clear(&mut self.rb);
+    /// Handle text pasted in all at once
+    ///
+    /// In a buffer, the text is inserted as it is, whatever the mode, and can
+    /// be undone in one go. An Overlay is given the text a char at a time, as
+    /// if it were typed, leaving out any line breaks and other control chars.
+    fn handle_paste(&mut self, text: String) {
+        if self.view.overlay.is_none() {
+            let _ = self.command_sender.send(Command::paste(text));
+            return;
+        }
+        for c in text.chars().filter(|c| !c.is_control()) {
+            let command = match self.view.overlay {
+                Some(ref mut overlay) => overlay.handle_key_event(Key::char(c)),
+                None => return,
+            };
+            self.handle_builder_event(command);
+        }
+    }
+
+    /// Dispatch the command built by a Mode or Overlay, once it is complete,
+    /// or as a preview while the Overlay carries on
+    fn handle_builder_event(&mut self, command: BuilderEvent) {
+        let c = match command {
+            BuilderEvent::Complete(c) => {
+                self.view.overlay = None;
+                self.view.clear(&mut self.rb);
+                c
+            }
+            BuilderEvent::Preview(c) => c,
+            _ => return,
+        };
+
+        let cmd = match build_command(&c.command_name, c.args) {
+            Ok(cmd) => cmd,
+            Err(e) => Command::message(e.to_string()),
+        };
+        let _ = self.command_sender.send(cmd);
+    }
+
+    /// Handle mouse events
+    ///
+    /// Pressing the left button moves the cursor to where it was pressed, and
+    /// moving it before letting go selects the text in between. A double click
+    /// selects a word. The wheel scrolls the view. While there is an Overlay,
+    /// clicks go to it instead.
+    fn handle_mouse_event(&mut self, button: Mouse, x: usize, y: usize) {
+        if self.view.overlay.is_some() {
+            let height = self.rb.height();
+            let command = match (button, self.view.overlay.as_mut()) {
+                (Mouse::Left, Some(overlay)) => overlay.handle_click(x, y, height),
+                _ => BuilderEvent::Incomplete,
+            };
+            return self.handle_builder_event(command);
+        }
 
-            match ALL_COMMANDS.get(&*c.command_name) {
-                Some(cmd) => {
-                    let cmd = cmd(c.args);
-                    let _ = self.command_sender.send(cmd);
+        let instruction = match button {
+            Mouse::Left => {
+                match self.mouse_down {
+                    // some terminals report the mouse moving with the button held
+                    Some(down) if down == (x, y) => return,
+                    Some(_) => Instruction::DragTo(x, y),
+                    None => {
+                        let now = Instant::now();
+                        let double = match self.last_click {
+                            Some((time, last_x, last_y)) => {
+                                (last_x, last_y) == (x, y) &&
+                                    now.duration_since(time) < Duration::from_millis(DOUBLE_CLICK_MS)
+                            }
+                            None => false,
+                        };
+                        // a third click starts again
+                        self.last_click = if double { None } else { Some((now, x, y)) };
+                        if double { Instruction::SelectWordAt(x, y) } else { Instruction::ClickAt(x, y) }
+                    }
                 }
-                None => {
-                    panic!("Unknown command: {}", c.command_name);
+            }
+            Mouse::Release => {
+                let down = self.mouse_down.take();
+                match down {
+                    Some(down) if down != (x, y) => Instruction::DragTo(x, y),
+                    _ => return,
                 }
             }
+            Mouse::WheelUp => Instruction::Scroll(-SCROLL_LINES),
+            Mouse::WheelDown => Instruction::Scroll(SCROLL_LINES),
+            _ => return,
+        };
+        if let Mouse::Left = button {
+            self.mouse_down = Some((x, y));
+        }
+        let _ = self.command_sender.send(Command::mouse(instruction));
+    }
 
-            // let _ = self.command_sender.send(c);
+    /// Start selecting text with the mouse, switching to the mode text is
+    /// selected in if there is one, and give back how the text is selected.
+    fn start_mouse_selection(&mut self) -> Selection {
+        match self.mode.selection_mode() {
+            Some(mode) => {
+                self.set_mode(mode);
+                Selection::Chars
+            }
+            None => {
+                self.view.select(Selection::Region);
+                Selection::Region
+            }
         }
     }
 
@@ -150,45 +543,170 @@ impl<'e> Editor<'e> {
 
     /// Handle the given command, performing the associated action
     fn handle_command(&mut self, command: Command) {
+        self.group_edits(&command);
+
+        match command.action {
+            Action::Instruction(Instruction::CloseBuffer) => {}
+            _ => self.force_close = false,
+        }
+
         let repeat = if command.number > 0 {
             command.number
         } else { 1 };
+
+        // a repeated command is undone all at once. The command may switch
+        // buffers, so hold on to the one the transaction is opened in.
+        let buffer = self.view.buffer.clone();
+        buffer.lock().unwrap().start_transaction();
         for _ in 0..repeat {
             match command.action {
                 Action::Instruction(_) => self.handle_instruction(command.clone()),
                 Action::Operation(_) => self.handle_operation(command.clone()),
             }
         }
+        buffer.lock().unwrap().commit_transaction();
+
+        // some commands carry on from the one before: a register is chosen for
+        // the one command after it, kills add to the kill before them, and
+        // yank-pop replaces the text just yanked
+        let (register, kill, yank) = match command.action {
+            Action::Instruction(Instruction::UseRegister(_)) => (true, false, false),
+            Action::Operation(Operation::KillRegion) |
+            Action::Operation(Operation::CopyRegion) |
+            Action::Operation(Operation::KillLine) => (false, true, false),
+            Action::Operation(Operation::YankKill) |
+            Action::Operation(Operation::YankPop) => (false, false, true),
+            _ => (false, false, false),
+        };
+        if !register {
+            self.view.clear_register();
+        }
+        if !kill {
+            self.view.end_kill();
+        }
+        if !yank {
+            self.view.end_yank();
+        }
+    }
+
+    /// Open or close the transaction grouping a run of edits, so that they
+    /// can be undone in one go.
+    ///
+    /// A run starts with inserted text (or entering insert mode, which vi's
+    /// `c` and `o` also do) and carries on through further insertions and
+    /// deletions. Anything else, such as moving the cursor or leaving insert
+    /// mode, ends it.
+    fn group_edits(&mut self, command: &Command) {
+        let (starts_run, continues_run) = match command.action {
+            Action::Operation(Operation::Insert(_)) |
+            Action::Operation(Operation::Operate(Operator::Change, _)) |
+            Action::Operation(Operation::OperateOnLines(Operator::Change, _)) |
+            Action::Operation(Operation::OpenLine(_)) |
+            Action::Operation(Operation::OperateOnSelection(Operator::Change)) |
+            Action::Operation(Operation::InsertInBlock(_)) |
+            Action::Instruction(Instruction::SetMode(ModeType::Insert)) => (true, true),
+
+            // leaving a block insert copies what was typed to the block's other
+            // lines, which belongs with the typing
+            Action::Instruction(Instruction::SetMode(ModeType::Normal)) if self.view.in_block_insert() => (false, true),
+
+            Action::Operation(Operation::DeleteObject) |
+            Action::Operation(Operation::DeleteFromMark(_)) |
+            Action::Instruction(Instruction::ShowMessage(_)) |
+            Action::Instruction(Instruction::Scroll(_)) |
+            Action::Instruction(Instruction::None) => (false, true),
+
+            _ => (false, false),
+        };
+
+        if self.editing && !continues_run {
+            self.view.commit_transaction();
+            self.view.finish_insert();
+            self.editing = false;
+        }
+        if !self.editing && starts_run {
+            self.view.start_transaction();
+            self.editing = true;
+        }
     }
 
 
     fn handle_instruction(&mut self, command: Command) {
         match command.action {
-            Action::Instruction(Instruction::SaveBuffer) => { self.view.try_save_buffer() }
-            Action::Instruction(Instruction::ExitEditor) => {
-                if self.view.buffer_is_dirty() && ! self.force_quit {
-                    let args = BuilderArgs::new().with_str("Unsaved changes (press ctrl+q to force quit)".into());
-                    let _ = self.command_sender.send(Command::show_message(Some(args)));
-                    self.force_quit = true;
-                } else {
-                    self.running = false;
+            Action::Instruction(Instruction::SaveBuffer) => { self.save_buffer(); }
+            Action::Instruction(Instruction::ExitEditor) => { self.exit() }
+            Action::Instruction(Instruction::ForceExitEditor) => { self.running = false }
+            Action::Instruction(Instruction::SaveAndExit(only_if_modified, path)) => {
+                if self.view.buffer_is_dirty() || !only_if_modified {
+                    let saved = match path {
+                        Some(path) => self.save_as(path),
+                        None => self.save_buffer(),
+                    };
+                    if !saved {
+                        return;
+                    }
                 }
-
+                self.exit();
+            }
+            Action::Instruction(Instruction::GotoLine(address)) => { self.view.goto_line(address) }
+            Action::Instruction(Instruction::SwapSelectionEnds) => { self.view.swap_selection_ends() }
+            Action::Instruction(Instruction::UseRegister(name)) => { self.view.use_register(name) }
+            Action::Instruction(Instruction::SetRegionMark) => { self.view.set_region_mark() }
+            Action::Instruction(Instruction::ClearSelection) => { self.view.clear_selection() }
+            Action::Instruction(Instruction::Search(ref pattern, backward)) => { self.view.search(pattern, backward) }
+            Action::Instruction(Instruction::SearchPreview(ref pattern, backward)) => {
+                self.view.search_preview(pattern, backward)
+            }
+            Action::Instruction(Instruction::CancelSearch) => { self.view.cancel_search() }
+            Action::Instruction(Instruction::SearchNext(reverse)) => { self.view.search_next(reverse) }
+            Action::Instruction(Instruction::SearchAgain(backward)) => { self.view.search_again(backward) }
+            Action::Instruction(Instruction::ClearSearch) => { self.view.clear_search() }
+            Action::Instruction(Instruction::SetSearchCase(case)) => { self.view.set_search_case(case) }
+            Action::Instruction(Instruction::SearchMotion(operator, backward)) => {
+                self.view.overlay = Some(Box::new(SearchPrompt::for_operator(operator, backward)));
             }
             Action::Instruction(Instruction::SetMark(mark)) => {
                 if let Some(object) = command.object {
                     self.view.move_mark(mark, object)
                 }
             }
+            Action::Instruction(Instruction::SetOverlay(OverlayType::BufferList)) => {
+                let entries = self.buffers.iter().map(|b| {
+                    let b = b.lock().unwrap();
+                    let name = b.status_text();
+                    if b.dirty { format!("{}+", name) } else { name }
+                }).collect();
+                self.view.overlay = Some(Box::new(BufferList::new(entries, self.current_buffer())));
+            }
             Action::Instruction(Instruction::SetOverlay(overlay_type)) => {
                 self.view.set_overlay(overlay_type)
             }
             Action::Instruction(Instruction::SetMode(mode)) => {
-                match mode {
-                    ModeType::Insert => { self.mode = Box::new(InsertMode::new()) }
-                    ModeType::Normal => { self.mode = Box::new(NormalMode::new()) }
+                if let Some(object) = command.object {
+                    self.view.move_mark(Mark::Cursor(0), object)
+                }
+                self.set_mode(mode);
+            }
+            Action::Instruction(Instruction::ClickAt(x, y)) => {
+                if self.view.selection().is_some() && self.mode.selection_mode().is_some() {
+                    self.set_mode(ModeType::Normal);
                 }
+                self.view.click(x, y);
             }
+            Action::Instruction(Instruction::DragTo(x, y)) => {
+                if self.view.selection().is_none() {
+                    self.start_mouse_selection();
+                }
+                self.view.drag_to(x, y);
+            }
+            Action::Instruction(Instruction::SelectWordAt(x, y)) => {
+                let selection = match self.view.selection() {
+                    Some(selection) => selection,
+                    None => self.start_mouse_selection(),
+                };
+                self.view.select_word_at(x, y, selection);
+            }
+            Action::Instruction(Instruction::Scroll(lines)) => { self.view.scroll(lines) }
             Action::Instruction(Instruction::SwitchToLastBuffer) => {
                 self.view.switch_last_buffer();
                 self.view.clear(&mut self.rb);
@@ -196,11 +714,144 @@ impl<'e> Editor<'e> {
             Action::Instruction(Instruction::ShowMessage(msg)) => {
                 self.view.show_message(msg)
             }
+            Action::Instruction(Instruction::SwitchToBuffer(index)) => {
+                self.switch_to_buffer(index);
+            }
+            Action::Instruction(Instruction::NextBuffer) => {
+                let index = (self.current_buffer() + 1) % self.buffers.len();
+                self.switch_to_buffer(index);
+            }
+            Action::Instruction(Instruction::PreviousBuffer) => {
+                let len = self.buffers.len();
+                let index = (self.current_buffer() + len - 1) % len;
+                self.switch_to_buffer(index);
+            }
+            Action::Instruction(Instruction::CloseBuffer) => { self.close_buffer() }
+            Action::Instruction(Instruction::OpenFile(path)) => { self.open_file(path) }
+            Action::Instruction(Instruction::SaveAs(path)) => { self.save_as(path); }
 
             _ => {}
         }
     }
 
+    /// Switch to the mode of type `mode`, starting or ending a selection as
+    /// the mode needs.
+    fn set_mode(&mut self, mode: ModeType) {
+        match mode {
+            ModeType::Insert => { self.mode = Box::new(InsertMode::new()) }
+            ModeType::Normal => {
+                self.view.finish_block_insert();
+                self.view.clear_selection();
+                self.mode = Box::new(NormalMode::new())
+            }
+            ModeType::Visual | ModeType::VisualLine | ModeType::VisualBlock => {
+                let selection = match mode {
+                    ModeType::VisualLine => Selection::Lines,
+                    ModeType::VisualBlock => Selection::Block,
+                    _ => Selection::Chars,
+                };
+                self.view.select(selection);
+                self.mode = Box::new(VisualMode::new(mode))
+            }
+        }
+    }
+
+    /// Save the current buffer, saying why if it can't be.  Returns whether
+    /// it was saved.
+    fn save_buffer(&mut self) -> bool {
+        match self.view.try_save_buffer() {
+            Ok(()) => true,
+            Err(e) => {
+                self.view.show_message(e.to_string());
+                false
+            }
+        }
+    }
+
+    /// Save the current buffer to `path`, which it is then associated with.
+    /// Returns whether it was saved.
+    fn save_as(&mut self, path: PathBuf) -> bool {
+        let old_path = mem::replace(&mut self.view.buffer.lock().unwrap().file_path, Some(path));
+        let saved = self.save_buffer();
+        if !saved {
+            // stay associated with the old file if the new one can't be written
+            self.view.buffer.lock().unwrap().file_path = old_path;
+        }
+        saved
+    }
+
+    /// Stop the editor, unless some buffer has unsaved changes and this is
+    /// the first attempt.
+    fn exit(&mut self) {
+        let any_dirty = self.buffers.iter().any(|b| b.lock().unwrap().dirty);
+        if any_dirty && ! self.force_quit {
+            let message = "Unsaved changes (press ctrl+q to force quit)".into();
+            let _ = self.command_sender.send(Command::message(message));
+            self.force_quit = true;
+        } else {
+            self.running = false;
+        }
+    }
+
+    /// Position in the buffer list of the buffer being shown.
+    fn current_buffer(&self) -> usize {
+        self.buffers.iter().position(|b| Arc::ptr_eq(b, &self.view.buffer)).unwrap_or(0)
+    }
+
+    fn switch_to_buffer(&mut self, index: usize) {
+        let buffer = match self.buffers.get(index) {
+            Some(buffer) => buffer.clone(),
+            None => return,
+        };
+        if !Arc::ptr_eq(&buffer, &self.view.buffer) {
+            self.view.set_buffer(buffer);
+            self.view.clear(&mut self.rb);
+        }
+    }
+
+    /// Show the buffer for the file at `path`, opening it first unless it is
+    /// already open.  A file that can't be opened is reported instead.
+    fn open_file(&mut self, path: PathBuf) {
+        let open = self.buffers.iter().position(|b| {
+            b.lock().unwrap().file_path.as_ref().map_or(false, |p| same_file(p, &path))
+        });
+        let index = match open {
+            Some(index) => index,
+            None => match Buffer::open(path) {
+                Ok(buffer) => {
+                    self.buffers.push(Arc::new(Mutex::new(buffer)));
+                    self.buffers.len() - 1
+                }
+                Err(e) => {
+                    self.view.show_message(e.to_string());
+                    return;
+                }
+            },
+        };
+        self.switch_to_buffer(index);
+    }
+
+    /// Close the current buffer, showing the next one in its place.
+    ///
+    /// A buffer with unsaved changes is only closed when asked twice in a row.
+    fn close_buffer(&mut self) {
+        if self.view.buffer_is_dirty() && !self.force_close {
+            self.view.show_message("Unsaved changes (close again to discard them)".into());
+            self.force_close = true;
+            return;
+        }
+        self.force_close = false;
+
+        let index = self.current_buffer();
+        self.buffers.remove(index);
+        if self.buffers.is_empty() {
+            self.buffers.push(Arc::new(Mutex::new(Buffer::new())));
+        }
+        let next = self.buffers[cmp::min(index, self.buffers.len() - 1)].clone();
+        self.view.replace_buffer(next);
+        self.view.clear(&mut self.rb);
+    }
+
     fn handle_operation(&mut self, command: Command) {
         match command.action {
             Action::Operation(Operation::Insert(c)) => {
@@ -208,6 +859,7 @@ impl<'e> Editor<'e> {
                     self.view.insert_char(c)
                 }
             }
+            Action::Operation(Operation::Paste(ref text)) => { self.view.insert_text(text) }
             Action::Operation(Operation::DeleteObject) => {
                 if let Some(obj) = command.object {
                     self.view.delete_object(obj);
@@ -220,6 +872,61 @@ impl<'e> Editor<'e> {
             }
             Action::Operation(Operation::Undo) => { self.view.undo() }
             Action::Operation(Operation::Redo) => { self.view.redo() }
+            Action::Operation(Operation::UndoBranchNext) => { self.view.undo_branch_next() }
+            Action::Operation(Operation::UndoBranchPrev) => { self.view.undo_branch_prev() }
+            Action::Operation(Operation::UndoEarlier(secs)) => {
+                self.view.undo_earlier(Duration::from_secs(secs))
+            }
+            Action::Operation(Operation::UndoLater(secs)) => {
+                self.view.undo_later(Duration::from_secs(secs))
+            }
+            Action::Operation(Operation::UndoGoto(state)) => { self.view.undo_goto(state) }
+            Action::Operation(Operation::DeleteLines(range)) => { self.view.delete_lines(range) }
+            Action::Operation(Operation::Operate(operator, mark)) => {
+                if let Some(object) = command.object {
+                    self.view.operate(operator, mark, object);
+                }
+                if operator == Operator::Change {
+                    self.mode = Box::new(InsertMode::new());
+                }
+            }
+            Action::Operation(Operation::OperateOnLines(operator, count)) => {
+                self.view.operate_on_lines(operator, count);
+                if operator == Operator::Change {
+                    self.mode = Box::new(InsertMode::new());
+                }
+            }
+            Action::Operation(Operation::OperateOnSelection(operator)) => {
+                self.view.operate_on_selection(operator);
+                if operator == Operator::Change {
+                    self.mode = Box::new(InsertMode::new());
+                } else {
+                    self.mode = Box::new(NormalMode::new());
+                }
+            }
+            Action::Operation(Operation::InsertInBlock(append)) => {
+                self.view.insert_in_block(append);
+                self.mode = Box::new(InsertMode::new());
+            }
+            Action::Operation(Operation::Put(before)) => { self.view.put(before) }
+            Action::Operation(Operation::KillRegion) => { self.view.kill_region(false) }
+            Action::Operation(Operation::CopyRegion) => { self.view.kill_region(true) }
+            Action::Operation(Operation::KillLine) => { self.view.kill_line() }
+            Action::Operation(Operation::YankKill) => { self.view.yank_kill() }
+            Action::Operation(Operation::YankPop) => { self.view.yank_pop() }
+            Action::Operation(Operation::OpenLine(above)) => {
+                self.view.open_line(above);
+                self.mode = Box::new(InsertMode::new());
+            }
+            Action::Operation(Operation::Substitute(range, substitution)) => {
+                // like the other operators on a selection, this one ends it
+                match self.view.selection() {
+                    Some(Selection::Region) | None => {}
+                    Some(_) => self.set_mode(ModeType::Normal),
+                }
+                self.view.substitute(range, &substitution)
+            }
+            Action::Operation(Operation::AnswerReplace(answer)) => { self.view.answer_replace(answer) }
 
             Action::Instruction(_) => {}
         }
@@ -227,6 +934,7 @@ impl<'e> Editor<'e> {
 
     /// Start Iota!
     pub fn start(&mut self) {
+        let _paste = BracketedPaste::on();
         while self.running {
             self.draw();
             self.rb.present();
@@ -234,6 +942,7 @@ impl<'e> Editor<'e> {
 
             match self.rb.poll_event(true) {
                 Ok(Event::ResizeEvent(width, height)) => self.handle_resize_event(width as usize, height as usize),
+                Ok(Event::MouseEvent(button, x, y)) => self.handle_mouse_event(button, x as usize, y as usize),
                 Ok(key_event) => self.handle_key_event(key_event),
                 _ => {}
             }
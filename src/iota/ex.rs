@@ -0,0 +1,189 @@
+//! The vi style `:` command line.
+//!
+//! Lines typed after `:` are turned into the same commands the rest of the
+//! editor uses.  A line may start with a range of lines (`10`, `.,$`, `%`, or
+//! `'<,'>` for the selected ones) followed by one of:
+//!
+//! - nothing, to go to the (last) line of the range
+//! - `w [file]` to save, to `file` if given
+//! - `q` to quit, `q!` to quit discarding changes
+//! - `wq [file]` to save and quit, `x [file]` to save only if there are
+//!   changes and quit, to `file` if given
+//! - `e file` to open a file
+//! - `d` to delete the lines
+//! - `s/pattern/replacement/gc` to replace matches in the lines, every one on
+//!   each line with `g`, asking about each with `c`
+//!
+//! Anything containing `::` is taken as a command name with arguments, as at
+//! the command prompt.
+
+use command::{BuilderArgs, LineRange};
+use editor::parse_command_line;
+use keymap::CommandInfo;
+
+/// Whether `word` is a command name such as `buffer::goto_line`, rather than
+/// an ex command with `::` in its argument, as in `s/std::io/std::fs/`.
+fn is_command_name(word: &str) -> bool {
+    match word.find("::") {
+        Some(idx) => idx > 0 && word[..idx].chars().all(|c| c.is_alphanumeric() || c == '_'),
+        None => false,
+    }
+}
+
+/// Turn an ex command line (without the leading `:`) into the command to run.
+pub fn parse(line: &str) -> Result<CommandInfo, String> {
+    let line = line.trim();
+    if is_command_name(line.split_whitespace().next().unwrap_or("")) {
+        return parse_command_line(line).map_err(|e| e.to_string());
+    }
+
+    // the range is made of line numbers and the characters `.$%,'<>`
+    let split = line.find(|c: char| !(c.is_digit(10) || ".$%,'<>".contains(c))).unwrap_or(line.len());
+    let (range, rest) = line.split_at(split);
+    let range = if range.is_empty() {
+        None
+    } else {
+        match LineRange::parse(range) {
+            Some(range) => Some(range),
+            None => return Err(format!("Invalid range: {}", range)),
+        }
+    };
+
+    let rest = rest.trim_start();
+    let name_len = rest.find(|c: char| !c.is_alphabetic()).unwrap_or(rest.len());
+    let (name, rest) = rest.split_at(name_len);
+    let (bang, argument) = if rest.starts_with('!') {
+        (true, rest[1..].trim())
+    } else {
+        (false, rest.trim())
+    };
+
+    let command = |name: &str, args: Option<BuilderArgs>| {
+        Ok(CommandInfo { command_name: String::from(name), args: args })
+    };
+    // the file a command saving before quitting saves to, if one was given
+    let file = || {
+        if argument.is_empty() { None } else { Some(BuilderArgs::new().with_str(argument.to_string())) }
+    };
+    let with_range = |args: BuilderArgs| {
+        match range {
+            Some(range) => args.with_range(range),
+            None => args,
+        }
+    };
+
+    match name {
+        "" if range.is_some() => command("buffer::goto_line", Some(with_range(BuilderArgs::new()))),
+        "" if argument.is_empty() => command("editor::noop", None),
+        "w" | "write" if argument.is_empty() => command("editor::save_buffer", None),
+        "w" | "write" => {
+            command("editor::save_as", Some(BuilderArgs::new().with_str(argument.to_string())))
+        }
+        "q" | "quit" if bang => command("editor::force_quit", None),
+        "q" | "quit" => command("editor::quit", None),
+        "wq" => command("editor::save_and_quit", file()),
+        "x" | "xit" | "exit" => command("editor::save_if_modified_and_quit", file()),
+        "e" | "edit" if argument.is_empty() => Err(String::from("No file name")),
+        "e" | "edit" => {
+            command("editor::open_file", Some(BuilderArgs::new().with_str(argument.to_string())))
+        }
+        "noh" | "nohlsearch" => command("buffer::clear_search", None),
+        "d" | "delete" => command("buffer::delete_lines", Some(with_range(BuilderArgs::new()))),
+        "s" | "substitute" => {
+            let args = with_range(BuilderArgs::new()).with_str(argument.to_string());
+            command("buffer::substitute", Some(args))
+        }
+        _ => Err(format!("Not an editor command: {}", line)),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use command::{LineAddress, LineRange};
+    use ex::parse;
+
+    fn name(line: &str) -> String {
+        parse(line).unwrap().command_name
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(name("w"), "editor::save_buffer");
+        assert_eq!(name("q"), "editor::quit");
+        assert_eq!(name("q!"), "editor::force_quit");
+        assert_eq!(name("wq"), "editor::save_and_quit");
+        assert_eq!(name("x"), "editor::save_if_modified_and_quit");
+        assert_eq!(name(""), "editor::noop");
+        assert_eq!(name("noh"), "buffer::clear_search");
+        assert_eq!(name("buffer::goto_line 3"), "buffer::goto_line");
+    }
+
+    #[test]
+    fn parses_file_arguments() {
+        let info = parse("w other.txt").unwrap();
+        assert_eq!(info.command_name, "editor::save_as");
+        assert_eq!(info.args.unwrap().str_args, Some(String::from("other.txt")));
+
+        let info = parse("e src/main.rs").unwrap();
+        assert_eq!(info.command_name, "editor::open_file");
+        assert_eq!(info.args.unwrap().str_args, Some(String::from("src/main.rs")));
+
+        assert!(parse("e").is_err());
+
+        let info = parse("wq other.txt").unwrap();
+        assert_eq!(info.command_name, "editor::save_and_quit");
+        assert_eq!(info.args.unwrap().str_args, Some(String::from("other.txt")));
+        assert_eq!(parse("x other.txt").unwrap().args.unwrap().str_args, Some(String::from("other.txt")));
+    }
+
+    #[test]
+    fn parses_line_numbers() {
+        let info = parse("123").unwrap();
+        assert_eq!(info.command_name, "buffer::goto_line");
+        let range = info.args.unwrap().range.unwrap();
+        assert_eq!(range.end, LineAddress::Number(123));
+
+        let info = parse("$").unwrap();
+        assert_eq!(info.args.unwrap().range.unwrap().end, LineAddress::Last);
+    }
+
+    #[test]
+    fn parses_ranges() {
+        let info = parse("%s/foo/bar/g").unwrap();
+        assert_eq!(info.command_name, "buffer::substitute");
+        let args = info.args.unwrap();
+        assert_eq!(args.range, LineRange::parse("%"));
+        assert_eq!(args.str_args, Some(String::from("/foo/bar/g")));
+
+        let info = parse("2,5d").unwrap();
+        assert_eq!(info.command_name, "buffer::delete_lines");
+        assert_eq!(info.args.unwrap().range, Some(LineRange {
+            start: LineAddress::Number(2),
+            end: LineAddress::Number(5),
+        }));
+
+        let info = parse("s/a/b/").unwrap();
+        assert_eq!(info.args.unwrap().range, None);
+
+        // `::` after the command is part of its argument
+        let info = parse("s/std::io/std::fs/").unwrap();
+        assert_eq!(info.command_name, "buffer::substitute");
+        assert_eq!(info.args.unwrap().str_args, Some(String::from("/std::io/std::fs/")));
+        let info = parse("e src/a::b.rs").unwrap();
+        assert_eq!(info.args.unwrap().str_args, Some(String::from("src/a::b.rs")));
+
+        let info = parse("'<,'>s/a/b/c").unwrap();
+        assert_eq!(info.args.unwrap().range, Some(LineRange {
+            start: LineAddress::SelectionStart,
+            end: LineAddress::SelectionEnd,
+        }));
+    }
+
+    #[test]
+    fn reports_unknown_commands() {
+        assert!(parse("frobnicate").is_err());
+        assert!(parse("1,2,3").is_err());
+    }
+}
@@ -2,11 +2,11 @@ use std::io;
 
 /// A source of Input for the Editor.
 ///
-/// This is used at startup, where the user can either open a file, or
+/// This is used at startup, where the user can either open files, or
 /// start Iota with data from stdin.
 pub enum Input {
-    /// A Filename
-    Filename(Option<String>),
+    /// The files to open, which may be none
+    Filenames(Vec<String>),
 
     /// The stdin reader
     Stdin(io::Stdin),
@@ -1,25 +1,25 @@
-use gapbuffer::GapBuffer;
+use rope::Rope;
 
 pub struct Lines<'a> {
-    pub buffer: &'a GapBuffer<u8>,
+    pub buffer: &'a Rope,
     pub tail: usize,
     pub head: usize,
 }
 
 impl<'a> Iterator for Lines<'a> {
-    type Item = Vec<u8>;
+    type Item = Vec<char>;
 
-    fn next(&mut self) -> Option<Vec<u8>> {
+    fn next(&mut self) -> Option<Vec<char>> {
         if self.tail == self.head { return None; }
         let old_tail = self.tail;
         //update tail to either the first char after the next \n or to self.head
-        self.tail = (old_tail..self.head).filter(|i| { *i + 1 == self.head
-                                                       || self.buffer[*i] == b'\n' })
-                                         .take(1)
-                                         .next()
-                                         .unwrap() + 1;
-        Some((old_tail..if self.tail == self.head { self.tail - 1 } else { self.tail })
-            .map( |i| self.buffer[i] ).collect())
+        let line = self.buffer.line_of(old_tail);
+        self.tail = match self.buffer.nth_newline(line) {
+            Some(idx) if idx + 1 < self.head => idx + 1,
+            _ => self.head,
+        };
+        let end = if self.tail == self.head { self.tail - 1 } else { self.tail };
+        Some(self.buffer.chars_at(old_tail).take(end - old_tail).collect())
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
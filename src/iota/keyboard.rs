@@ -1,94 +1,620 @@
 use std::char;
-use std::time::Duration;
+use std::cmp;
+use std::ops::BitOr;
+use std::time::{Duration, Instant};
 
 use rustbox::{RustBox, Event};
 
+/// A key pressed, along with any modifier keys held down with it.
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
-pub enum Key {
+pub struct Key {
+    pub code: KeyCode,
+    pub mods: Mods,
+}
+
+/// The keys themselves, without modifiers.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum KeyCode {
+    /// A printable char.  Shift is part of the char (`A` rather than `a`),
+    /// so is never among the modifiers.
+    Char(char),
     Tab,
     Enter,
     Esc,
     Backspace,
-    Right,
-    Left,
-    Down,
-    Up,
+    Insert,
     Delete,
     Home,
     End,
-    CtrlLeft,
-    CtrlRight,
+    PageUp,
+    PageDown,
+    Up,
+    Down,
+    Left,
+    Right,
+    /// A function key, `F(1)` to `F(12)`.
+    F(u8),
+}
 
-    Char(char),
-    Ctrl(char),
+/// The modifier keys held down with a key.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Mods {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+impl Mods {
+    pub const NONE: Mods = Mods { shift: false, alt: false, ctrl: false };
+    pub const SHIFT: Mods = Mods { shift: true, alt: false, ctrl: false };
+    pub const ALT: Mods = Mods { shift: false, alt: true, ctrl: false };
+    pub const CTRL: Mods = Mods { shift: false, alt: false, ctrl: true };
+
+    /// Whether no modifiers are held.
+    pub fn is_empty(&self) -> bool {
+        *self == Mods::NONE
+    }
+
+    /// The modifiers given by the parameter of an xterm escape sequence,
+    /// which is one more than a mask of shift (1), alt (2), ctrl (4) and meta
+    /// (8), which is taken as alt.
+    fn from_xterm(param: u32) -> Mods {
+        let mask = param.saturating_sub(1);
+        Mods {
+            shift: mask & 1 != 0,
+            alt: mask & 2 != 0 || mask & 8 != 0,
+            ctrl: mask & 4 != 0,
+        }
+    }
+}
+
+impl BitOr for Mods {
+    type Output = Mods;
+
+    fn bitor(self, other: Mods) -> Mods {
+        Mods {
+            shift: self.shift || other.shift,
+            alt: self.alt || other.alt,
+            ctrl: self.ctrl || other.ctrl,
+        }
+    }
 }
 
 impl Key {
-    pub fn from_special_code(code: u16) -> Option<Key> {
-        match code {
-            1     => Some(Key::Ctrl('a')),
-            2     => Some(Key::Ctrl('b')),
-            3     => Some(Key::Ctrl('c')),
-            4     => Some(Key::Ctrl('d')),
-            5     => Some(Key::Ctrl('e')),
-            6     => Some(Key::Ctrl('f')),
-            7     => Some(Key::Ctrl('g')),
-            8     => Some(Key::Ctrl('h')),
-            9     => Some(Key::Tab),
-            13    => Some(Key::Enter),
-            14    => Some(Key::Ctrl('n')),
-            16    => Some(Key::Ctrl('p')),
-            17    => Some(Key::Ctrl('q')),
-            18    => Some(Key::Ctrl('r')),
-            19    => Some(Key::Ctrl('s')),
-            24    => Some(Key::Ctrl('x')),
-            25    => Some(Key::Ctrl('y')),
-            26    => Some(Key::Ctrl('z')),
-            27    => Some(Key::Esc),
-            32    => Some(Key::Char(' ')),
-            127   => Some(Key::Backspace),
-            65514 => Some(Key::Right),
-            65515 => Some(Key::Left),
-            65516 => Some(Key::Down),
-            65517 => Some(Key::Up),
-            65520 => Some(Key::End),
-            65521 => Some(Key::Home),
-            65522 => Some(Key::Delete),
-            _     => None,
+    pub const TAB: Key = Key { code: KeyCode::Tab, mods: Mods::NONE };
+    pub const ENTER: Key = Key { code: KeyCode::Enter, mods: Mods::NONE };
+    pub const ESC: Key = Key { code: KeyCode::Esc, mods: Mods::NONE };
+    pub const BACKSPACE: Key = Key { code: KeyCode::Backspace, mods: Mods::NONE };
+    pub const INSERT: Key = Key { code: KeyCode::Insert, mods: Mods::NONE };
+    pub const DELETE: Key = Key { code: KeyCode::Delete, mods: Mods::NONE };
+    pub const HOME: Key = Key { code: KeyCode::Home, mods: Mods::NONE };
+    pub const END: Key = Key { code: KeyCode::End, mods: Mods::NONE };
+    pub const PAGE_UP: Key = Key { code: KeyCode::PageUp, mods: Mods::NONE };
+    pub const PAGE_DOWN: Key = Key { code: KeyCode::PageDown, mods: Mods::NONE };
+    pub const UP: Key = Key { code: KeyCode::Up, mods: Mods::NONE };
+    pub const DOWN: Key = Key { code: KeyCode::Down, mods: Mods::NONE };
+    pub const LEFT: Key = Key { code: KeyCode::Left, mods: Mods::NONE };
+    pub const RIGHT: Key = Key { code: KeyCode::Right, mods: Mods::NONE };
+
+    pub fn new(code: KeyCode, mods: Mods) -> Key {
+        Key { code: code, mods: mods }
+    }
+
+    /// A char typed on its own.
+    pub fn char(c: char) -> Key {
+        Key::new(KeyCode::Char(c), Mods::NONE)
+    }
+
+    /// A char typed with ctrl held down.
+    pub fn ctrl(c: char) -> Key {
+        Key::new(KeyCode::Char(c), Mods::CTRL)
+    }
+
+    /// A char typed with alt (meta) held down.
+    pub fn alt(c: char) -> Key {
+        Key::new(KeyCode::Char(c), Mods::ALT)
+    }
+
+    /// A function key, from `F1` to `F12`.
+    pub fn function(n: u8) -> Key {
+        Key::new(KeyCode::F(n), Mods::NONE)
+    }
+
+    /// The same key, with `mods` held down as well.
+    pub fn with(self, mods: Mods) -> Key {
+        let mut mods = self.mods | mods;
+        if let KeyCode::Char(_) = self.code {
+            mods.shift = false;
         }
+        Key::new(self.code, mods)
     }
 
-    pub fn from_chord(rb: &mut RustBox, start: u16) -> Option<Key> {
-        let chord = Key::get_chord(rb, start);
+    /// The char typed, if this is a char without ctrl or alt held down.
+    pub fn typed(&self) -> Option<char> {
+        match self.code {
+            KeyCode::Char(c) if self.mods.is_empty() => Some(c),
+            _ => None,
+        }
+    }
 
-        match chord.as_str() {
-            "\x1b[1;5C" => Some(Key::CtrlRight),
-            "\x1b[1;5D" => Some(Key::CtrlLeft),
-            _ => Key::from_special_code(start)
+    /// The key reported by termbox with code `code`, which covers the control
+    /// chars, and the keys whose escape sequences it knows.
+    pub fn from_special_code(code: u16) -> Option<Key> {
+        match code {
+            0     => Some(Key::ctrl(' ')),
+            // shared by ctrl-h and backspace on many terminals
+            8     => Some(Key::ctrl('h')),
+            9     => Some(Key::TAB),
+            13    => Some(Key::ENTER),
+            c if c >= 1 && c <= 26 => Some(Key::ctrl((b'a' + c as u8 - 1) as char)),
+            27    => Some(Key::ESC),
+            28    => Some(Key::ctrl('\\')),
+            29    => Some(Key::ctrl(']')),
+            30    => Some(Key::ctrl('^')),
+            31    => Some(Key::ctrl('_')),
+            32    => Some(Key::char(' ')),
+            127   => Some(Key::BACKSPACE),
+            65514 => Some(Key::RIGHT),
+            65515 => Some(Key::LEFT),
+            65516 => Some(Key::DOWN),
+            65517 => Some(Key::UP),
+            65518 => Some(Key::PAGE_DOWN),
+            65519 => Some(Key::PAGE_UP),
+            65520 => Some(Key::END),
+            65521 => Some(Key::HOME),
+            65522 => Some(Key::DELETE),
+            65523 => Some(Key::INSERT),
+            c if c >= 65524 => Some(Key::function((65535 - c + 1) as u8)),
+            _     => None,
         }
     }
 
-    pub fn get_chord(rb: &mut RustBox, start: u16) -> String {
-            // Copy any data waiting to a string
-            // There may be a cleaner way to do this?
-            let mut chord = char::from_u32(u32::from(start)).unwrap().to_string();
-            while let Ok(Event::KeyEventRaw(_, _, ch)) = rb.peek_event(Duration::from_secs(0), true) {
-                chord.push(char::from_u32(ch).unwrap())
+    /// The key sent as the sequence of chars `chord`, if it is one we know.
+    ///
+    /// This covers the sequences sent by xterm and the terminals copying it,
+    /// rxvt and the linux console: `CSI` (escape `[`) and `SS3` (escape `O`)
+    /// followed by the key and its modifiers.  An escape before any other key
+    /// is that key with alt held down, which is how terminals send it.
+    pub fn decode_chord(chord: &str) -> Option<Key> {
+        if !chord.starts_with('\x1b') {
+            return None;
+        }
+        let rest = &chord[1..];
+        if rest.is_empty() {
+            return Some(Key::ESC);
+        }
+        if rest.len() > 1 {
+            if rest.starts_with('[') {
+                return decode_csi(&rest[1..]);
+            }
+            if rest.starts_with('O') {
+                return decode_ss3(&rest[1..]);
             }
+        }
 
-            chord
+        let key = if rest.starts_with('\x1b') {
+            Key::decode_chord(rest)?
+        } else {
+            let mut chars = rest.chars();
+            let c = chars.next()?;
+            if chars.next().is_some() {
+                return None;
+            }
+            if c.is_control() {
+                Key::from_special_code(c as u16)?
+            } else {
+                Key::char(c)
+            }
+        };
+        Some(key.with(Mods::ALT))
     }
-    
-    pub fn from_event(rb: &mut RustBox, event: Event) -> Option<Key> {
-        match event {
+}
+
+/// Asks the terminal to mark the start and end of text pasted in, rather than
+/// sending it as though it were typed.
+pub const BRACKETED_PASTE_ON: &'static str = "\x1b[?2004h";
+/// Asks the terminal to stop marking pasted text.
+pub const BRACKETED_PASTE_OFF: &'static str = "\x1b[?2004l";
+
+/// Marks the start of text pasted in, when bracketed paste is enabled.
+pub const PASTE_START: &'static str = "\x1b[200~";
+/// Marks the end of text pasted in.
+pub const PASTE_END: &'static str = "\x1b[201~";
+
+/// How long to wait for the rest of a paste before giving up on its end.
+const PASTE_TIMEOUT_MS: u64 = 1000;
+/// The longest a paste can take, after which what arrived so far is the paste.
+const PASTE_LIMIT_MS: u64 = 10_000;
+
+/// What was typed at the terminal: a key, or text pasted in all at once.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum KeyEvent {
+    Key(Key),
+    /// Text pasted in between the bracketed paste markers, which is to be
+    /// inserted as it is rather than taken as keys.
+    Paste(String),
+}
+
+impl KeyEvent {
+    /// The input starting with `event`, reading the rest of any escape
+    /// sequence it starts, which is given up to `escape_timeout` to arrive.
+    pub fn from_event(rb: &mut RustBox, event: Event, escape_timeout: Duration) -> Option<KeyEvent> {
+        let key = match event {
             Event::KeyEventRaw(_, k, ch) => {
                 match k {
-                    0 => char::from_u32(ch).map(Key::Char),
-                    0x1b => Key::from_chord(rb, 0x1b),
+                    // ctrl-space comes as a char event with no char
+                    0 if ch == 0 => Key::from_special_code(0),
+                    0 => char::from_u32(ch).map(Key::char),
+                    0x1b => return KeyEvent::from_chord(rb, escape_timeout),
                     a => Key::from_special_code(a)
                 }
             },
             _ => None
+        };
+        key.map(KeyEvent::Key)
+    }
+
+    /// Read the rest of a sequence starting with an escape, waiting up to
+    /// `timeout` for it to begin arriving.
+    fn from_chord(rb: &mut RustBox, timeout: Duration) -> Option<KeyEvent> {
+        let mut chord = String::from("\x1b");
+        // the first of the rest may take a moment to arrive, after which the
+        // sequence is already waiting
+        let mut wait = timeout;
+        while !chord_complete(&chord) {
+            match rb.peek_event(wait, true) {
+                Ok(Event::KeyEventRaw(_, 0, ch)) => chord.push(char::from_u32(ch).unwrap_or('\0')),
+                Ok(Event::KeyEventRaw(_, k, _)) if k < 0x80 => chord.push(k as u8 as char),
+                // a key termbox has decoded already, after an escape which
+                // holds alt down for it
+                Ok(Event::KeyEventRaw(_, k, _)) if chord.len() == 1 => {
+                    return Key::from_special_code(k).map(|key| KeyEvent::Key(key.with(Mods::ALT)));
+                }
+                Ok(Event::KeyEventRaw(..)) => return None,
+                _ => break,
+            }
+            wait = Duration::from_secs(0);
+        }
+
+        if chord == PASTE_START {
+            return Some(KeyEvent::Paste(read_paste(rb)));
         }
+        Key::decode_chord(&chord).map(KeyEvent::Key)
+    }
+}
+
+/// Read pasted text up to the marker for the end of the paste, which arrives
+/// as chars and control codes rather than keys.
+///
+/// A paste whose end never arrives, or which is still arriving after
+/// `PASTE_LIMIT_MS`, is cut short at what has been read so far.
+fn read_paste(rb: &mut RustBox) -> String {
+    let mut raw = String::new();
+    let started = Instant::now();
+    let limit = Duration::from_millis(PASTE_LIMIT_MS);
+    while !raw.ends_with(PASTE_END) {
+        let elapsed = started.elapsed();
+        if elapsed >= limit {
+            break;
+        }
+        let wait = cmp::min(Duration::from_millis(PASTE_TIMEOUT_MS), limit - elapsed);
+        match rb.peek_event(wait, true) {
+            Ok(Event::KeyEventRaw(_, 0, ch)) => {
+                if let Some(c) = char::from_u32(ch) {
+                    if c != '\0' {
+                        raw.push(c);
+                    }
+                }
+            }
+            Ok(Event::KeyEventRaw(_, k, _)) if k < 0x80 => raw.push(k as u8 as char),
+            // nothing more arrived in time
+            Ok(Event::NoEvent) | Err(_) => break,
+            Ok(_) => {}
+        }
+    }
+    paste_text(&raw)
+}
+
+/// The text pasted in as `raw`, without the marker for its end, and with the
+/// carriage returns terminals send for line breaks made into newlines.
+pub fn paste_text(raw: &str) -> String {
+    let raw = if raw.ends_with(PASTE_END) { &raw[..raw.len() - PASTE_END.len()] } else { raw };
+    raw.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// Whether `chord`, which starts with an escape, is a whole key sequence, so
+/// that anything after it is a key of its own.
+fn chord_complete(chord: &str) -> bool {
+    let rest = &chord[1..];
+    let mut chars = rest.chars();
+    match chars.next() {
+        None => false,
+        Some('\x1b') => chord_complete(rest),
+        Some('[') | Some('O') => {
+            let seq = chars.as_str();
+            // the linux console's function keys have a second `[`
+            if rest.starts_with('[') && seq.starts_with('[') {
+                return seq.len() > 1;
+            }
+            // the parameters are digits and `;`, and the sequence ends with
+            // anything else
+            match seq.chars().last() {
+                Some(c) => c < '0' || c > '?',
+                None => false,
+            }
+        }
+        Some(_) => true,
+    }
+}
+
+/// Decode what follows `CSI` (escape `[`): numeric parameters separated by
+/// `;` and a final char, as in `1;5C` for ctrl-right or `5~` for page up.
+fn decode_csi(seq: &str) -> Option<Key> {
+    // the linux console sends F1 to F5 as `[[A` to `[[E`
+    if seq.starts_with('[') && seq.len() == 2 {
+        return match seq.as_bytes()[1] {
+            c @ b'A'..=b'E' => Some(Key::function(c - b'A' + 1)),
+            _ => None,
+        };
+    }
+
+    let last = seq.chars().last()?;
+    let params = parse_params(&seq[..seq.len() - last.len_utf8()])?;
+    let mods = params.get(1).map_or(Mods::NONE, |&param| Mods::from_xterm(param));
+
+    let code = match last {
+        'A' => KeyCode::Up,
+        'B' => KeyCode::Down,
+        'C' => KeyCode::Right,
+        'D' => KeyCode::Left,
+        'H' => KeyCode::Home,
+        'F' => KeyCode::End,
+        'P' => KeyCode::F(1),
+        'Q' => KeyCode::F(2),
+        'R' => KeyCode::F(3),
+        'S' => KeyCode::F(4),
+        'Z' => return Some(Key::new(KeyCode::Tab, Mods::SHIFT)),
+        // rxvt sends shifted arrows in lower case
+        'a'..='d' if params.is_empty() => return decode_csi(&last.to_ascii_uppercase().to_string())
+                                                .map(|key| key.with(Mods::SHIFT)),
+        // rxvt marks the modifiers of `~` keys by changing the final char
+        '~' | '$' | '^' | '@' => {
+            let code = tilde_key(*params.first()?)?;
+            let mods = match last {
+                '$' => Mods::SHIFT,
+                '^' => Mods::CTRL,
+                '@' => Mods::CTRL | Mods::SHIFT,
+                _ => mods,
+            };
+            return Some(Key::new(code, mods));
+        }
+        _ => return None,
+    };
+    Some(Key::new(code, mods))
+}
+
+/// Decode what follows `SS3` (escape `O`): an optional modifier parameter
+/// and a final char, as in `P` for F1 or `5A` for ctrl-up.
+fn decode_ss3(seq: &str) -> Option<Key> {
+    let last = seq.chars().last()?;
+    let params = parse_params(&seq[..seq.len() - last.len_utf8()])?;
+    let mods = params.last().map_or(Mods::NONE, |&param| Mods::from_xterm(param));
+
+    let code = match last {
+        'A' => KeyCode::Up,
+        'B' => KeyCode::Down,
+        'C' => KeyCode::Right,
+        'D' => KeyCode::Left,
+        'H' => KeyCode::Home,
+        'F' => KeyCode::End,
+        'P' => KeyCode::F(1),
+        'Q' => KeyCode::F(2),
+        'R' => KeyCode::F(3),
+        'S' => KeyCode::F(4),
+        // the keypad's enter
+        'M' => KeyCode::Enter,
+        // rxvt sends ctrl with the arrows in lower case
+        'a'..='d' if params.is_empty() => return decode_ss3(&last.to_ascii_uppercase().to_string())
+                                                .map(|key| key.with(Mods::CTRL)),
+        _ => return None,
+    };
+    Some(Key::new(code, mods))
+}
+
+/// The key sent as `CSI` followed by `number` and `~`.
+fn tilde_key(number: u32) -> Option<KeyCode> {
+    let code = match number {
+        1 | 7 => KeyCode::Home,
+        2 => KeyCode::Insert,
+        3 => KeyCode::Delete,
+        4 | 8 => KeyCode::End,
+        5 => KeyCode::PageUp,
+        6 => KeyCode::PageDown,
+        // the function keys skip 16 and 22
+        n @ 11..=15 => KeyCode::F((n - 10) as u8),
+        n @ 17..=21 => KeyCode::F((n - 11) as u8),
+        n @ 23..=24 => KeyCode::F((n - 12) as u8),
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// The numbers separated by `;` in `params`, with an empty one taken as 1.
+fn parse_params(params: &str) -> Option<Vec<u32>> {
+    if params.is_empty() {
+        return Some(Vec::new());
+    }
+    params.split(';').map(|param| {
+        if param.is_empty() { Some(1) } else { param.parse().ok() }
+    }).collect()
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use keyboard::{Key, KeyCode, Mods, PASTE_END, chord_complete, paste_text};
+
+    fn key(code: KeyCode, mods: Mods) -> Key {
+        Key::new(code, mods)
+    }
+
+    /// Escape sequences as sent by xterm, rxvt and the linux console, and the
+    /// keys they stand for.
+    fn sequences() -> Vec<(&'static str, Key)> {
+        let ctrl_shift = Mods::CTRL | Mods::SHIFT;
+        let ctrl_alt = Mods::CTRL | Mods::ALT;
+        vec![
+            // escape on its own, or before another key for alt
+            ("\x1b", Key::ESC),
+            ("\x1bx", Key::alt('x')),
+            ("\x1b<", Key::alt('<')),
+            ("\x1bW", Key::alt('W')),
+            ("\x1b[", Key::alt('[')),
+            ("\x1bO", Key::alt('O')),
+            ("\x1b\x18", key(KeyCode::Char('x'), ctrl_alt)),
+            ("\x1b\x7f", key(KeyCode::Backspace, Mods::ALT)),
+            ("\x1b\x1b", key(KeyCode::Esc, Mods::ALT)),
+            ("\x1b\x1b[A", key(KeyCode::Up, Mods::ALT)),
+
+            // xterm cursor keys, in normal and application mode
+            ("\x1b[A", Key::UP),
+            ("\x1b[B", Key::DOWN),
+            ("\x1b[C", Key::RIGHT),
+            ("\x1b[D", Key::LEFT),
+            ("\x1b[H", Key::HOME),
+            ("\x1b[F", Key::END),
+            ("\x1bOA", Key::UP),
+            ("\x1bOD", Key::LEFT),
+            ("\x1bOH", Key::HOME),
+            ("\x1bOF", Key::END),
+
+            // xterm modifiers
+            ("\x1b[1;2A", key(KeyCode::Up, Mods::SHIFT)),
+            ("\x1b[1;3B", key(KeyCode::Down, Mods::ALT)),
+            ("\x1b[1;5C", key(KeyCode::Right, Mods::CTRL)),
+            ("\x1b[1;5D", key(KeyCode::Left, Mods::CTRL)),
+            ("\x1b[1;6D", key(KeyCode::Left, ctrl_shift)),
+            ("\x1b[1;7H", key(KeyCode::Home, ctrl_alt)),
+            ("\x1b[1;2F", key(KeyCode::End, Mods::SHIFT)),
+            ("\x1b[1;9A", key(KeyCode::Up, Mods::ALT)),
+            ("\x1bO5A", key(KeyCode::Up, Mods::CTRL)),
+            ("\x1b[Z", key(KeyCode::Tab, Mods::SHIFT)),
+
+            // the editing keys
+            ("\x1b[1~", Key::HOME),
+            ("\x1b[2~", Key::INSERT),
+            ("\x1b[3~", Key::DELETE),
+            ("\x1b[4~", Key::END),
+            ("\x1b[5~", Key::PAGE_UP),
+            ("\x1b[6~", Key::PAGE_DOWN),
+            ("\x1b[7~", Key::HOME),
+            ("\x1b[8~", Key::END),
+            ("\x1b[3;5~", key(KeyCode::Delete, Mods::CTRL)),
+            ("\x1b[5;3~", key(KeyCode::PageUp, Mods::ALT)),
+            ("\x1b[2;2~", key(KeyCode::Insert, Mods::SHIFT)),
+
+            // function keys
+            ("\x1bOP", Key::function(1)),
+            ("\x1bOQ", Key::function(2)),
+            ("\x1bOR", Key::function(3)),
+            ("\x1bOS", Key::function(4)),
+            ("\x1b[1;5P", key(KeyCode::F(1), Mods::CTRL)),
+            ("\x1b[1;2S", key(KeyCode::F(4), Mods::SHIFT)),
+            ("\x1b[11~", Key::function(1)),
+            ("\x1b[15~", Key::function(5)),
+            ("\x1b[17~", Key::function(6)),
+            ("\x1b[21~", Key::function(10)),
+            ("\x1b[23~", Key::function(11)),
+            ("\x1b[24~", Key::function(12)),
+            ("\x1b[24;5~", key(KeyCode::F(12), Mods::CTRL)),
+            ("\x1b[15;2~", key(KeyCode::F(5), Mods::SHIFT)),
+
+            // rxvt
+            ("\x1b[a", key(KeyCode::Up, Mods::SHIFT)),
+            ("\x1b[d", key(KeyCode::Left, Mods::SHIFT)),
+            ("\x1bOa", key(KeyCode::Up, Mods::CTRL)),
+            ("\x1bOc", key(KeyCode::Right, Mods::CTRL)),
+            ("\x1b[2^", key(KeyCode::Insert, Mods::CTRL)),
+            ("\x1b[7$", key(KeyCode::Home, Mods::SHIFT)),
+            ("\x1b[3@", key(KeyCode::Delete, ctrl_shift)),
+            ("\x1b[13^", key(KeyCode::F(3), Mods::CTRL)),
+
+            // the linux console
+            ("\x1b[[A", Key::function(1)),
+            ("\x1b[[E", Key::function(5)),
+
+            // the keypad
+            ("\x1bOM", Key::ENTER),
+        ]
+    }
+
+    #[test]
+    fn decodes_escape_sequences() {
+        for (chord, key) in sequences() {
+            assert_eq!(Key::decode_chord(chord), Some(key), "decoding {:?}", chord);
+        }
+    }
+
+    #[test]
+    fn unknown_sequences_are_not_keys() {
+        for chord in &["\x1b[1;5X", "\x1b[99~", "\x1b[16~", "\x1b[1;xA", "\x1bOZ", "\x1bxy", "\x1b[[F", "x"] {
+            assert_eq!(Key::decode_chord(chord), None, "decoding {:?}", chord);
+        }
+    }
+
+    #[test]
+    fn decodes_control_codes() {
+        assert_eq!(Key::from_special_code(0), Some(Key::ctrl(' ')));
+        assert_eq!(Key::from_special_code(3), Some(Key::ctrl('c')));
+        assert_eq!(Key::from_special_code(8), Some(Key::ctrl('h')));
+        assert_eq!(Key::from_special_code(9), Some(Key::TAB));
+        assert_eq!(Key::from_special_code(10), Some(Key::ctrl('j')));
+        assert_eq!(Key::from_special_code(13), Some(Key::ENTER));
+        assert_eq!(Key::from_special_code(15), Some(Key::ctrl('o')));
+        assert_eq!(Key::from_special_code(21), Some(Key::ctrl('u')));
+        assert_eq!(Key::from_special_code(31), Some(Key::ctrl('_')));
+        assert_eq!(Key::from_special_code(127), Some(Key::BACKSPACE));
+    }
+
+    #[test]
+    fn decodes_termbox_keys() {
+        assert_eq!(Key::from_special_code(65535), Some(Key::function(1)));
+        assert_eq!(Key::from_special_code(65524), Some(Key::function(12)));
+        assert_eq!(Key::from_special_code(65523), Some(Key::INSERT));
+        assert_eq!(Key::from_special_code(65519), Some(Key::PAGE_UP));
+        assert_eq!(Key::from_special_code(65518), Some(Key::PAGE_DOWN));
+        assert_eq!(Key::from_special_code(65514), Some(Key::RIGHT));
+    }
+
+    #[test]
+    fn shift_is_part_of_chars() {
+        assert_eq!(Key::char('a').with(Mods::SHIFT), Key::char('a'));
+        assert_eq!(Key::UP.with(Mods::SHIFT), key(KeyCode::Up, Mods::SHIFT));
+        assert_eq!(Key::ctrl('x').typed(), None);
+        assert_eq!(Key::char('x').typed(), Some('x'));
+    }
+
+    #[test]
+    fn sequences_end_where_the_next_key_starts() {
+        for chord in &["\x1bx", "\x1b\x18", "\x1b[A", "\x1b[1;5C", "\x1b[5~", "\x1b[2^", "\x1b[7$",
+                       "\x1bOP", "\x1bO5A", "\x1b[[A", "\x1b\x1b[A", "\x1b[200~"] {
+            assert!(chord_complete(chord), "{:?} should be complete", chord);
+            for end in 1..chord.len() - 1 {
+                assert!(!chord_complete(&chord[..end]), "{:?} is complete too soon", &chord[..end]);
+            }
+        }
+        // these may yet be the start of a longer sequence
+        for chord in &["\x1b", "\x1b\x1b", "\x1b[", "\x1bO", "\x1b[[", "\x1b[1;"] {
+            assert!(!chord_complete(chord), "{:?} should not be complete", chord);
+        }
+    }
+
+    #[test]
+    fn pasted_text_keeps_its_lines() {
+        let raw = format!("fn main() {{\r\tx\r\n}}{}", PASTE_END);
+        assert_eq!(paste_text(&raw), "fn main() {\n\tx\n}");
+        // a paste cut short keeps what arrived
+        assert_eq!(paste_text("dd"), "dd");
     }
 }
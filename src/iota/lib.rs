@@ -10,26 +10,33 @@
 #![warn(missing_docs)]
 
 extern crate rustbox;
-extern crate gapbuffer;
 extern crate tempdir;
 extern crate regex;
 extern crate unicode_width;
+#[cfg(unix)] extern crate libc;
 #[macro_use] extern crate lazy_static;
 
 pub use editor::Editor;
 pub use input::Input;
 pub use modes::{StandardMode, NormalMode, EmacsMode, Mode};
+pub use rope::Rope;
 
 mod input;
 mod utils;
 mod buffer;
+mod rope;
 mod editor;
 mod keyboard;
 mod keymap;
 mod view;
 mod log;
+mod undofile;
+mod save;
 mod modes;
 mod overlay;
+mod registers;
 mod command;
+mod ex;
+mod search;
 mod textobject;
 mod iterators;
@@ -1,28 +1,42 @@
 //! Primitive command log, currently used for undo / redo.
 //! This is a deliberately unoptimized representation, for simplicity.  It is by no means final.
+//!
+//! The history is kept as a tree rather than a stack: making a change after an undo starts a new
+//! branch instead of throwing away the undone changes, so every state the text has been in can
+//! still be reached.
 
+use std::io::{self, Write};
 use std::mem;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Represents a modification of data.
+#[derive(Clone)]
 pub enum Change {
     ///Character insertion.
-    Insert(usize, u8),
+    Insert(usize, char),
     ///Character removal.
-    Remove(usize, u8),
+    Remove(usize, char),
+    ///Insertion of a string, starting at the index.
+    InsertRange(usize, String),
+    ///Removal of a span starting at the index, with the text that was removed.
+    RemoveRange(usize, String),
 }
 
 impl Change {
     /// Reverses a change, consuming it in the process
     pub fn reverse(self) -> Change {
         match self {
-            Change::Insert(usize, u8) => Change::Remove(usize, u8),
-            Change::Remove(usize, u8) => Change::Insert(usize, u8),
+            Change::Insert(idx, ch) => Change::Remove(idx, ch),
+            Change::Remove(idx, ch) => Change::Insert(idx, ch),
+            Change::InsertRange(idx, text) => Change::RemoveRange(idx, text),
+            Change::RemoveRange(idx, text) => Change::InsertRange(idx, text),
         }
     }
 }
 
 /// Log entry
 /// Entries may only be played linearly--they don't make sense out of order.
+#[derive(Clone)]
 pub struct LogEntry {
     /// The initial point position associated with this log entry.
     ///
@@ -50,7 +64,7 @@ impl LogEntry {
 /// A set of `Change`s that should be treated atomically.
 ///
 /// This transaction always has an associated entry log.  When the transaction is dropped, the
-/// entries are committed.
+/// entries are committed, or added to the log's open transaction if there is one.
 pub struct Transaction<'a> {
     /// Currently, only one transaction may be open at a time.
     entries: &'a mut Log,
@@ -74,34 +88,129 @@ impl<'a> Drop for Transaction<'a> {
     fn drop(&mut self) {
         // Check to see if there were any changes, and if not return early.
         if self.entry.changes.is_empty() { return }
+        let changes = mem::replace(&mut self.entry.changes, Vec::new());
+        // If a longer transaction is open, the changes become part of it.
+        if let Some(ref mut open) = self.entries.open {
+            if open.changes.is_empty() {
+                open.init_point = self.entry.init_point;
+            }
+            open.changes.extend(changes);
+            open.end_point = self.entry.end_point;
+            return;
+        }
         // Create the new log entry
         let entry = LogEntry {
-            changes: mem::replace(&mut self.entry.changes, Vec::new()),
+            changes: changes,
             .. self.entry
         };
         // Commit the transaction.
-        self.entries.undo.push(entry);
-        // Clear the redo entries now that the transaction has been committed.
-        self.entries.redo.clear();
+        self.entries.push(entry);
     }
 }
 
-/// Log entries structure.  Just two stacks.
+/// A state of the text in the undo tree.
+struct UndoNode {
+    /// The state this one was reached from.  `None` for the initial state.
+    parent: Option<usize>,
+    /// The changes that take the parent state to this one.
+    entry: Option<LogEntry>,
+    /// States reached from this one, oldest first.
+    children: Vec<usize>,
+    /// The child a redo moves to.  This is the most recently visited one.
+    redo_child: usize,
+    /// When this state was reached.
+    time: SystemTime,
+}
+
+impl UndoNode {
+    fn new(parent: Option<usize>, entry: Option<LogEntry>) -> UndoNode {
+        UndoNode {
+            parent: parent,
+            entry: entry,
+            children: Vec::new(),
+            redo_child: 0,
+            time: SystemTime::now(),
+        }
+    }
+}
+
+/// A summary of one state in the undo tree, used to display the tree.
+pub struct UndoState {
+    /// Identifies the state, for use with `Log::goto`.
+    pub id: usize,
+    /// How many branches away from the main line of history this state is.
+    pub branch_depth: usize,
+    /// When the state was reached.
+    pub time: SystemTime,
+    /// Number of changes from the previous state.
+    pub changes: usize,
+    /// Whether this is the current state of the text.
+    pub current: bool,
+}
+
+/// Log entries structure.  A tree of states, with the changes needed to move between them.
 pub struct Log {
-    /// Undo log entries--LIFO stack.
-    undo: Vec<LogEntry>,
-    /// Redo log entries--LIFO stack.  Cleared after a new change (other than an undo or redo)
-    /// is committed.
-    redo: Vec<LogEntry>,
+    /// Every state the text has been in, in the order they were reached.  The first is the
+    /// initial state.
+    nodes: Vec<UndoNode>,
+    /// The current state of the text.
+    current: usize,
+    /// Entry collecting the changes made while a transaction is open.
+    open: Option<LogEntry>,
+    /// How many times `open_transaction` has been called without a matching
+    /// `commit_transaction`.
+    depth: usize,
 }
 
 impl Log {
     /// Set up log entries.  They are initially empty.
     pub fn new() -> Log {
         Log {
-            undo: Vec::new(),
-            redo: Vec::new(),
+            nodes: vec![UndoNode::new(None, None)],
+            current: 0,
+            open: None,
+            depth: 0,
+        }
+    }
+    /// Open a transaction which groups every change logged until it is committed into a single
+    /// `LogEntry`, so that it is undone and redone in one step.
+    ///
+    /// Transactions may be nested, in which case nothing is committed until the outermost one
+    /// is.
+    pub fn open_transaction(&mut self) {
+        if self.depth == 0 {
+            self.open = Some(LogEntry {
+                init_point: 0,
+                end_point: 0,
+                changes: Vec::new(),
+            });
         }
+        self.depth += 1;
+    }
+
+    /// Commit the most recently opened transaction.
+    ///
+    /// Does nothing if there is no open transaction.
+    pub fn commit_transaction(&mut self) {
+        if self.depth == 0 { return }
+        self.depth -= 1;
+        if self.depth == 0 {
+            if let Some(entry) = self.open.take() {
+                self.push(entry);
+            }
+        }
+    }
+
+    /// Add a finished entry to the tree, as a new state following the current one.
+    fn push(&mut self, entry: LogEntry) {
+        if entry.changes.is_empty() { return }
+        let id = self.nodes.len();
+        self.nodes.push(UndoNode::new(Some(self.current), Some(entry)));
+
+        let parent = &mut self.nodes[self.current];
+        parent.children.push(id);
+        parent.redo_child = parent.children.len() - 1;
+        self.current = id;
     }
 
     /// Start a new transaction.
@@ -119,30 +228,372 @@ impl Log {
         }
     }
 
-    /// This reverses the most recent change on the undo stack, places the new change on the redo
-    /// stack, and then returns a reference to it.  It is the caller's responsibility to actually
-    /// perform the change.
-    pub fn undo(&mut self) -> Option<&LogEntry> {
-        match self.undo.pop() {
-            Some(change) => {
-                let last = self.redo.len();
-                self.redo.push(change.reverse());
-                Some(&self.redo[last])
-            },
-            None => None
-        }
-    }
-    /// This reverses the most recent change on the redo stack, places the new change on the undo
-    /// stack, and then returns a reference to it.  It is the caller's responsibility to actually
-    /// perform the change.
-    pub fn redo(&mut self) -> Option<&LogEntry> {
-        match self.redo.pop() {
-            Some(change) => {
-                let last = self.undo.len();
-                self.undo.push(change.reverse());
-                Some(&self.undo[last])
-            },
-            None => None
+    /// This moves to the previous state and returns the reversed entry that leads there.  It is
+    /// the caller's responsibility to actually perform the change.
+    pub fn undo(&mut self) -> Option<LogEntry> {
+        let parent = match self.nodes[self.current].parent {
+            Some(parent) => parent,
+            None => return None,
+        };
+        let entry = self.nodes[self.current].entry.clone().map(LogEntry::reverse);
+
+        // redo should come back to the state we are leaving
+        let current = self.current;
+        if let Some(idx) = self.nodes[parent].children.iter().position(|&c| c == current) {
+            self.nodes[parent].redo_child = idx;
+        }
+        self.current = parent;
+        entry
+    }
+
+    /// This moves to the most recently visited state following the current one, and returns the
+    /// entry that leads there.  It is the caller's responsibility to actually perform the change.
+    pub fn redo(&mut self) -> Option<LogEntry> {
+        let child = {
+            let node = &self.nodes[self.current];
+            match node.children.get(node.redo_child) {
+                Some(&child) => child,
+                None => return None,
+            }
+        };
+        self.current = child;
+        self.nodes[child].entry.clone()
+    }
+
+    /// Move to the state identified by `target`, returning the entries to perform (in order) to
+    /// get there.
+    pub fn goto(&mut self, target: usize) -> Vec<LogEntry> {
+        let mut entries = Vec::new();
+        if target >= self.nodes.len() {
+            return entries;
+        }
+
+        // the states from the initial one down to the target
+        let mut path = vec![target];
+        while let Some(parent) = self.nodes[path[path.len() - 1]].parent {
+            path.push(parent);
+        }
+        path.reverse();
+
+        // undo back to a state on the path, then redo down it
+        while !path.contains(&self.current) {
+            match self.undo() {
+                Some(entry) => entries.push(entry),
+                None => break,
+            }
+        }
+        let start = path.iter().position(|&n| n == self.current).unwrap_or(0);
+        for &node in &path[start + 1..] {
+            let current = self.current;
+            if let Some(idx) = self.nodes[current].children.iter().position(|&c| c == node) {
+                self.nodes[current].redo_child = idx;
+            }
+            if let Some(entry) = self.redo() {
+                entries.push(entry);
+            }
+        }
+        entries
+    }
+
+    /// Move to the next (or previous) branch at the current state: the state reached by the
+    /// change made after, or before, the one which led to the current state.
+    pub fn goto_branch(&mut self, forward: bool) -> Vec<LogEntry> {
+        let target = {
+            let parent = match self.nodes[self.current].parent {
+                Some(parent) => &self.nodes[parent],
+                None => return Vec::new(),
+            };
+            let siblings = &parent.children;
+            let len = siblings.len();
+            let pos = siblings.iter().position(|&c| c == self.current).unwrap_or(0);
+            if forward {
+                siblings[(pos + 1) % len]
+            } else {
+                siblings[(pos + len - 1) % len]
+            }
+        };
+        self.goto(target)
+    }
+
+    /// Move to the state the text was in `duration` before the current state was reached.
+    pub fn goto_earlier(&mut self, duration: Duration) -> Vec<LogEntry> {
+        let now = self.nodes[self.current].time;
+        let target = self.nodes.iter().rposition(|node| node.time + duration <= now).unwrap_or(0);
+        self.goto(target)
+    }
+
+    /// Move to the state the text was in `duration` after the current state was reached.
+    pub fn goto_later(&mut self, duration: Duration) -> Vec<LogEntry> {
+        let then = self.nodes[self.current].time + duration;
+        let target = self.nodes.iter().rposition(|node| node.time <= then).unwrap_or(0);
+        if target < self.current {
+            return Vec::new();
+        }
+        self.goto(target)
+    }
+
+    /// A summary of every state in the tree, parents before children and older branches first.
+    pub fn states(&self) -> Vec<UndoState> {
+        let mut states = Vec::with_capacity(self.nodes.len());
+        let mut stack = vec![(0, 0)];
+
+        while let Some((id, branch_depth)) = stack.pop() {
+            let node = &self.nodes[id];
+            states.push(UndoState {
+                id: id,
+                branch_depth: branch_depth,
+                time: node.time,
+                changes: node.entry.as_ref().map_or(0, |entry| entry.changes.len()),
+                current: id == self.current,
+            });
+            // the first child carries on the current branch, later ones start new branches
+            for (i, &child) in node.children.iter().enumerate().rev() {
+                stack.push((child, if i == 0 { branch_depth } else { branch_depth + 1 }));
+            }
+        }
+        states
+    }
+
+    /// Write the tree out in a line based text format which `Log::read_from` understands.
+    ///
+    /// Each state is a `node` line giving its parent, the time it was reached and the point
+    /// positions of its entry, followed by one line per change.  Single characters are written
+    /// as code points, and strings as hex encoded UTF-8 so they can't break up the lines.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "current {}", self.current)?;
+        for node in &self.nodes {
+            let parent = node.parent.map_or("-".to_string(), |p| p.to_string());
+            let secs = node.time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            match node.entry {
+                Some(ref entry) => {
+                    writeln!(w, "node {} {} {} {}", parent, secs, entry.init_point, entry.end_point)?;
+                    for change in &entry.changes {
+                        match *change {
+                            Change::Insert(idx, ch) => writeln!(w, "+ {} {}", idx, ch as u32)?,
+                            Change::Remove(idx, ch) => writeln!(w, "- {} {}", idx, ch as u32)?,
+                            Change::InsertRange(idx, ref text) => writeln!(w, "+s {} {}", idx, to_hex(text))?,
+                            Change::RemoveRange(idx, ref text) => writeln!(w, "-s {} {}", idx, to_hex(text))?,
+                        }
+                    }
+                }
+                None => writeln!(w, "node {} {}", parent, secs)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Rebuild a tree from lines written by `Log::write_to`.
+    ///
+    /// Returns `None` if the lines are malformed.
+    pub fn read_from<I: Iterator<Item=String>>(lines: I) -> Option<Log> {
+        let mut current = None;
+        let mut nodes: Vec<UndoNode> = Vec::new();
+
+        for line in lines {
+            let fields: Vec<&str> = line.split(' ').collect();
+            match (fields[0], fields.len()) {
+                ("current", 2) => current = fields[1].parse().ok(),
+                ("node", 3) | ("node", 5) => {
+                    let id = nodes.len();
+                    let parent = match fields[1] {
+                        "-" => None,
+                        p => match p.parse() {
+                            Ok(p) if p < id => Some(p),
+                            _ => return None,
+                        },
+                    };
+                    // only the initial state has no parent, and it has no entry
+                    if parent.is_none() != (id == 0) || parent.is_none() != (fields.len() == 3) {
+                        return None;
+                    }
+                    let secs = fields[2].parse().ok()?;
+                    let entry = if fields.len() == 5 {
+                        Some(LogEntry {
+                            init_point: fields[3].parse().ok()?,
+                            end_point: fields[4].parse().ok()?,
+                            changes: Vec::new(),
+                        })
+                    } else {
+                        None
+                    };
+                    let mut node = UndoNode::new(parent, entry);
+                    node.time = UNIX_EPOCH + Duration::from_secs(secs);
+                    if let Some(p) = parent {
+                        let parent_node = &mut nodes[p];
+                        parent_node.children.push(id);
+                        parent_node.redo_child = parent_node.children.len() - 1;
+                    }
+                    nodes.push(node);
+                }
+                (sign, 3) if sign == "+" || sign == "-" => {
+                    let idx = fields[1].parse().ok()?;
+                    let ch = fields[2].parse().ok().and_then(::std::char::from_u32)?;
+                    let change = if sign == "+" { Change::Insert(idx, ch) } else { Change::Remove(idx, ch) };
+                    match nodes.last_mut().and_then(|node| node.entry.as_mut()) {
+                        Some(entry) => entry.changes.push(change),
+                        None => return None,
+                    }
+                }
+                (sign, 3) if sign == "+s" || sign == "-s" => {
+                    let idx = fields[1].parse().ok()?;
+                    let text = from_hex(fields[2])?;
+                    let change = if sign == "+s" { Change::InsertRange(idx, text) } else { Change::RemoveRange(idx, text) };
+                    match nodes.last_mut().and_then(|node| node.entry.as_mut()) {
+                        Some(entry) => entry.changes.push(change),
+                        None => return None,
+                    }
+                }
+                _ => return None,
+            }
+        }
+
+        match current {
+            Some(current) if current < nodes.len() => Some(Log {
+                nodes: nodes,
+                current: current,
+                open: None,
+                depth: 0,
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn to_hex(text: &str) -> String {
+    text.bytes().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<String> {
+    if hex.len() % 2 != 0 { return None }
+    let bytes: Option<Vec<u8>> = (0..hex.len() / 2)
+        .map(|i| hex.get(i * 2..i * 2 + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect();
+    bytes.and_then(|bytes| String::from_utf8(bytes).ok())
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::time::Duration;
+
+    use log::{Log, Change};
+
+    fn log_insert(log: &mut Log, idx: usize, ch: char) {
+        let mut transaction = log.start(idx);
+        transaction.log(Change::Insert(idx, ch), idx);
+    }
+
+    #[test]
+    fn undo_then_change_keeps_old_branch() {
+        let mut log = Log::new();
+        log_insert(&mut log, 0, 'a');
+        log_insert(&mut log, 1, 'b');
+        assert!(log.undo().is_some());
+        log_insert(&mut log, 1, 'c');
+
+        assert_eq!(log.states().len(), 4);
+        // back on to the 'b' branch
+        let entries = log.goto_branch(false);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(log.current, 2);
+        // and forward again to 'c'
+        log.goto_branch(true);
+        assert_eq!(log.current, 3);
+    }
+
+    #[test]
+    fn redo_follows_last_visited_branch() {
+        let mut log = Log::new();
+        log_insert(&mut log, 0, 'a');
+        log.undo();
+        log_insert(&mut log, 0, 'b');
+        log.goto(1);
+        log.undo();
+
+        log.redo();
+        assert_eq!(log.current, 1);
+    }
+
+    #[test]
+    fn goto_earlier_and_later() {
+        let mut log = Log::new();
+        log_insert(&mut log, 0, 'a');
+        log_insert(&mut log, 1, 'b');
+        log_insert(&mut log, 2, 'c');
+        let now = log.nodes[3].time;
+        log.nodes[0].time = now - Duration::from_secs(60 * 30);
+        log.nodes[1].time = now - Duration::from_secs(60 * 20);
+        log.nodes[2].time = now - Duration::from_secs(60 * 5);
+
+        assert_eq!(log.goto_earlier(Duration::from_secs(60 * 10)).len(), 2);
+        assert_eq!(log.current, 1);
+
+        assert_eq!(log.goto_later(Duration::from_secs(60 * 15)).len(), 1);
+        assert_eq!(log.current, 2);
+    }
+
+    #[test]
+    fn states_are_indented_by_branch() {
+        let mut log = Log::new();
+        log_insert(&mut log, 0, 'a');
+        log.undo();
+        log_insert(&mut log, 0, 'b');
+
+        let states = log.states();
+        let depths: Vec<(usize, usize)> = states.iter().map(|s| (s.id, s.branch_depth)).collect();
+        assert_eq!(depths, vec![(0, 0), (1, 0), (2, 1)]);
+        assert!(states[2].current);
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let mut log = Log::new();
+        log_insert(&mut log, 0, 'a');
+        log.undo();
+        log_insert(&mut log, 0, 'é');
+
+        let mut out = Vec::new();
+        log.write_to(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let mut read = Log::read_from(text.lines().map(String::from)).unwrap();
+
+        assert_eq!(read.current, 2);
+        assert_eq!(read.nodes[0].children, vec![1, 2]);
+        let entry = read.undo().unwrap();
+        match entry.changes[0] {
+            Change::Remove(0, 'é') => {}
+            _ => panic!("expected the insertion to be undone"),
+        }
+        assert_eq!(read.goto(1).len(), 1);
+    }
+
+    #[test]
+    fn read_rejects_malformed_history() {
+        let lines = vec!["current 1", "node - 0", "+ 0 97"];
+        assert!(Log::read_from(lines.into_iter().map(String::from)).is_none());
+    }
+
+    #[test]
+    fn write_and_read_back_ranges() {
+        let mut log = Log::new();
+        {
+            let mut transaction = log.start(0);
+            transaction.log(Change::InsertRange(0, "two\nlines ✓".to_string()), 12);
+            transaction.log(Change::RemoveRange(3, "\n".to_string()), 3);
+        }
+
+        let mut out = Vec::new();
+        log.write_to(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let mut read = Log::read_from(text.lines().map(String::from)).unwrap();
+
+        let entry = read.undo().unwrap();
+        match (&entry.changes[0], &entry.changes[1]) {
+            (&Change::InsertRange(3, ref a), &Change::RemoveRange(0, ref b)) => {
+                assert_eq!(a, "\n");
+                assert_eq!(b, "two\nlines ✓");
+            }
+            _ => panic!("expected the ranges to be reversed"),
         }
     }
 }
@@ -1,17 +1,21 @@
-use keyboard::Key;
+use keyboard::{Key, Mods};
 use keymap::{KeyMap, KeyMapState, CommandInfo};
 use command::{BuilderEvent, BuilderArgs };
 use textobject::{Offset, Anchor, Kind};
 use buffer::Mark;
+use command::{LineAddress, LineRange};
+use overlay::OverlayType;
 
 use super::Mode;
 
 
 /// Emacs mode uses Emacs-like keybindings.
 ///
+/// Meta keys can also be typed as escape followed by the key.
 pub struct EmacsMode {
     keymap: KeyMap,
     match_in_progress: bool,
+    meta_pending: bool,
 }
 
 impl EmacsMode {
@@ -21,6 +25,7 @@ impl EmacsMode {
         EmacsMode {
             keymap: EmacsMode::key_defaults(),
             match_in_progress: false,
+            meta_pending: false,
         }
     }
 
@@ -29,15 +34,36 @@ impl EmacsMode {
         let mut keymap = KeyMap::new();
 
         // Editor Commands
+        keymap.bind_key(
+            Key::ctrl('s'),
+            CommandInfo {
+                command_name: String::from("editor::set_overlay"),
+                args: Some(BuilderArgs::new().with_overlay(OverlayType::Search)),
+            }
+        );
+        keymap.bind_key(
+            Key::ctrl('r'),
+            CommandInfo {
+                command_name: String::from("editor::set_overlay"),
+                args: Some(BuilderArgs::new().with_overlay(OverlayType::SearchBackward)),
+            }
+        );
+        keymap.bind_key(
+            Key::alt('x'),
+            CommandInfo {
+                command_name: String::from("editor::set_overlay"),
+                args: Some(BuilderArgs::new().with_overlay(OverlayType::CommandPrompt)),
+            }
+        );
         keymap.bind_keys(
-            &[Key::Ctrl('x'), Key::Ctrl('c')],
+            &[Key::ctrl('x'), Key::ctrl('c')],
             CommandInfo {
                 command_name: String::from("editor::quit"),
                 args: None,
             }
         );
         keymap.bind_keys(
-            &[Key::Ctrl('x'), Key::Ctrl('s')],
+            &[Key::ctrl('x'), Key::ctrl('s')],
             CommandInfo {
                 command_name: String::from("editor::save_buffer"),
                 args: None,
@@ -46,7 +72,7 @@ impl EmacsMode {
 
         // Cursor movement
         keymap.bind_key(
-            Key::Up,
+            Key::UP,
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Line(Anchor::Same))
@@ -54,7 +80,7 @@ impl EmacsMode {
             }
         );
         keymap.bind_key(
-            Key::Down,
+            Key::DOWN,
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Line(Anchor::Same))
@@ -62,7 +88,7 @@ impl EmacsMode {
             }
         );
         keymap.bind_key(
-            Key::Left,
+            Key::LEFT,
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Char)
@@ -70,7 +96,7 @@ impl EmacsMode {
             }
         );
         keymap.bind_key(
-            Key::Right,
+            Key::RIGHT,
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Char)
@@ -78,7 +104,7 @@ impl EmacsMode {
             }
         );
         keymap.bind_key(
-            Key::Ctrl('p'),
+            Key::ctrl('p'),
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Line(Anchor::Same))
@@ -86,7 +112,7 @@ impl EmacsMode {
             }
         );
         keymap.bind_key(
-            Key::Ctrl('n'),
+            Key::ctrl('n'),
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Line(Anchor::Same))
@@ -94,7 +120,7 @@ impl EmacsMode {
             }
         );
         keymap.bind_key(
-            Key::Ctrl('b'),
+            Key::ctrl('b'),
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Char)
@@ -102,7 +128,7 @@ impl EmacsMode {
             }
         );
         keymap.bind_key(
-            Key::Ctrl('f'),
+            Key::ctrl('f'),
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Char)
@@ -111,7 +137,44 @@ impl EmacsMode {
         );
 
         keymap.bind_key(
-            Key::Ctrl('e'),
+            Key::alt('f'),
+            CommandInfo {
+                command_name: String::from("buffer::move_cursor"),
+                args: Some(BuilderArgs::new().with_kind(Kind::Word(Anchor::Start))
+                                             .with_offset(Offset::Forward(1, Mark::Cursor(0))))
+            }
+        );
+        keymap.bind_key(
+            Key::alt('b'),
+            CommandInfo {
+                command_name: String::from("buffer::move_cursor"),
+                args: Some(BuilderArgs::new().with_kind(Kind::Word(Anchor::Start))
+                                             .with_offset(Offset::Backward(1, Mark::Cursor(0))))
+            }
+        );
+        keymap.bind_key(
+            Key::alt('<'),
+            CommandInfo {
+                command_name: String::from("buffer::goto_line"),
+                args: Some(BuilderArgs::new().with_range(LineRange {
+                    start: LineAddress::Number(1),
+                    end: LineAddress::Number(1),
+                }))
+            }
+        );
+        keymap.bind_key(
+            Key::alt('>'),
+            CommandInfo {
+                command_name: String::from("buffer::goto_line"),
+                args: Some(BuilderArgs::new().with_range(LineRange {
+                    start: LineAddress::Last,
+                    end: LineAddress::Last,
+                }))
+            }
+        );
+
+        keymap.bind_key(
+            Key::ctrl('e'),
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Line(Anchor::End))
@@ -119,7 +182,7 @@ impl EmacsMode {
             }
         );
         keymap.bind_key(
-            Key::Ctrl('a'),
+            Key::ctrl('a'),
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Line(Anchor::End))
@@ -129,21 +192,21 @@ impl EmacsMode {
 
         // Editing
         keymap.bind_key(
-            Key::Tab,
+            Key::TAB,
             CommandInfo {
                 command_name: String::from("buffer::insert_tab"),
                 args: None,
             }
         );
         keymap.bind_key(
-            Key::Enter,
+            Key::ENTER,
             CommandInfo {
                 command_name: String::from("buffer::insert_char"),
                 args: Some(BuilderArgs::new().with_char_arg('\n')),
             }
         );
         keymap.bind_key(
-            Key::Backspace,
+            Key::BACKSPACE,
             CommandInfo {
                 command_name: String::from("buffer::delete_char"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Char)
@@ -151,7 +214,7 @@ impl EmacsMode {
             }
         );
         keymap.bind_key(
-            Key::Delete,
+            Key::DELETE,
             CommandInfo {
                 command_name: String::from("buffer::delete_char"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Char)
@@ -159,7 +222,7 @@ impl EmacsMode {
             }
         );
         keymap.bind_key(
-            Key::Ctrl('h'),
+            Key::ctrl('h'),
             CommandInfo {
                 command_name: String::from("buffer::delete_char"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Char)
@@ -167,7 +230,7 @@ impl EmacsMode {
             }
         );
         keymap.bind_key(
-            Key::Ctrl('d'),
+            Key::ctrl('d'),
             CommandInfo {
                 command_name: String::from("buffer::delete_char"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Char)
@@ -175,14 +238,108 @@ impl EmacsMode {
             }
         );
         keymap.bind_keys(
-            &[Key::Ctrl('x'), Key::Ctrl('f')],
+            &[Key::ctrl('x'), Key::char('u')],
+            CommandInfo {
+                command_name: String::from("editor::undo_tree"),
+                args: None,
+            }
+        );
+        keymap.bind_keys(
+            &[Key::ctrl('x'), Key::ctrl('f')],
             CommandInfo {
                 command_name: String::from("editor::find_file"),
                 args: None,
             }
         );
+        keymap.bind_keys(
+            &[Key::ctrl('x'), Key::char('b')],
+            CommandInfo {
+                command_name: String::from("buffer::list"),
+                args: None,
+            }
+        );
+        keymap.bind_keys(
+            &[Key::ctrl('x'), Key::char('k')],
+            CommandInfo {
+                command_name: String::from("buffer::close"),
+                args: None,
+            }
+        );
+        keymap.bind_keys(
+            &[Key::ctrl('x'), Key::RIGHT],
+            CommandInfo {
+                command_name: String::from("buffer::next"),
+                args: None,
+            }
+        );
+        keymap.bind_keys(
+            &[Key::ctrl('x'), Key::LEFT],
+            CommandInfo {
+                command_name: String::from("buffer::previous"),
+                args: None,
+            }
+        );
+
+        // The mark, region and kill ring
+        keymap.bind_key(
+            Key::ctrl(' '),
+            CommandInfo {
+                command_name: String::from("buffer::set_mark"),
+                args: None,
+            }
+        );
+        keymap.bind_key(
+            Key::ctrl('g'),
+            CommandInfo {
+                command_name: String::from("buffer::clear_mark"),
+                args: None,
+            }
+        );
+        keymap.bind_keys(
+            &[Key::ctrl('x'), Key::ctrl('x')],
+            CommandInfo {
+                command_name: String::from("buffer::swap_selection_ends"),
+                args: None,
+            }
+        );
+        keymap.bind_key(
+            Key::ctrl('w'),
+            CommandInfo {
+                command_name: String::from("buffer::kill_region"),
+                args: None,
+            }
+        );
+        keymap.bind_key(
+            Key::alt('w'),
+            CommandInfo {
+                command_name: String::from("buffer::copy_region"),
+                args: None,
+            }
+        );
+        keymap.bind_key(
+            Key::ctrl('k'),
+            CommandInfo {
+                command_name: String::from("buffer::kill_line"),
+                args: None,
+            }
+        );
+        keymap.bind_key(
+            Key::ctrl('y'),
+            CommandInfo {
+                command_name: String::from("buffer::yank_kill"),
+                args: None,
+            }
+        );
+        keymap.bind_key(
+            Key::alt('y'),
+            CommandInfo {
+                command_name: String::from("buffer::yank_pop"),
+                args: None,
+            }
+        );
+
         // keymap.bind_keys(
-        //     &[Key::Ctrl('x'), Key::Ctrl('f')],
+        //     &[Key::ctrl('x'), Key::ctrl('f')],
         //     CommandInfo {
         //         command_name: String::from("editor::switch_to_last_buffer"),
         //         args: None,
@@ -224,11 +381,20 @@ impl Mode for EmacsMode {
     /// Given a key, pass it through the EmacsMode KeyMap and return the associated Command, if any.
     /// If no match is found, treat it as an InsertChar command.
     fn handle_key_event(&mut self, key: Key) -> BuilderEvent {
+        // an escape on its own holds meta down for the next key
+        if self.meta_pending {
+            self.meta_pending = false;
+            return self.check_key(key.with(Mods::ALT));
+        } else if key == Key::ESC && !self.match_in_progress {
+            self.meta_pending = true;
+            return BuilderEvent::Incomplete;
+        }
+
         if self.match_in_progress {
             return self.check_key(key)
         }
 
-        if let Key::Char(c) = key {
+        if let Some(c) = key.typed() {
             let mut builder_args = BuilderArgs::new().with_char_arg(c);
             let command_info = CommandInfo {
                 command_name: String::from("buffer::insert_char"),
@@ -247,3 +413,35 @@ impl Default for EmacsMode {
         Self::new()
     }
 }
+
+
+#[cfg(test)]
+mod test {
+
+    use command::BuilderEvent;
+    use keyboard::Key;
+    use modes::{EmacsMode, Mode};
+
+    fn command_name(mode: &mut EmacsMode, key: Key) -> Option<String> {
+        match mode.handle_key_event(key) {
+            BuilderEvent::Complete(c) => Some(c.command_name),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn meta_keys_are_bound() {
+        let mut mode = EmacsMode::new();
+        assert_eq!(command_name(&mut mode, Key::alt('w')), Some(String::from("buffer::copy_region")));
+        assert_eq!(command_name(&mut mode, Key::alt('x')), Some(String::from("editor::set_overlay")));
+    }
+
+    #[test]
+    fn escape_holds_meta_for_the_next_key() {
+        let mut mode = EmacsMode::new();
+        assert_eq!(command_name(&mut mode, Key::ESC), None);
+        assert_eq!(command_name(&mut mode, Key::char('y')), Some(String::from("buffer::yank_pop")));
+        // only for the one key
+        assert_eq!(command_name(&mut mode, Key::char('y')), Some(String::from("buffer::insert_char")));
+    }
+}
@@ -26,7 +26,7 @@ impl InsertMode {
         let mut keymap = KeyMap::new();
 
         keymap.bind_key(
-            Key::Esc,
+            Key::ESC,
             CommandInfo {
                 command_name: String::from("editor::set_mode"),
                 args: Some(BuilderArgs::new().with_mode(ModeType::Normal))
@@ -34,7 +34,7 @@ impl InsertMode {
         );
         // Cursor movement
         keymap.bind_key(
-            Key::Up,
+            Key::UP,
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Line(Anchor::Same))
@@ -42,7 +42,7 @@ impl InsertMode {
             }
         );
         keymap.bind_key(
-            Key::Down,
+            Key::DOWN,
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Line(Anchor::Same))
@@ -50,7 +50,7 @@ impl InsertMode {
             }
         );
         keymap.bind_key(
-            Key::Left,
+            Key::LEFT,
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Char)
@@ -58,7 +58,7 @@ impl InsertMode {
             }
         );
         keymap.bind_key(
-            Key::Right,
+            Key::RIGHT,
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Char)
@@ -66,21 +66,21 @@ impl InsertMode {
             }
         );
         keymap.bind_key(
-            Key::Tab,
+            Key::TAB,
             CommandInfo {
                 command_name: String::from("buffer::insert_tab"),
                 args: None,
             }
         );
         keymap.bind_key(
-            Key::Enter,
+            Key::ENTER,
             CommandInfo {
                 command_name: String::from("buffer::insert_char"),
                 args: Some(BuilderArgs::new().with_char_arg('\n')),
             }
         );
         keymap.bind_key(
-            Key::Backspace,
+            Key::BACKSPACE,
             CommandInfo {
                 command_name: String::from("buffer::delete_char"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Char)
@@ -88,7 +88,7 @@ impl InsertMode {
             }
         );
         keymap.bind_key(
-            Key::Delete,
+            Key::DELETE,
             CommandInfo {
                 command_name: String::from("buffer::delete_char"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Char)
@@ -104,7 +104,7 @@ impl InsertMode {
 
 impl Mode for InsertMode {
     fn handle_key_event(&mut self, key: Key) -> BuilderEvent {
-        if let Key::Char(c) = key {
+        if let Some(c) = key.typed() {
             let builder_args = BuilderArgs::new().with_char_arg(c);
             let command_info = CommandInfo {
                 command_name: String::from("buffer::insert_char"),
@@ -117,4 +117,8 @@ impl Mode for InsertMode {
             BuilderEvent::Incomplete
         }
     }
+
+    fn selection_mode(&self) -> Option<ModeType> {
+        Some(ModeType::Visual)
+    }
 }
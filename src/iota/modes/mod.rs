@@ -5,16 +5,21 @@ pub use self::standard::StandardMode;
 pub use self::normal::NormalMode;
 pub use self::insert::InsertMode;
 pub use self::emacs::EmacsMode;
+pub use self::visual::VisualMode;
 
 mod standard;
 mod normal;
 mod insert;
 mod emacs;
+mod visual;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ModeType {
     Normal,
     Insert,
+    Visual,
+    VisualLine,
+    VisualBlock,
 }
 
 /// The concept of Iota's modes are taken from Vi.
@@ -24,4 +29,10 @@ pub enum ModeType {
 pub trait Mode {
     /// Given a Key, return a Command wrapped in a BuilderEvent for the Editor to interpret
     fn handle_key_event(&mut self, key: Key) -> BuilderEvent;
+
+    /// The mode to switch to when text is selected with the mouse, if text
+    /// is selected in a mode of its own.
+    fn selection_mode(&self) -> Option<ModeType> {
+        None
+    }
 }
@@ -9,11 +9,120 @@ use modes::ModeType;
 use super::Mode;
 
 
+/// The operators, as bound in the keymap, and the commands that apply them to
+/// whole lines when their key is doubled (`dd`, `cc`, `yy`, `>>`, `<<`).
+const OPERATORS: &'static [(&'static str, &'static str)] = &[
+    ("buffer::delete", "buffer::delete_line"),
+    ("buffer::change", "buffer::change_line"),
+    ("buffer::yank", "buffer::yank_line"),
+    ("buffer::indent", "buffer::indent_line"),
+    ("buffer::outdent", "buffer::outdent_line"),
+];
+
+/// An operator waiting for the motion it applies to.
+struct PendingOperator {
+    command_name: String,
+    line_command: &'static str,
+    number: Option<i32>,
+}
+
+/// The command choosing register `name` for the next yank, delete or put,
+/// which is typed as `"` followed by the name.
+pub fn use_register(name: char) -> BuilderEvent {
+    BuilderEvent::Complete(CommandInfo {
+        command_name: String::from("buffer::use_register"),
+        args: Some(BuilderArgs::new().with_char_arg(name)),
+    })
+}
+
+/// Whether `c` starts a search backward, if it starts one at all.
+fn search_direction(c: &CommandInfo) -> Option<bool> {
+    if c.command_name != "editor::set_overlay" {
+        return None;
+    }
+    match c.args.as_ref().and_then(|args| args.overlay_args) {
+        Some(OverlayType::Search) => Some(false),
+        Some(OverlayType::SearchBackward) => Some(true),
+        _ => None,
+    }
+}
+
+/// Bind the keys which move the cursor, shared by the modes that move around
+/// the text the way vi does.
+pub fn bind_motions(keymap: &mut KeyMap) {
+    keymap.bind_key(
+        Key::char('h'),
+        CommandInfo {
+            command_name: String::from("buffer::move_cursor"),
+            args: Some(BuilderArgs::new().with_kind(Kind::Char)
+                                         .with_offset(Offset::Backward(1, Mark::Cursor(0))))
+        }
+    );
+    keymap.bind_key(
+        Key::char('j'),
+        CommandInfo {
+            command_name: String::from("buffer::move_cursor"),
+            args: Some(BuilderArgs::new().with_kind(Kind::Line(Anchor::Same))
+                                         .with_offset(Offset::Forward(1, Mark::Cursor(0))))
+        }
+    );
+    keymap.bind_key(
+        Key::char('k'),
+        CommandInfo {
+            command_name: String::from("buffer::move_cursor"),
+            args: Some(BuilderArgs::new().with_kind(Kind::Line(Anchor::Same))
+                                         .with_offset(Offset::Backward(1, Mark::Cursor(0))))
+        }
+    );
+    keymap.bind_key(
+        Key::char('l'),
+        CommandInfo {
+            command_name: String::from("buffer::move_cursor"),
+            args: Some(BuilderArgs::new().with_kind(Kind::Char)
+                                         .with_offset(Offset::Forward(1, Mark::Cursor(0))))
+        }
+    );
+    keymap.bind_key(
+        Key::char('w'),
+        CommandInfo {
+            command_name: String::from("buffer::move_cursor"),
+            args: Some(BuilderArgs::new().with_kind(Kind::Word(Anchor::Start))
+                                         .with_offset(Offset::Forward(1, Mark::Cursor(0))))
+        }
+    );
+    keymap.bind_key(
+        Key::char('b'),
+        CommandInfo {
+            command_name: String::from("buffer::move_cursor"),
+            args: Some(BuilderArgs::new().with_kind(Kind::Word(Anchor::Start))
+                                         .with_offset(Offset::Backward(1, Mark::Cursor(0))))
+        }
+    );
+    keymap.bind_key(
+        Key::char('$'),
+        CommandInfo {
+            command_name: String::from("buffer::move_cursor"),
+            args: Some(BuilderArgs::new().with_kind(Kind::Line(Anchor::End))
+                                         .with_offset(Offset::Forward(0, Mark::Cursor(0))))
+        }
+    );
+    keymap.bind_key(
+        Key::char('0'),
+        CommandInfo {
+            command_name: String::from("buffer::move_cursor"),
+            args: Some(BuilderArgs::new().with_kind(Kind::Line(Anchor::Start))
+                                         .with_offset(Offset::Backward(0, Mark::Cursor(0))))
+        }
+    );
+}
+
 /// `NormalMode` mimics Vi's Normal mode.
 pub struct NormalMode {
     keymap: KeyMap,
     number: Option<i32>,
     reading_number: bool,
+    reading_register: bool,
+    pending: Option<PendingOperator>,
 }
 
 impl NormalMode {
@@ -24,118 +133,330 @@ impl NormalMode {
             keymap: NormalMode::key_defaults(),
             number: None,
             reading_number: false,
+            reading_register: false,
+            pending: None,
         }
     }
 
     /// Creates a `KeyMap` with default `NormalMode` key bindings
     fn key_defaults() -> KeyMap {
         let mut keymap = KeyMap::new();
-        // movement
-        // { keys: 'h', command: 'buffer::move_cursor', args: { direction: backward, kind: char, number: 1 } }
+        bind_motions(&mut keymap);
+
+        // operators, which wait for a motion
         keymap.bind_key(
-            Key::Char('h'),
+            Key::char('d'),
             CommandInfo {
-                command_name: String::from("buffer::move_cursor"),
-                args: Some(BuilderArgs::new().with_kind(Kind::Char)
-                                             .with_offset(Offset::Backward(1, Mark::Cursor(0))))
+                command_name: String::from("buffer::delete"),
+                args: None,
             }
         );
         keymap.bind_key(
-            Key::Char('j'),
+            Key::char('c'),
             CommandInfo {
-                command_name: String::from("buffer::move_cursor"),
-                args: Some(BuilderArgs::new().with_kind(Kind::Line(Anchor::Same))
-                                             .with_offset(Offset::Forward(1, Mark::Cursor(0))))
+                command_name: String::from("buffer::change"),
+                args: None,
             }
         );
         keymap.bind_key(
-            Key::Char('k'),
+            Key::char('y'),
             CommandInfo {
-                command_name: String::from("buffer::move_cursor"),
-                args: Some(BuilderArgs::new().with_kind(Kind::Line(Anchor::Same))
-                                             .with_offset(Offset::Backward(1, Mark::Cursor(0))))
+                command_name: String::from("buffer::yank"),
+                args: None,
             }
         );
         keymap.bind_key(
-            Key::Char('l'),
+            Key::char('>'),
             CommandInfo {
-                command_name: String::from("buffer::move_cursor"),
-                args: Some(BuilderArgs::new().with_kind(Kind::Char)
-                                             .with_offset(Offset::Forward(1, Mark::Cursor(0))))
+                command_name: String::from("buffer::indent"),
+                args: None,
             }
         );
         keymap.bind_key(
-            Key::Char('w'),
+            Key::char('<'),
             CommandInfo {
-                command_name: String::from("buffer::move_cursor"),
-                args: Some(BuilderArgs::new().with_kind(Kind::Word(Anchor::Start))
+                command_name: String::from("buffer::outdent"),
+                args: None,
+            }
+        );
+
+        // operators with their motion built in
+        keymap.bind_key(
+            Key::char('x'),
+            CommandInfo {
+                command_name: String::from("buffer::delete"),
+                args: Some(BuilderArgs::new().with_kind(Kind::LineChar)
                                              .with_offset(Offset::Forward(1, Mark::Cursor(0))))
             }
         );
         keymap.bind_key(
-            Key::Char('b'),
+            Key::char('X'),
             CommandInfo {
-                command_name: String::from("buffer::move_cursor"),
-                args: Some(BuilderArgs::new().with_kind(Kind::Word(Anchor::Start))
+                command_name: String::from("buffer::delete"),
+                args: Some(BuilderArgs::new().with_kind(Kind::LineChar)
                                              .with_offset(Offset::Backward(1, Mark::Cursor(0))))
             }
         );
         keymap.bind_key(
-            Key::Char('$'),
+            Key::char('D'),
+            CommandInfo {
+                command_name: String::from("buffer::delete"),
+                args: Some(BuilderArgs::new().with_kind(Kind::Line(Anchor::End))
+                                             .with_offset(Offset::Forward(0, Mark::Cursor(0))))
+            }
+        );
+        keymap.bind_key(
+            Key::char('C'),
             CommandInfo {
-                command_name: String::from("buffer::move_cursor"),
+                command_name: String::from("buffer::change"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Line(Anchor::End))
                                              .with_offset(Offset::Forward(0, Mark::Cursor(0))))
             }
         );
         keymap.bind_key(
-            Key::Char('0'),
+            Key::char('Y'),
             CommandInfo {
-                command_name: String::from("buffer::move_cursor"),
-                args: Some(BuilderArgs::new().with_kind(Kind::Line(Anchor::Start))
-                                             .with_offset(Offset::Backward(0, Mark::Cursor(0))))
+                command_name: String::from("buffer::yank_line"),
+                args: Some(BuilderArgs::new()),
+            }
+        );
+        keymap.bind_key(
+            Key::char('p'),
+            CommandInfo {
+                command_name: String::from("buffer::put"),
+                args: Some(BuilderArgs::new()),
+            }
+        );
+        keymap.bind_key(
+            Key::char('P'),
+            CommandInfo {
+                command_name: String::from("buffer::put_before"),
+                args: Some(BuilderArgs::new()),
             }
         );
 
         // actions
         keymap.bind_key(
-            Key::Char('u'),
+            Key::char('u'),
             CommandInfo {
                 command_name: String::from("editor::undo"),
                 args: None,
             }
         );
         keymap.bind_key(
-            Key::Ctrl('r'),
+            Key::ctrl('r'),
             CommandInfo {
                 command_name: String::from("editor::redo"),
                 args: None,
             }
         );
+        keymap.bind_keys(
+            &[Key::char('g'), Key::char('-')],
+            CommandInfo {
+                command_name: String::from("editor::undo_branch_prev"),
+                args: None,
+            }
+        );
+        keymap.bind_keys(
+            &[Key::char('g'), Key::char('+')],
+            CommandInfo {
+                command_name: String::from("editor::undo_branch_next"),
+                args: None,
+            }
+        );
+        keymap.bind_keys(
+            &[Key::char('g'), Key::char('t')],
+            CommandInfo {
+                command_name: String::from("buffer::next"),
+                args: None,
+            }
+        );
+        keymap.bind_keys(
+            &[Key::char('g'), Key::char('T')],
+            CommandInfo {
+                command_name: String::from("buffer::previous"),
+                args: None,
+            }
+        );
 
         keymap.bind_key(
-            Key::Char('i'),
+            Key::char('i'),
             CommandInfo {
                 command_name: String::from("editor::set_mode"),
                 args: Some(BuilderArgs::new().with_mode(ModeType::Insert)),
             }
         );
         keymap.bind_key(
-            Key::Char(':'),
+            Key::char('a'),
+            CommandInfo {
+                command_name: String::from("editor::set_mode"),
+                args: Some(BuilderArgs::new().with_mode(ModeType::Insert)
+                                             .with_kind(Kind::Char)
+                                             .with_offset(Offset::Forward(1, Mark::Cursor(0)))),
+            }
+        );
+        keymap.bind_key(
+            Key::char('A'),
+            CommandInfo {
+                command_name: String::from("editor::set_mode"),
+                args: Some(BuilderArgs::new().with_mode(ModeType::Insert)
+                                             .with_kind(Kind::Line(Anchor::End))
+                                             .with_offset(Offset::Forward(0, Mark::Cursor(0)))),
+            }
+        );
+        keymap.bind_key(
+            Key::char('I'),
+            CommandInfo {
+                command_name: String::from("editor::set_mode"),
+                args: Some(BuilderArgs::new().with_mode(ModeType::Insert)
+                                             .with_kind(Kind::Line(Anchor::Start))
+                                             .with_offset(Offset::Backward(0, Mark::Cursor(0)))),
+            }
+        );
+        keymap.bind_key(
+            Key::char('v'),
+            CommandInfo {
+                command_name: String::from("editor::set_mode"),
+                args: Some(BuilderArgs::new().with_mode(ModeType::Visual)),
+            }
+        );
+        keymap.bind_key(
+            Key::char('V'),
+            CommandInfo {
+                command_name: String::from("editor::set_mode"),
+                args: Some(BuilderArgs::new().with_mode(ModeType::VisualLine)),
+            }
+        );
+        keymap.bind_key(
+            Key::ctrl('v'),
+            CommandInfo {
+                command_name: String::from("editor::set_mode"),
+                args: Some(BuilderArgs::new().with_mode(ModeType::VisualBlock)),
+            }
+        );
+        keymap.bind_key(
+            Key::char('o'),
+            CommandInfo {
+                command_name: String::from("buffer::open_line_below"),
+                args: None,
+            }
+        );
+        keymap.bind_key(
+            Key::char('O'),
+            CommandInfo {
+                command_name: String::from("buffer::open_line_above"),
+                args: None,
+            }
+        );
+        keymap.bind_key(
+            Key::char(':'),
+            CommandInfo {
+                command_name: String::from("editor::set_overlay"),
+                args: Some(BuilderArgs::new().with_overlay(OverlayType::ExCommand)),
+            }
+        );
+
+        // searching
+        keymap.bind_key(
+            Key::char('/'),
             CommandInfo {
                 command_name: String::from("editor::set_overlay"),
-                args: Some(BuilderArgs::new().with_overlay(OverlayType::CommandPrompt)),
+                args: Some(BuilderArgs::new().with_overlay(OverlayType::Search)),
+            }
+        );
+        keymap.bind_key(
+            Key::char('?'),
+            CommandInfo {
+                command_name: String::from("editor::set_overlay"),
+                args: Some(BuilderArgs::new().with_overlay(OverlayType::SearchBackward)),
+            }
+        );
+        keymap.bind_key(
+            Key::char('n'),
+            CommandInfo {
+                command_name: String::from("buffer::search_next"),
+                args: Some(BuilderArgs::new()),
+            }
+        );
+        keymap.bind_key(
+            Key::char('N'),
+            CommandInfo {
+                command_name: String::from("buffer::search_previous"),
+                args: Some(BuilderArgs::new()),
             }
         );
 
         keymap
     }
 
+    /// Finish off a command matched in the keymap, which may start or complete
+    /// an operator.
+    fn complete(&mut self, mut c: CommandInfo, number: Option<i32>) -> BuilderEvent {
+        if let Some(pending) = self.pending.take() {
+            // counts before the operator and the motion multiply, so `2d3w` deletes 6 words
+            let number = match (pending.number, number) {
+                (Some(a), Some(b)) => Some(a * b),
+                (a, b) => a.or(b),
+            };
+            let (command_name, args) = if c.command_name == pending.command_name && c.args.is_none() {
+                (String::from(pending.line_command), BuilderArgs::new())
+            } else if c.command_name == "buffer::move_cursor" {
+                (pending.command_name, c.args.unwrap_or_else(BuilderArgs::new))
+            } else if let Some(backward) = search_direction(&c) {
+                // a search is a motion too, applying the operator up to the match
+                let offset = if backward {
+                    Offset::Backward(1, Mark::Cursor(0))
+                } else {
+                    Offset::Forward(1, Mark::Cursor(0))
+                };
+                let args = BuilderArgs::new().with_offset(offset).with_str(pending.command_name);
+                (String::from("buffer::search_motion"), args)
+            } else {
+                // anything but a motion cancels the operator
+                return BuilderEvent::Incomplete;
+            };
+            let args = match number {
+                Some(num) => args.with_number(num),
+                None => args,
+            };
+            return BuilderEvent::Complete(CommandInfo { command_name: command_name, args: Some(args) });
+        }
+
+        // operators are bound without arguments, and wait for a motion
+        if c.args.is_none() {
+            if let Some(&(_, line_command)) = OPERATORS.iter().find(|&&(name, _)| name == c.command_name) {
+                self.pending = Some(PendingOperator {
+                    command_name: c.command_name,
+                    line_command: line_command,
+                    number: number,
+                });
+                return BuilderEvent::Incomplete;
+            }
+        }
+
+        if let Some(num) = number {
+            if let Some(args) = c.args {
+                c.args = Some(args.with_number(num));
+            }
+        }
+        BuilderEvent::Complete(c)
+    }
 }
 
 impl Mode for NormalMode {
     fn handle_key_event(&mut self, key: Key) -> BuilderEvent {
-        if let Key::Char(c) = key {
+        if self.reading_register {
+            self.reading_register = false;
+            return match key.typed() {
+                Some(c) => use_register(c),
+                _ => BuilderEvent::Incomplete,
+            };
+        }
+        if key == Key::char('"') && self.pending.is_none() {
+            self.reading_register = true;
+            return BuilderEvent::Incomplete;
+        }
+        if let Some(c) = key.typed() {
             // '0' might be bound (start of line), and cannot be the start of a number sequence
             if c.is_digit(10) && (self.reading_number || c != '0') {
                 let n = c.to_digit(10).unwrap() as i32;
@@ -151,20 +472,22 @@ impl Mode for NormalMode {
             }
         }
         match self.keymap.check_key(key) {
-            KeyMapState::Match(mut c) => {
-                if let Some(num) = self.number {
-                    if let Some(args) = c.args {
-                        c.args = Some(args.with_number(num));
-                    }
-                }
-                self.number = None;
-                BuilderEvent::Complete(c)
+            KeyMapState::Match(c) => {
+                let number = self.number.take();
+                self.complete(c, number)
             }
-            _ => {
+            KeyMapState::Continue => BuilderEvent::Incomplete,
+            KeyMapState::None => {
+                // an unbound key, such as escape, cancels a waiting operator
+                self.pending = None;
                 BuilderEvent::Incomplete
             }
         }
     }
+
+    fn selection_mode(&self) -> Option<ModeType> {
+        Some(ModeType::Visual)
+    }
 }
 
 impl Default for NormalMode {
@@ -172,3 +495,113 @@ impl Default for NormalMode {
         Self::new()
     }
 }
+
+
+#[cfg(test)]
+mod test {
+
+    use command::BuilderEvent;
+    use keyboard::Key;
+    use keymap::CommandInfo;
+    use modes::Mode;
+    use modes::normal::NormalMode;
+    use textobject::{Kind, Offset};
+
+    /// Feed `keys` to a fresh `NormalMode`, returning the command they complete.
+    fn keys(keys: &str) -> Option<CommandInfo> {
+        let mut mode = NormalMode::new();
+        let mut result = None;
+        for ch in keys.chars() {
+            let key = if ch == '\x1b' { Key::ESC } else { Key::char(ch) };
+            result = match mode.handle_key_event(key) {
+                BuilderEvent::Complete(c) => Some(c),
+                _ => None,
+            };
+        }
+        result
+    }
+
+    #[test]
+    fn operator_waits_for_a_motion() {
+        assert!(keys("d").is_none());
+
+        let c = keys("dw").unwrap();
+        assert_eq!(c.command_name, "buffer::delete");
+        match c.args.unwrap().kind {
+            Some(Kind::Word(_)) => {}
+            other => panic!("expected a word, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn doubled_operator_takes_lines() {
+        assert_eq!(keys("dd").unwrap().command_name, "buffer::delete_line");
+        assert_eq!(keys("cc").unwrap().command_name, "buffer::change_line");
+        assert_eq!(keys("yy").unwrap().command_name, "buffer::yank_line");
+    }
+
+    #[test]
+    fn counts_multiply() {
+        assert_eq!(keys("d3w").unwrap().args.unwrap().number, Some(3));
+        assert_eq!(keys("2d3w").unwrap().args.unwrap().number, Some(6));
+        assert_eq!(keys("3dd").unwrap().args.unwrap().number, Some(3));
+        assert_eq!(keys("2yy").unwrap().args.unwrap().number, Some(2));
+        assert_eq!(keys("c$").unwrap().args.unwrap().number, None);
+    }
+
+    #[test]
+    fn escape_cancels_operator() {
+        assert!(keys("d\x1b").is_none());
+        let c = keys("d\x1bj").unwrap();
+        assert_eq!(c.command_name, "buffer::move_cursor");
+        match c.args.unwrap().offset {
+            Some(Offset::Forward(1, _)) => {}
+            other => panic!("expected to move forward, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn registers_are_named_before_commands() {
+        let c = keys("\"a").unwrap();
+        assert_eq!(c.command_name, "buffer::use_register");
+        assert_eq!(c.args.unwrap().char_args, Some('a'));
+        // a digit after `"` names a register, rather than starting a count
+        assert_eq!(keys("\"1").unwrap().args.unwrap().char_args, Some('1'));
+
+        let c = keys("3\"ayy").unwrap();
+        assert_eq!(c.command_name, "buffer::yank_line");
+        assert_eq!(c.args.unwrap().number, Some(3));
+    }
+
+    #[test]
+    fn motions_built_in() {
+        assert_eq!(keys("x").unwrap().command_name, "buffer::delete");
+        match keys("X").unwrap().args.unwrap().kind {
+            Some(Kind::LineChar) => {}
+            other => panic!("expected a char on the line, got {:?}", other),
+        }
+        assert_eq!(keys("3x").unwrap().args.unwrap().number, Some(3));
+        assert_eq!(keys("C").unwrap().command_name, "buffer::change");
+        assert_eq!(keys("o").unwrap().command_name, "buffer::open_line_below");
+    }
+
+    #[test]
+    fn searches_repeat_with_counts() {
+        assert_eq!(keys("/").unwrap().command_name, "editor::set_overlay");
+        assert_eq!(keys("N").unwrap().command_name, "buffer::search_previous");
+        assert_eq!(keys("3n").unwrap().args.unwrap().number, Some(3));
+    }
+
+    #[test]
+    fn searches_are_motions_for_operators() {
+        let c = keys("2d?").unwrap();
+        assert_eq!(c.command_name, "buffer::search_motion");
+        let args = c.args.unwrap();
+        assert_eq!(args.str_args, Some(String::from("buffer::delete")));
+        assert_eq!(args.number, Some(2));
+        match args.offset {
+            Some(Offset::Backward(1, _)) => {}
+            _ => panic!("expected a backward search"),
+        }
+    }
+}
@@ -1,8 +1,9 @@
-use keyboard::Key;
+use keyboard::{Key, KeyCode, Mods};
 use keymap::{KeyMap, KeyMapState, CommandInfo};
-use command::{BuilderEvent, BuilderArgs };
+use command::{BuilderEvent, BuilderArgs, LineAddress, LineRange};
 use buffer::Mark;
 use textobject::{ Offset, Kind, Anchor };
+use overlay::OverlayType;
 
 use super::Mode;
 
@@ -37,14 +38,14 @@ impl StandardMode {
 
         // Editor Commands
         keymap.bind_key(
-            Key::Ctrl('q'),
+            Key::ctrl('q'),
             CommandInfo {
                 command_name: String::from("editor::quit"),
                 args: None,
             }
         );
         keymap.bind_key(
-            Key::Ctrl('s'),
+            Key::ctrl('s'),
             CommandInfo {
                 command_name: String::from("editor::save_buffer"),
                 args: None,
@@ -53,7 +54,7 @@ impl StandardMode {
 
         // Cursor movement
         keymap.bind_key(
-            Key::Up,
+            Key::UP,
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Line(Anchor::Same))
@@ -61,7 +62,7 @@ impl StandardMode {
             }
         );
         keymap.bind_key(
-            Key::Down,
+            Key::DOWN,
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Line(Anchor::Same))
@@ -69,7 +70,7 @@ impl StandardMode {
             }
         );
         keymap.bind_key(
-            Key::Left,
+            Key::LEFT,
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Char)
@@ -77,7 +78,7 @@ impl StandardMode {
             }
         );
         keymap.bind_key(
-            Key::Right,
+            Key::RIGHT,
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Char)
@@ -86,7 +87,7 @@ impl StandardMode {
         );
 
         keymap.bind_key(
-            Key::CtrlRight,
+            Key::new(KeyCode::Right, Mods::CTRL),
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Word(Anchor::Start))
@@ -94,7 +95,7 @@ impl StandardMode {
             }
         );
         keymap.bind_key(
-            Key::CtrlLeft,
+            Key::new(KeyCode::Left, Mods::CTRL),
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Word(Anchor::Start))
@@ -103,7 +104,7 @@ impl StandardMode {
         );
     
         keymap.bind_key(
-            Key::End,
+            Key::END,
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Line(Anchor::End))
@@ -111,31 +112,51 @@ impl StandardMode {
             }
         );
         keymap.bind_key(
-            Key::Home,
+            Key::HOME,
             CommandInfo {
                 command_name: String::from("buffer::move_cursor"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Line(Anchor::End))
                                              .with_offset(Offset::Backward(0, Mark::Cursor(0))))
             }
         );
+        keymap.bind_key(
+            Key::new(KeyCode::Home, Mods::CTRL),
+            CommandInfo {
+                command_name: String::from("buffer::goto_line"),
+                args: Some(BuilderArgs::new().with_range(LineRange {
+                    start: LineAddress::Number(1),
+                    end: LineAddress::Number(1),
+                }))
+            }
+        );
+        keymap.bind_key(
+            Key::new(KeyCode::End, Mods::CTRL),
+            CommandInfo {
+                command_name: String::from("buffer::goto_line"),
+                args: Some(BuilderArgs::new().with_range(LineRange {
+                    start: LineAddress::Last,
+                    end: LineAddress::Last,
+                }))
+            }
+        );
 
         // Editing
         keymap.bind_key(
-            Key::Tab,
+            Key::TAB,
             CommandInfo {
                 command_name: String::from("buffer::insert_tab"),
                 args: None,
             }
         );
         keymap.bind_key(
-            Key::Enter,
+            Key::ENTER,
             CommandInfo {
                 command_name: String::from("buffer::insert_char"),
                 args: Some(BuilderArgs::new().with_char_arg('\n')),
             }
         );
         keymap.bind_key(
-            Key::Backspace,
+            Key::BACKSPACE,
             CommandInfo {
                 command_name: String::from("buffer::delete_char"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Char)
@@ -143,7 +164,7 @@ impl StandardMode {
             }
         );
         keymap.bind_key(
-            Key::Delete,
+            Key::DELETE,
             CommandInfo {
                 command_name: String::from("buffer::delete_char"),
                 args: Some(BuilderArgs::new().with_kind(Kind::Char)
@@ -153,20 +174,43 @@ impl StandardMode {
 
         // History
         keymap.bind_key(
-            Key::Ctrl('z'),
+            Key::ctrl('z'),
             CommandInfo {
                 command_name: String::from("editor::undo"),
                 args: None,
             }
         );
         keymap.bind_key(
-            Key::Ctrl('r'),
+            Key::ctrl('r'),
             CommandInfo {
                 command_name: String::from("editor::redo"),
                 args: None,
             }
         );
 
+        // searching
+        keymap.bind_key(
+            Key::ctrl('f'),
+            CommandInfo {
+                command_name: String::from("editor::set_overlay"),
+                args: Some(BuilderArgs::new().with_overlay(OverlayType::Search)),
+            }
+        );
+        keymap.bind_key(
+            Key::function(3),
+            CommandInfo {
+                command_name: String::from("buffer::search_next"),
+                args: None,
+            }
+        );
+        keymap.bind_key(
+            Key::new(KeyCode::F(3), Mods::SHIFT),
+            CommandInfo {
+                command_name: String::from("buffer::search_previous"),
+                args: None,
+            }
+        );
+
         keymap
     }
 
@@ -206,7 +250,7 @@ impl Mode for StandardMode {
             return self.check_key(key)
         }
 
-        if let Key::Char(c) = key {
+        if let Some(c) = key.typed() {
             let command_info = CommandInfo {
                 command_name: String::from("buffer::insert_char"),
                 args: Some(BuilderArgs::new().with_char_arg(c)),
@@ -0,0 +1,211 @@
+use keyboard::Key;
+use keymap::{KeyMap, KeyMapState, CommandInfo};
+use command::{BuilderEvent, BuilderArgs };
+use overlay::OverlayType;
+
+use super::{ModeType, Mode};
+use super::normal::{bind_motions, use_register};
+
+
+/// `VisualMode` mimics Vi's Visual modes, selecting text from where it was
+/// entered up to the cursor, for an operator to be applied to.
+///
+/// The same mode covers selecting chars (`v`), whole lines (`V`) and blocks
+/// (`ctrl-v`), as given by its `ModeType`.
+pub struct VisualMode {
+    keymap: KeyMap,
+    number: Option<i32>,
+    reading_register: bool,
+}
+
+impl VisualMode {
+
+    /// Create a new instance of `VisualMode`, of the given kind
+    pub fn new(mode: ModeType) -> VisualMode {
+        VisualMode {
+            keymap: VisualMode::key_defaults(mode),
+            number: None,
+            reading_register: false,
+        }
+    }
+
+    /// Creates a `KeyMap` with default `VisualMode` key bindings
+    fn key_defaults(mode: ModeType) -> KeyMap {
+        let mut keymap = KeyMap::new();
+        bind_motions(&mut keymap);
+
+        // the key for each kind of selection switches to it, or back to normal
+        // mode from the kind already being made
+        let kinds = [
+            (Key::char('v'), ModeType::Visual),
+            (Key::char('V'), ModeType::VisualLine),
+            (Key::ctrl('v'), ModeType::VisualBlock),
+        ];
+        for &(key, kind) in &kinds {
+            let target = if kind == mode { ModeType::Normal } else { kind };
+            keymap.bind_key(
+                key,
+                CommandInfo {
+                    command_name: String::from("editor::set_mode"),
+                    args: Some(BuilderArgs::new().with_mode(target)),
+                }
+            );
+        }
+        keymap.bind_key(
+            Key::ESC,
+            CommandInfo {
+                command_name: String::from("editor::set_mode"),
+                args: Some(BuilderArgs::new().with_mode(ModeType::Normal)),
+            }
+        );
+
+        // operators on the selection
+        let operators = [
+            (Key::char('d'), "buffer::delete_selection"),
+            (Key::char('x'), "buffer::delete_selection"),
+            (Key::char('y'), "buffer::yank_selection"),
+            (Key::char('c'), "buffer::change_selection"),
+            (Key::char('s'), "buffer::change_selection"),
+            (Key::char('>'), "buffer::indent_selection"),
+            (Key::char('<'), "buffer::outdent_selection"),
+            (Key::char('~'), "buffer::toggle_case_selection"),
+            (Key::char('o'), "buffer::swap_selection_ends"),
+        ];
+        for &(key, command_name) in &operators {
+            keymap.bind_key(
+                key,
+                CommandInfo {
+                    command_name: String::from(command_name),
+                    args: None,
+                }
+            );
+        }
+
+        // ex commands, given the selected lines as their range
+        keymap.bind_key(
+            Key::char(':'),
+            CommandInfo {
+                command_name: String::from("editor::set_overlay"),
+                args: Some(BuilderArgs::new().with_overlay(OverlayType::ExCommand)),
+            }
+        );
+
+        if mode == ModeType::VisualBlock {
+            keymap.bind_key(
+                Key::char('I'),
+                CommandInfo {
+                    command_name: String::from("buffer::insert_block"),
+                    args: None,
+                }
+            );
+            keymap.bind_key(
+                Key::char('A'),
+                CommandInfo {
+                    command_name: String::from("buffer::append_block"),
+                    args: None,
+                }
+            );
+        }
+
+        keymap
+    }
+
+}
+
+impl Mode for VisualMode {
+    fn handle_key_event(&mut self, key: Key) -> BuilderEvent {
+        if self.reading_register {
+            self.reading_register = false;
+            return match key.typed() {
+                Some(c) => use_register(c),
+                _ => BuilderEvent::Incomplete,
+            };
+        }
+        if key == Key::char('"') {
+            self.reading_register = true;
+            return BuilderEvent::Incomplete;
+        }
+        if let Some(c) = key.typed() {
+            // '0' is bound (start of line), and cannot be the start of a number sequence
+            if c.is_digit(10) && (self.number.is_some() || c != '0') {
+                let n = c.to_digit(10).unwrap() as i32;
+                self.number = Some(self.number.unwrap_or(0) * 10 + n);
+                return BuilderEvent::Incomplete;
+            }
+        }
+        match self.keymap.check_key(key) {
+            KeyMapState::Match(mut c) => {
+                if let Some(num) = self.number.take() {
+                    if let Some(args) = c.args {
+                        c.args = Some(args.with_number(num));
+                    }
+                }
+                BuilderEvent::Complete(c)
+            }
+            _ => {
+                BuilderEvent::Incomplete
+            }
+        }
+    }
+
+    fn selection_mode(&self) -> Option<ModeType> {
+        Some(ModeType::Visual)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use command::BuilderEvent;
+    use keyboard::Key;
+    use modes::{Mode, ModeType, VisualMode};
+
+    fn command_name(mode: &mut VisualMode, key: Key) -> Option<String> {
+        match mode.handle_key_event(key) {
+            BuilderEvent::Complete(c) => Some(c.command_name),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn operators_apply_to_selection() {
+        let mut mode = VisualMode::new(ModeType::Visual);
+        assert_eq!(command_name(&mut mode, Key::char('d')), Some(String::from("buffer::delete_selection")));
+        assert_eq!(command_name(&mut mode, Key::char('~')), Some(String::from("buffer::toggle_case_selection")));
+        assert_eq!(command_name(&mut mode, Key::char(':')), Some(String::from("editor::set_overlay")));
+    }
+
+    #[test]
+    fn selection_keys_switch_kind_or_leave() {
+        let mut mode = VisualMode::new(ModeType::VisualLine);
+        let mode_of = |mode: &mut VisualMode, key| {
+            match mode.handle_key_event(key) {
+                BuilderEvent::Complete(c) => c.args.unwrap().mode_args,
+                _ => None,
+            }
+        };
+        assert_eq!(mode_of(&mut mode, Key::char('V')), Some(ModeType::Normal));
+        assert_eq!(mode_of(&mut mode, Key::char('v')), Some(ModeType::Visual));
+        assert_eq!(mode_of(&mut mode, Key::ctrl('v')), Some(ModeType::VisualBlock));
+    }
+
+    #[test]
+    fn block_insert_only_in_block_mode() {
+        let mut mode = VisualMode::new(ModeType::VisualBlock);
+        assert_eq!(command_name(&mut mode, Key::char('I')), Some(String::from("buffer::insert_block")));
+
+        let mut mode = VisualMode::new(ModeType::Visual);
+        assert_eq!(command_name(&mut mode, Key::char('I')), None);
+    }
+
+    #[test]
+    fn counts_apply_to_motions() {
+        let mut mode = VisualMode::new(ModeType::Visual);
+        mode.handle_key_event(Key::char('3'));
+        match mode.handle_key_event(Key::char('j')) {
+            BuilderEvent::Complete(c) => assert_eq!(c.args.unwrap().number, Some(3)),
+            _ => panic!("expected a motion"),
+        }
+    }
+}
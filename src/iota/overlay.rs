@@ -1,23 +1,43 @@
 use std::cmp;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 use unicode_width::UnicodeWidthStr;
 use rustbox::{Style, Color, RustBox};
 
-use editor::ALL_COMMANDS;
-use command::BuilderEvent;
-use keyboard::Key;
+use editor::{ALL_COMMANDS, parse_command_line};
+use ex;
+use command::{BuilderEvent, BuilderArgs, ParamType, split_words};
+use keyboard::{Key, KeyCode, Mods};
 use keymap::CommandInfo;
+use buffer::Mark;
+use textobject::{Anchor, Kind, Offset};
+use log::UndoState;
 
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum OverlayType {
     CommandPrompt,
+    UndoTree,
+    BufferList,
+    FindFile,
+    MessageHistory,
+    ExCommand,
+    Search,
+    SearchBackward,
+    ConfirmReplace,
 }
 
 pub trait Overlay {
     fn draw(&self, rb: &mut RustBox);
     fn draw_cursor(&mut self, rb: &mut RustBox);
     fn handle_key_event(&mut self, key: Key) -> BuilderEvent;
+
+    /// Handle a click at column `x` and row `y` of a screen `height` rows high.
+    fn handle_click(&mut self, _x: usize, _y: usize, _height: usize) -> BuilderEvent {
+        BuilderEvent::Incomplete
+    }
 }
 
 pub struct CommandPrompt {
@@ -37,15 +57,62 @@ impl CommandPrompt {
 }
 
 impl CommandPrompt {
-    fn get_filtered_command_names(&self) -> Vec<&&str> {
-        let mut keys: Vec<&&str> = ALL_COMMANDS
-            .keys()
-            .filter(|item| item.starts_with(&*self.data) )
-            .collect();
-        keys.sort();
-        keys.reverse();
+    /// Lines completing what has been typed so far: command names until a
+    /// space is typed, and values for the argument being typed after that.
+    fn get_candidates(&self) -> Vec<String> {
+        let words = split_words(&self.data);
+        let typing_name = words.len() < 2 && !self.data.ends_with(' ');
+
+        let mut candidates: Vec<String> = if typing_name {
+            ALL_COMMANDS
+                .keys()
+                .filter(|item| item.starts_with(&*self.data) )
+                .map(|item| item.to_string())
+                .collect()
+        } else {
+            self.get_argument_candidates(&words)
+        };
+        candidates.sort();
+        candidates.reverse();
 
-        keys
+        candidates
+    }
+
+    fn get_argument_candidates(&self, words: &[String]) -> Vec<String> {
+        let spec = match words.first().and_then(|name| ALL_COMMANDS.get(&**name)) {
+            Some(spec) => spec,
+            None => return Vec::new(),
+        };
+        // the word being typed, and which of the command's parameters it is
+        let (partial, index) = if self.data.ends_with(' ') {
+            ("", words.len() - 1)
+        } else {
+            (&*words[words.len() - 1], words.len() - 2)
+        };
+        let param = match spec.params.get(index) {
+            Some(param) => param,
+            None => return Vec::new(),
+        };
+        // a quoted word doesn't appear in the line as it was split
+        if !self.data.ends_with(partial) {
+            return Vec::new();
+        }
+        let line_start = &self.data[..self.data.len() - partial.len()];
+
+        let values: Vec<String> = match param.kind {
+            ParamType::Path => path_candidates(Path::new("."), partial),
+            kind => kind.values().iter()
+                        .filter(|value| value.starts_with(partial))
+                        .map(|value| value.to_string())
+                        .collect(),
+        };
+        values.into_iter().map(|value| {
+            if value.contains(' ') {
+                format!("{}\"{}\"", line_start, value)
+            } else {
+                format!("{}{}", line_start, value)
+            }
+        }).collect()
     }
 }
 
@@ -55,13 +122,15 @@ impl Overlay for CommandPrompt {
         let height = rb.height() - 1;
         let offset = self.prefix.len();
 
-        let keys = self.get_filtered_command_names();
+        let mut keys = self.get_candidates();
+        keys.truncate(height.saturating_sub(1));
 
         // find the longest command in the resulting list
         let mut max = 20;
         for k in &keys {
-            max = cmp::max(max, k.len());
+            max = cmp::max(max, UnicodeWidthStr::width(k.as_str()));
         }
+        max = cmp::min(max, rb.width().saturating_sub(2));
 
         // draw the command completion list
         let mut index = 1;
@@ -87,11 +156,13 @@ impl Overlay for CommandPrompt {
             index += 1;
         }
 
-        rb.print_char(0, height - index, Style::empty(), Color::White, Color::Black, '╭');
-        for x in 1..max + 1 {
-            rb.print_char(x, height - keys.len() - 1, Style::empty(), Color::White, Color::Black, '─');
+        if index <= height {
+            rb.print_char(0, height - index, Style::empty(), Color::White, Color::Black, '╭');
+            for x in 1..max + 1 {
+                rb.print_char(x, height - index, Style::empty(), Color::White, Color::Black, '─');
+            }
+            rb.print_char(max + 1, height - index, Style::empty(), Color::White, Color::Black, '╮');
         }
-        rb.print_char(max + 1, height - index, Style::empty(), Color::White, Color::Black, '╮');
 
         // draw the given prefix
         for (index, ch) in self.prefix.chars().enumerate() {
@@ -116,50 +187,965 @@ impl Overlay for CommandPrompt {
 
     fn handle_key_event(&mut self, key: Key) -> BuilderEvent {
         match key {
-            Key::Esc => {
+            Key::ESC => {
                 let command_info = CommandInfo {
                     command_name: String::from("editor::noop"),
                     args: None,
                 };
                 return BuilderEvent::Complete(command_info);
             }
-            Key::Backspace => { self.data.pop(); },
-            Key::Enter => {
-                let command_info = CommandInfo {
-                    command_name: self.data.clone(),
-                    args: None,
+            Key::BACKSPACE => {
+                self.data.pop();
+                self.selected_index = 0;
+            }
+            Key::ENTER => {
+                let command_info = match parse_command_line(&self.data) {
+                    Ok(command_info) => command_info,
+                    Err(e) => CommandInfo {
+                        command_name: String::from("editor::show_message"),
+                        args: Some(BuilderArgs::new().with_str(e.to_string())),
+                    },
                 };
                 return BuilderEvent::Complete(command_info);
             }
-            Key::Up => {
-                let max = self.get_filtered_command_names().len();
+            Key::UP => {
+                let max = self.get_candidates().len();
                 if self.selected_index < max {
                     self.selected_index += 1;
                 }
             }
-            Key::Down => {
+            Key::DOWN => {
                 if self.selected_index > 0 {
                     self.selected_index -= 1;
                 }
             }
-            Key::Tab => {
+            Key::TAB => {
+                let candidates = self.get_candidates();
+                if self.selected_index > 0 && self.selected_index <= candidates.len() {
+                    self.data = candidates[self.selected_index - 1].clone();
+                } else if candidates.len() == 1 {
+                    self.data = candidates[0].clone();
+                }
+                self.selected_index = 0;
+            }
+            Key { code: KeyCode::Char(c), mods: Mods::NONE } => {
+                self.data.push(c);
+                self.selected_index = 0;
+            }
+            _ => {}
+        }
+        BuilderEvent::Incomplete
+    }
+
+    fn handle_click(&mut self, _x: usize, y: usize, height: usize) -> BuilderEvent {
+        // the completion list is drawn upwards from the line above the prompt
+        let index = (height - 1).saturating_sub(y);
+        let candidates = self.get_candidates();
+        if index > 0 && index < height - 1 && index <= candidates.len() {
+            self.data = candidates[index - 1].clone();
+            self.selected_index = 0;
+        }
+        BuilderEvent::Incomplete
+    }
+}
+
+
+/// Shows every state in the buffer's undo tree, and lets the user pick one
+/// to jump to.
+pub struct UndoTree {
+    states: Vec<UndoState>,
+    selected_index: usize,
+}
+
+impl UndoTree {
+    pub fn new(states: Vec<UndoState>) -> UndoTree {
+        let selected_index = states.iter().position(|s| s.current).unwrap_or(0);
+        UndoTree {
+            states: states,
+            selected_index: selected_index,
+        }
+    }
+
+    fn describe(state: &UndoState) -> String {
+        let indent = "  ".repeat(state.branch_depth);
+        let marker = if state.current { '●' } else { '○' };
+        let age = match state.time.elapsed() {
+            Ok(elapsed) => format_age(elapsed.as_secs()),
+            Err(_) => String::from("just now"),
+        };
+        if state.id == 0 {
+            format!("{}{} original ({})", indent, marker, age)
+        } else {
+            format!("{}{} {}: {} change(s), {}", indent, marker, state.id, state.changes, age)
+        }
+    }
+}
+
+fn format_age(secs: u64) -> String {
+    if secs < 60 {
+        format!("{}s ago", secs)
+    } else if secs < 60 * 60 {
+        format!("{}m ago", secs / 60)
+    } else if secs < 60 * 60 * 24 {
+        format!("{}h ago", secs / (60 * 60))
+    } else {
+        format!("{}d ago", secs / (60 * 60 * 24))
+    }
+}
+
+impl Overlay for UndoTree {
+    fn draw(&self, rb: &mut RustBox) {
+        let height = rb.height() - 1;
+        let width = rb.width();
+        let rows = cmp::min(self.states.len(), height.saturating_sub(1));
+
+        // keep the selected state on screen
+        let first = if self.selected_index >= rows { self.selected_index + 1 - rows } else { 0 };
+
+        for (row, state) in self.states.iter().skip(first).take(rows).enumerate() {
+            let y = height - rows + row;
+            let (fg, bg) = if first + row == self.selected_index {
+                (Color::White, Color::Red)
+            } else {
+                (Color::White, Color::Black)
+            };
+
+            let text = UndoTree::describe(state);
+            let mut chars = text.chars();
+            for x in 0..width {
+                let ch = chars.next().unwrap_or(' ');
+                rb.print_char(x, y, Style::empty(), fg, bg, ch);
+            }
+        }
+
+        let mut help = "undo tree: up/down to select, enter to jump, esc to cancel".chars();
+        for x in 0..width {
+            let ch = help.next().unwrap_or(' ');
+            rb.print_char(x, height, Style::empty(), Color::White, Color::Black, ch);
+        }
+    }
+
+    fn draw_cursor(&mut self, rb: &mut RustBox) {
+        rb.set_cursor(-1, -1);
+    }
+
+    fn handle_key_event(&mut self, key: Key) -> BuilderEvent {
+        match key {
+            Key::ESC => {
+                let command_info = CommandInfo {
+                    command_name: String::from("editor::noop"),
+                    args: None,
+                };
+                return BuilderEvent::Complete(command_info);
+            }
+            Key::ENTER => {
+                if let Some(state) = self.states.get(self.selected_index) {
+                    let command_info = CommandInfo {
+                        command_name: String::from("editor::undo_goto"),
+                        args: Some(BuilderArgs::new().with_number(state.id as i32)),
+                    };
+                    return BuilderEvent::Complete(command_info);
+                }
+            }
+            Key::UP | Key { code: KeyCode::Char('k'), mods: Mods::NONE } => {
                 if self.selected_index > 0 {
-                    let command = {
-                        let mut keys: Vec<&&str> = ALL_COMMANDS
-                            .keys()
-                            .filter(|item| item.starts_with(&*self.data) )
-                            .collect();
-                        keys.sort();
-                        keys.reverse();
-
-                        keys[self.selected_index - 1].clone()
+                    self.selected_index -= 1;
+                }
+            }
+            Key::DOWN | Key { code: KeyCode::Char('j'), mods: Mods::NONE } => {
+                if self.selected_index + 1 < self.states.len() {
+                    self.selected_index += 1;
+                }
+            }
+            _ => {}
+        }
+        BuilderEvent::Incomplete
+    }
+}
+
+
+/// Lists the open buffers, and lets the user pick one to switch to.
+pub struct BufferList {
+    entries: Vec<String>,
+    selected_index: usize,
+}
+
+impl BufferList {
+    /// `entries` describes each open buffer, `current` is the one being shown.
+    pub fn new(entries: Vec<String>, current: usize) -> BufferList {
+        BufferList {
+            entries: entries,
+            selected_index: current,
+        }
+    }
+}
+
+impl Overlay for BufferList {
+    fn draw(&self, rb: &mut RustBox) {
+        let height = rb.height() - 1;
+        let width = rb.width();
+        let rows = cmp::min(self.entries.len(), height.saturating_sub(1));
+
+        // keep the selected buffer on screen
+        let first = if self.selected_index >= rows { self.selected_index + 1 - rows } else { 0 };
+
+        for (row, entry) in self.entries.iter().skip(first).take(rows).enumerate() {
+            let y = height - rows + row;
+            let (fg, bg) = if first + row == self.selected_index {
+                (Color::White, Color::Red)
+            } else {
+                (Color::White, Color::Black)
+            };
+
+            let text = format!("{:>3} {}", first + row + 1, entry);
+            let mut chars = text.chars();
+            for x in 0..width {
+                let ch = chars.next().unwrap_or(' ');
+                rb.print_char(x, y, Style::empty(), fg, bg, ch);
+            }
+        }
+
+        let mut help = "buffers: up/down to select, enter to switch, esc to cancel".chars();
+        for x in 0..width {
+            let ch = help.next().unwrap_or(' ');
+            rb.print_char(x, height, Style::empty(), Color::White, Color::Black, ch);
+        }
+    }
+
+    fn draw_cursor(&mut self, rb: &mut RustBox) {
+        rb.set_cursor(-1, -1);
+    }
+
+    fn handle_key_event(&mut self, key: Key) -> BuilderEvent {
+        match key {
+            Key::ESC => {
+                let command_info = CommandInfo {
+                    command_name: String::from("editor::noop"),
+                    args: None,
+                };
+                return BuilderEvent::Complete(command_info);
+            }
+            Key::ENTER => {
+                if self.selected_index < self.entries.len() {
+                    let command_info = CommandInfo {
+                        command_name: String::from("buffer::switch_to"),
+                        args: Some(BuilderArgs::new().with_number(self.selected_index as i32 + 1)),
+                    };
+                    return BuilderEvent::Complete(command_info);
+                }
+            }
+            Key::UP | Key { code: KeyCode::Char('k'), mods: Mods::NONE } => {
+                if self.selected_index > 0 {
+                    self.selected_index -= 1;
+                }
+            }
+            Key::DOWN | Key { code: KeyCode::Char('j'), mods: Mods::NONE } => {
+                if self.selected_index + 1 < self.entries.len() {
+                    self.selected_index += 1;
+                }
+            }
+            _ => {}
+        }
+        BuilderEvent::Incomplete
+    }
+}
+
+
+/// Paths completing `typed`, taken relative to `base`, with directories
+/// ending in a `/`.
+fn path_candidates(base: &Path, typed: &str) -> Vec<String> {
+    let (dir, name) = match typed.rfind('/') {
+        Some(idx) => typed.split_at(idx + 1),
+        None => ("", typed),
+    };
+
+    let entries = match fs::read_dir(base.join(dir)) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut candidates: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            // hidden files are only offered once a `.` has been typed
+            if !file_name.starts_with(name) || (file_name.starts_with('.') && !name.starts_with('.')) {
+                return None;
+            }
+            let is_dir = entry.path().is_dir();
+            Some(format!("{}{}{}", dir, file_name, if is_dir { "/" } else { "" }))
+        })
+        .collect();
+    candidates.sort();
+    candidates.reverse();
+
+    candidates
+}
+
+/// The vi style `:` command line.
+pub struct ExPrompt {
+    data: String,
+    prefix: String,
+}
+
+impl ExPrompt {
+    pub fn new() -> ExPrompt {
+        ExPrompt {
+            data: String::new(),
+            prefix: String::from(":"),
+        }
+    }
+
+    /// An `ExPrompt` with `data` already typed in, such as a range.
+    pub fn with_data(data: &str) -> ExPrompt {
+        ExPrompt {
+            data: String::from(data),
+            ..ExPrompt::new()
+        }
+    }
+
+    /// Complete the last word typed as a path, as far as every candidate agrees.
+    fn complete(&mut self) {
+        let start = self.data.rfind(' ').map_or(0, |idx| idx + 1);
+        let mut candidates = path_candidates(Path::new("."), &self.data[start..]).into_iter();
+        let mut common = match candidates.next() {
+            Some(first) => first,
+            None => return,
+        };
+        for candidate in candidates {
+            let len = common.chars().zip(candidate.chars())
+                            .take_while(|&(a, b)| a == b)
+                            .map(|(a, _)| a.len_utf8())
+                            .sum();
+            common.truncate(len);
+        }
+        if common.len() > self.data.len() - start {
+            self.data.truncate(start);
+            self.data.push_str(&common);
+        }
+    }
+}
+
+impl Overlay for ExPrompt {
+    fn draw(&self, rb: &mut RustBox) {
+        let height = rb.height() - 1;
+        let width = rb.width();
+        let mut chars = self.prefix.chars().chain(self.data.chars());
+        for x in 0..width {
+            let ch = chars.next().unwrap_or(' ');
+            rb.print_char(x, height, Style::empty(), Color::White, Color::Black, ch);
+        }
+    }
+
+    fn draw_cursor(&mut self, rb: &mut RustBox) {
+        let height = rb.height() - 1;
+        let prefix_len = UnicodeWidthStr::width(self.prefix.as_str());
+        let data_len = UnicodeWidthStr::width(self.data.as_str());
+        let cursor_x = prefix_len + data_len;
+        rb.set_cursor(cursor_x as isize, height as isize);
+    }
+
+    fn handle_key_event(&mut self, key: Key) -> BuilderEvent {
+        match key {
+            Key::ESC => {
+                let command_info = CommandInfo {
+                    command_name: String::from("editor::noop"),
+                    args: None,
+                };
+                return BuilderEvent::Complete(command_info);
+            }
+            Key::BACKSPACE => {
+                // backspacing over the `:` leaves the command line, as in vi
+                if self.data.pop().is_none() {
+                    let command_info = CommandInfo {
+                        command_name: String::from("editor::noop"),
+                        args: None,
                     };
-                    self.data = command.to_string();
+                    return BuilderEvent::Complete(command_info);
+                }
+            }
+            Key::ENTER => {
+                let command_info = match ex::parse(&self.data) {
+                    Ok(command_info) => command_info,
+                    Err(e) => CommandInfo {
+                        command_name: String::from("editor::show_message"),
+                        args: Some(BuilderArgs::new().with_str(e)),
+                    },
+                };
+                return BuilderEvent::Complete(command_info);
+            }
+            Key::TAB => { self.complete() }
+            Key { code: KeyCode::Char(c), mods: Mods::NONE } => { self.data.push(c) }
+            _ => {}
+        }
+        BuilderEvent::Incomplete
+    }
+}
+
+/// Prompts for a file to open, completing paths relative to the directory of
+/// the current buffer.
+pub struct FindFile {
+    /// Directory that relative paths are taken from.
+    base: PathBuf,
+    data: String,
+    prefix: String,
+    selected_index: usize,
+}
+
+impl FindFile {
+    pub fn new(base: PathBuf) -> FindFile {
+        FindFile {
+            base: base,
+            data: String::new(),
+            prefix: String::from("Find file: "),
+            selected_index: 0,
+        }
+    }
+
+    /// Paths completing what has been typed so far, with directories ending in
+    /// a `/`.
+    fn get_candidates(&self) -> Vec<String> {
+        path_candidates(&self.base, &self.data)
+    }
+
+    /// Fill in as much of the path as every candidate agrees on.
+    fn complete(&mut self) {
+        let candidates = self.get_candidates();
+        if self.selected_index > 0 && self.selected_index <= candidates.len() {
+            self.data = candidates[self.selected_index - 1].clone();
+            self.selected_index = 0;
+            return;
+        }
+
+        let mut candidates = candidates.into_iter();
+        let mut common = match candidates.next() {
+            Some(first) => first,
+            None => return,
+        };
+        for candidate in candidates {
+            let len = common.chars().zip(candidate.chars())
+                            .take_while(|&(a, b)| a == b)
+                            .map(|(a, _)| a.len_utf8())
+                            .sum();
+            common.truncate(len);
+        }
+        if common.len() > self.data.len() {
+            self.data = common;
+        }
+    }
+}
+
+impl Overlay for FindFile {
+    fn draw(&self, rb: &mut RustBox) {
+        let height = rb.height() - 1;
+
+        let mut candidates = self.get_candidates();
+        candidates.truncate(height.saturating_sub(1));
+
+        // find the longest path in the resulting list
+        let mut max = 20;
+        for c in &candidates {
+            max = cmp::max(max, UnicodeWidthStr::width(c.as_str()));
+        }
+        max = cmp::min(max, rb.width().saturating_sub(2));
+
+        // draw the completion list
+        if !candidates.is_empty() {
+            let mut index = 1;
+            for candidate in &candidates {
+                rb.print_char(0, height - index, Style::empty(), Color::White, Color::Black, '│');
+                rb.print_char(max + 1, height - index, Style::empty(), Color::White, Color::Black, '│');
+
+                let (fg, bg) = if index == self.selected_index {
+                    (Color::White, Color::Red)
+                } else {
+                    (Color::White, Color::Black)
+                };
+
+                let mut chars = candidate.chars();
+                for x in 0..max {
+                    let ch = chars.next().unwrap_or(' ');
+                    rb.print_char(x + 1, height - index, Style::empty(), fg, bg, ch);
                 }
+
+                index += 1;
+            }
+
+            if index <= height {
+                rb.print_char(0, height - index, Style::empty(), Color::White, Color::Black, '╭');
+                for x in 1..max + 1 {
+                    rb.print_char(x, height - index, Style::empty(), Color::White, Color::Black, '─');
+                }
+                rb.print_char(max + 1, height - index, Style::empty(), Color::White, Color::Black, '╮');
+            }
+        }
+
+        // draw the prompt and the path typed so far
+        let width = rb.width();
+        let mut chars = self.prefix.chars().chain(self.data.chars());
+        for x in 0..width {
+            let ch = chars.next().unwrap_or(' ');
+            rb.print_char(x, height, Style::empty(), Color::White, Color::Black, ch);
+        }
+    }
+
+    fn draw_cursor(&mut self, rb: &mut RustBox) {
+        let height = rb.height() - 1;
+        let prefix_len = UnicodeWidthStr::width(self.prefix.as_str());
+        let data_len = UnicodeWidthStr::width(self.data.as_str());
+        let cursor_x = prefix_len + data_len;
+        rb.set_cursor(cursor_x as isize, height as isize);
+    }
+
+    fn handle_key_event(&mut self, key: Key) -> BuilderEvent {
+        match key {
+            Key::ESC => {
+                let command_info = CommandInfo {
+                    command_name: String::from("editor::noop"),
+                    args: None,
+                };
+                return BuilderEvent::Complete(command_info);
+            }
+            Key::BACKSPACE => {
+                self.data.pop();
+                self.selected_index = 0;
+            }
+            Key::ENTER => {
+                if self.selected_index > 0 {
+                    self.complete();
+                }
+                let path = self.base.join(&self.data);
+                if self.data.is_empty() || path.is_dir() {
+                    // keep going into the directory rather than opening it
+                    if !self.data.is_empty() && !self.data.ends_with('/') {
+                        self.data.push('/');
+                    }
+                    return BuilderEvent::Incomplete;
+                }
+                let command_info = CommandInfo {
+                    command_name: String::from("editor::open_file"),
+                    args: Some(BuilderArgs::new().with_str(path.to_string_lossy().into_owned())),
+                };
+                return BuilderEvent::Complete(command_info);
+            }
+            Key::UP => {
+                let max = self.get_candidates().len();
+                if self.selected_index < max {
+                    self.selected_index += 1;
+                }
+            }
+            Key::DOWN => {
+                if self.selected_index > 0 {
+                    self.selected_index -= 1;
+                }
+            }
+            Key::TAB => { self.complete() }
+            Key { code: KeyCode::Char(c), mods: Mods::NONE } => {
+                self.data.push(c);
+                self.selected_index = 0;
             }
-            Key::Char(c) => { self.data.push(c) },
             _ => {}
         }
         BuilderEvent::Incomplete
     }
 }
+
+/// Reads a pattern to search for, moving to its first match as it is typed.
+///
+/// Read for an operator, as in vi's `d/foo`, it stays put while it is typed,
+/// and then applies the operator up to the match.
+pub struct SearchPrompt {
+    data: String,
+    backward: bool,
+    operator: Option<CommandInfo>,
+}
+
+impl SearchPrompt {
+    pub fn new(backward: bool) -> SearchPrompt {
+        SearchPrompt {
+            data: String::new(),
+            backward: backward,
+            operator: None,
+        }
+    }
+
+    /// A `SearchPrompt` reading the pattern for `operator` to apply up to.
+    pub fn for_operator(operator: CommandInfo, backward: bool) -> SearchPrompt {
+        SearchPrompt {
+            operator: Some(operator),
+            ..SearchPrompt::new(backward)
+        }
+    }
+
+    fn prefix(&self) -> &'static str {
+        if self.backward { "?" } else { "/" }
+    }
+
+    /// The command `command_name` with the pattern typed so far.
+    fn search_command(&self, command_name: &str) -> CommandInfo {
+        let offset = if self.backward {
+            Offset::Backward(1, Mark::Cursor(0))
+        } else {
+            Offset::Forward(1, Mark::Cursor(0))
+        };
+        CommandInfo {
+            command_name: String::from(command_name),
+            args: Some(BuilderArgs::new().with_offset(offset).with_pattern(self.data.clone())),
+        }
+    }
+
+    /// Handle a key while reading the pattern for an operator.
+    fn handle_operator_key(&mut self, key: Key) -> BuilderEvent {
+        let cancel = BuilderEvent::Complete(CommandInfo {
+            command_name: String::from("editor::noop"),
+            args: None,
+        });
+        match key {
+            Key::ESC | Key { code: KeyCode::Char('g'), mods: Mods::CTRL } => cancel,
+            Key::BACKSPACE => {
+                if self.data.pop().is_none() { cancel } else { BuilderEvent::Incomplete }
+            }
+            Key::ENTER if self.data.is_empty() => cancel,
+            Key::ENTER => {
+                let mut operator = match self.operator.take() {
+                    Some(operator) => operator,
+                    None => return cancel,
+                };
+                let offset = if self.backward {
+                    Offset::Backward(1, Mark::Cursor(0))
+                } else {
+                    Offset::Forward(1, Mark::Cursor(0))
+                };
+                let args = operator.args.take().unwrap_or_else(BuilderArgs::new)
+                                   .with_kind(Kind::Match(self.data.clone(), Anchor::Start))
+                                   .with_offset(offset);
+                operator.args = Some(args);
+                BuilderEvent::Complete(operator)
+            }
+            Key { code: KeyCode::Char(c), mods: Mods::NONE } => {
+                self.data.push(c);
+                BuilderEvent::Incomplete
+            }
+            _ => BuilderEvent::Incomplete,
+        }
+    }
+
+    /// Move on to the next match, searching backward from now on when
+    /// `backward` is set.
+    fn next_match(&mut self, backward: bool) -> BuilderEvent {
+        self.backward = backward;
+        BuilderEvent::Preview(self.search_command("buffer::search_again"))
+    }
+}
+
+impl Overlay for SearchPrompt {
+    fn draw(&self, rb: &mut RustBox) {
+        let height = rb.height() - 1;
+        let width = rb.width();
+        let mut chars = self.prefix().chars().chain(self.data.chars());
+        for x in 0..width {
+            let ch = chars.next().unwrap_or(' ');
+            rb.print_char(x, height, Style::empty(), Color::White, Color::Black, ch);
+        }
+    }
+
+    fn draw_cursor(&mut self, rb: &mut RustBox) {
+        let height = rb.height() - 1;
+        let cursor_x = 1 + UnicodeWidthStr::width(self.data.as_str());
+        rb.set_cursor(cursor_x as isize, height as isize);
+    }
+
+    fn handle_key_event(&mut self, key: Key) -> BuilderEvent {
+        if self.operator.is_some() {
+            return self.handle_operator_key(key);
+        }
+        match key {
+            Key::ESC | Key { code: KeyCode::Char('g'), mods: Mods::CTRL } => {
+                let command_info = CommandInfo {
+                    command_name: String::from("buffer::cancel_search"),
+                    args: None,
+                };
+                BuilderEvent::Complete(command_info)
+            }
+            Key::BACKSPACE => {
+                // backspacing over the `/` gives up the search, as in vi
+                if self.data.pop().is_none() {
+                    return self.handle_key_event(Key::ESC);
+                }
+                BuilderEvent::Preview(self.search_command("buffer::search_preview"))
+            }
+            Key::ENTER => BuilderEvent::Complete(self.search_command("buffer::search")),
+            Key { code: KeyCode::Char('s'), mods: Mods::CTRL } => self.next_match(false),
+            Key { code: KeyCode::Char('r'), mods: Mods::CTRL } => self.next_match(true),
+            Key { code: KeyCode::Char(c), mods: Mods::NONE } => {
+                self.data.push(c);
+                BuilderEvent::Preview(self.search_command("buffer::search_preview"))
+            }
+            _ => BuilderEvent::Incomplete,
+        }
+    }
+}
+
+/// Asks whether to replace the match a substitution has stopped at.
+pub struct ReplacePrompt {
+    prompt: String,
+}
+
+impl ReplacePrompt {
+    pub fn new(replacement: &str) -> ReplacePrompt {
+        ReplacePrompt {
+            prompt: format!("Replace with {}? (y/n/a/q)", replacement.replace('\n', "\\n")),
+        }
+    }
+}
+
+impl Overlay for ReplacePrompt {
+    fn draw(&self, rb: &mut RustBox) {
+        let height = rb.height() - 1;
+        let width = rb.width();
+        let mut chars = self.prompt.chars();
+        for x in 0..width {
+            let ch = chars.next().unwrap_or(' ');
+            rb.print_char(x, height, Style::empty(), Color::White, Color::Black, ch);
+        }
+    }
+
+    fn draw_cursor(&mut self, rb: &mut RustBox) {
+        let height = rb.height() - 1;
+        let cursor_x = UnicodeWidthStr::width(self.prompt.as_str());
+        rb.set_cursor(cursor_x as isize, height as isize);
+    }
+
+    fn handle_key_event(&mut self, key: Key) -> BuilderEvent {
+        // the prompt stays up while there are matches to ask about, so only
+        // the answers that end the substitution close it
+        let (command_name, last) = match key {
+            Key { code: KeyCode::Char('y'), mods: Mods::NONE } => ("buffer::replace_match", false),
+            Key { code: KeyCode::Char('n'), mods: Mods::NONE } => ("buffer::skip_match", false),
+            Key { code: KeyCode::Char('a'), mods: Mods::NONE } => ("buffer::replace_remaining", true),
+            Key { code: KeyCode::Char('q'), mods: Mods::NONE } |
+            Key { code: KeyCode::Char('g'), mods: Mods::CTRL } |
+            Key::ESC => ("buffer::stop_replacing", true),
+            _ => return BuilderEvent::Incomplete,
+        };
+        let command_info = CommandInfo {
+            command_name: String::from(command_name),
+            args: None,
+        };
+        if last {
+            BuilderEvent::Complete(command_info)
+        } else {
+            BuilderEvent::Preview(command_info)
+        }
+    }
+}
+
+/// Shows the messages displayed so far, newest at the bottom.
+pub struct MessageHistory {
+    messages: Vec<(String, SystemTime)>,
+    /// How many messages up from the newest one the view is scrolled.
+    scroll: usize,
+}
+
+impl MessageHistory {
+    pub fn new(messages: Vec<(String, SystemTime)>) -> MessageHistory {
+        MessageHistory {
+            messages: messages,
+            scroll: 0,
+        }
+    }
+}
+
+impl Overlay for MessageHistory {
+    fn draw(&self, rb: &mut RustBox) {
+        let height = rb.height() - 1;
+        let width = rb.width();
+        let end = self.messages.len() - cmp::min(self.scroll, self.messages.len());
+        let rows = cmp::min(end, height.saturating_sub(1));
+
+        for (row, &(ref message, time)) in self.messages[end - rows..end].iter().enumerate() {
+            let y = height - rows + row;
+            let age = match time.elapsed() {
+                Ok(elapsed) => format_age(elapsed.as_secs()),
+                Err(_) => String::from("just now"),
+            };
+            let text = format!("{:>8}  {}", age, message);
+            let mut chars = text.chars();
+            for x in 0..width {
+                let ch = chars.next().unwrap_or(' ');
+                rb.print_char(x, y, Style::empty(), Color::White, Color::Black, ch);
+            }
+        }
+
+        let help = if self.messages.is_empty() {
+            "messages: none yet, esc to close"
+        } else {
+            "messages: up/down to scroll, esc to close"
+        };
+        let mut help = help.chars();
+        for x in 0..width {
+            let ch = help.next().unwrap_or(' ');
+            rb.print_char(x, height, Style::empty(), Color::White, Color::Black, ch);
+        }
+    }
+
+    fn draw_cursor(&mut self, rb: &mut RustBox) {
+        rb.set_cursor(-1, -1);
+    }
+
+    fn handle_key_event(&mut self, key: Key) -> BuilderEvent {
+        match key {
+            Key::ESC | Key::ENTER | Key { code: KeyCode::Char('q'), mods: Mods::NONE } => {
+                let command_info = CommandInfo {
+                    command_name: String::from("editor::noop"),
+                    args: None,
+                };
+                return BuilderEvent::Complete(command_info);
+            }
+            Key::UP | Key { code: KeyCode::Char('k'), mods: Mods::NONE } => {
+                if self.scroll + 1 < self.messages.len() {
+                    self.scroll += 1;
+                }
+            }
+            Key::DOWN | Key { code: KeyCode::Char('j'), mods: Mods::NONE } => {
+                if self.scroll > 0 {
+                    self.scroll -= 1;
+                }
+            }
+            _ => {}
+        }
+        BuilderEvent::Incomplete
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs::{self, File};
+
+    use tempdir::TempDir;
+
+    use command::{BuilderArgs, BuilderEvent};
+    use keyboard::Key;
+    use keymap::CommandInfo;
+    use overlay::{CommandPrompt, FindFile, Overlay, SearchPrompt};
+    use textobject::{Anchor, Kind, Offset};
+
+    fn setup_dir() -> TempDir {
+        let dir = TempDir::new("iota-find-file").unwrap();
+        File::create(dir.path().join("main.rs")).unwrap();
+        File::create(dir.path().join("make.rs")).unwrap();
+        File::create(dir.path().join(".hidden")).unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        File::create(dir.path().join("src").join("lib.rs")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn candidates_are_relative_to_base() {
+        let dir = setup_dir();
+        let mut find = FindFile::new(dir.path().to_path_buf());
+
+        assert_eq!(find.get_candidates(), vec!["src/", "make.rs", "main.rs"]);
+
+        find.data = String::from("src/");
+        assert_eq!(find.get_candidates(), vec!["src/lib.rs"]);
+
+        find.data = String::from(".");
+        assert_eq!(find.get_candidates(), vec![".hidden"]);
+    }
+
+    #[test]
+    fn tab_completes_common_prefix() {
+        let dir = setup_dir();
+        let mut find = FindFile::new(dir.path().to_path_buf());
+
+        find.data = String::from("m");
+        find.complete();
+        assert_eq!(find.data, "ma");
+
+        find.data = String::from("s");
+        find.complete();
+        assert_eq!(find.data, "src/");
+    }
+
+    #[test]
+    fn prompt_completes_command_names_then_arguments() {
+        let mut prompt = CommandPrompt::new();
+        prompt.data = String::from("editor::set_mo");
+        assert_eq!(prompt.get_candidates(), vec!["editor::set_mode"]);
+
+        prompt.data = String::from("editor::set_mode ");
+        assert_eq!(prompt.get_candidates(), vec![
+            "editor::set_mode visual_line",
+            "editor::set_mode visual_block",
+            "editor::set_mode visual",
+            "editor::set_mode normal",
+            "editor::set_mode insert",
+        ]);
+
+        prompt.data = String::from("editor::set_mode n");
+        assert_eq!(prompt.get_candidates(), vec!["editor::set_mode normal"]);
+
+        prompt.data = String::from("editor::set_mode normal ");
+        assert!(prompt.get_candidates().is_empty());
+    }
+
+    #[test]
+    fn clicking_a_candidate_fills_it_in() {
+        let mut prompt = CommandPrompt::new();
+        prompt.data = String::from("editor::set_mode v");
+        // on a screen 20 rows high, the list starts on the row above the prompt
+        prompt.handle_click(3, 17, 20);
+        assert_eq!(prompt.data, "editor::set_mode visual_block");
+
+        // rows with no candidate, or the prompt itself, change nothing
+        prompt.data = String::from("editor::set_mode v");
+        prompt.handle_click(3, 2, 20);
+        prompt.handle_click(3, 19, 20);
+        assert_eq!(prompt.data, "editor::set_mode v");
+    }
+
+    #[test]
+    fn search_keys_give_the_way_to_go() {
+        let mut prompt = SearchPrompt::new(false);
+        prompt.handle_key_event(Key::char('x'));
+        let backward = |event| match event {
+            BuilderEvent::Preview(c) => {
+                assert_eq!(c.command_name, "buffer::search_again");
+                match c.args.unwrap().offset {
+                    Some(Offset::Backward(..)) => true,
+                    _ => false,
+                }
+            }
+            _ => panic!("expected a search"),
+        };
+        // pressing C-r again keeps going backward
+        assert!(backward(prompt.handle_key_event(Key::ctrl('r'))));
+        assert!(backward(prompt.handle_key_event(Key::ctrl('r'))));
+        assert!(!backward(prompt.handle_key_event(Key::ctrl('s'))));
+    }
+
+    #[test]
+    fn operator_searches_end_in_the_operator() {
+        let operator = CommandInfo {
+            command_name: String::from("buffer::change"),
+            args: Some(BuilderArgs::new().with_number(2)),
+        };
+        let mut prompt = SearchPrompt::for_operator(operator, false);
+        // nothing moves while the pattern is typed
+        assert!(match prompt.handle_key_event(Key::char('x')) { BuilderEvent::Incomplete => true, _ => false });
+        prompt.handle_key_event(Key::char('y'));
+
+        let c = match prompt.handle_key_event(Key::ENTER) {
+            BuilderEvent::Complete(c) => c,
+            _ => panic!("expected a command"),
+        };
+        assert_eq!(c.command_name, "buffer::change");
+        let args = c.args.unwrap();
+        assert_eq!(args.number, Some(2));
+        match (args.kind, args.offset) {
+            (Some(Kind::Match(pattern, Anchor::Start)), Some(Offset::Forward(1, _))) => assert_eq!(pattern, "xy"),
+            _ => panic!("expected a match object"),
+        }
+    }
+}
@@ -0,0 +1,358 @@
+//! Registers hold text taken by yanks and deletes, to be put back later.
+//!
+//! As in vi, each register is named by a single char:
+//!
+//! - `"` the unnamed register, holding whatever was last yanked or deleted
+//! - `a` to `z` named registers, which are only written to when asked for;
+//!   naming them in upper case (`A` to `Z`) appends to them instead
+//! - `1` to `9` the history of deletes, most recent first, which is shifted
+//!   along by every delete not given a register of its own
+//! - `-` the last text deleted a char at a time, by backspace and the like
+//! - `.` the text last typed in, which can't be written to
+//!
+//! Emacs keeps its kills apart from these, in the `KillRing`.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+/// How many deletes are kept in the numbered registers.
+const HISTORY: usize = 9;
+
+/// How many kills are kept in the kill ring.
+const KILL_RING_MAX: usize = 60;
+
+/// The contents of a register.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Register {
+    pub text: String,
+    /// Whether whole lines were taken, which are put back as whole lines.
+    pub linewise: bool,
+}
+
+impl Register {
+    pub fn new(text: String, linewise: bool) -> Register {
+        Register { text: text, linewise: linewise }
+    }
+
+    /// Add `other` to the end of this register.  If either holds whole lines,
+    /// so does the result.
+    fn append(&mut self, other: &Register) {
+        if self.linewise || other.linewise {
+            if !self.text.is_empty() && !self.text.ends_with('\n') {
+                self.text.push('\n');
+            }
+            self.text.push_str(&other.text);
+            if !self.text.ends_with('\n') {
+                self.text.push('\n');
+            }
+            self.linewise = true;
+        } else {
+            self.text.push_str(&other.text);
+        }
+    }
+}
+
+/// Why a register could not be used.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RegisterError {
+    /// No register has the given name.
+    Unknown(char),
+    /// The register is filled in by the editor, and can only be read.
+    ReadOnly(char),
+}
+
+impl fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RegisterError::Unknown(name) => write!(f, "Unknown register: {}", name),
+            RegisterError::ReadOnly(name) => write!(f, "Register {} is read-only", name),
+        }
+    }
+}
+
+/// Every register, along with the text being typed in for `.`.
+pub struct Registers {
+    unnamed: Option<Register>,
+    named: [Option<Register>; 26],
+    deleted: VecDeque<Register>,
+    small_delete: Option<Register>,
+    inserted: Option<Register>,
+
+    /// Text typed since the last call to `finish_insert`.
+    typing: String,
+}
+
+impl Registers {
+    pub fn new() -> Registers {
+        Registers {
+            unnamed: None,
+            named: Default::default(),
+            deleted: VecDeque::new(),
+            small_delete: None,
+            inserted: None,
+            typing: String::new(),
+        }
+    }
+
+    /// Whether `name` is the name of a register.
+    pub fn is_register(name: char) -> bool {
+        name == '"' || name == '-' || name == '.' || name.is_ascii_alphabetic() ||
+            (name.is_digit(10) && name != '0')
+    }
+
+    /// The contents of the register called `name`, or the unnamed register
+    /// when no name is given.
+    pub fn get(&self, name: Option<char>) -> Result<Option<&Register>, RegisterError> {
+        let register = match name {
+            None | Some('"') => self.unnamed.as_ref(),
+            Some('-') => self.small_delete.as_ref(),
+            Some('.') => self.inserted.as_ref(),
+            Some(c) if c.is_ascii_alphabetic() => self.named[named_index(c)].as_ref(),
+            Some(c) if c.is_digit(10) && c != '0' => {
+                self.deleted.get(c.to_digit(10).unwrap() as usize - 1)
+            }
+            Some(c) => return Err(RegisterError::Unknown(c)),
+        };
+        Ok(register)
+    }
+
+    /// Keep text which was yanked, in the register called `name` if given.
+    pub fn yanked(&mut self, name: Option<char>, register: Register) -> Result<(), RegisterError> {
+        self.store(name, register, false)
+    }
+
+    /// Keep text which was deleted, in the register called `name` if given,
+    /// or at the front of the delete history if not.
+    pub fn deleted(&mut self, name: Option<char>, register: Register) -> Result<(), RegisterError> {
+        self.store(name, register, true)
+    }
+
+    fn store(&mut self, name: Option<char>, register: Register, deleted: bool) -> Result<(), RegisterError> {
+        match name {
+            None | Some('"') => {
+                if deleted {
+                    self.deleted.push_front(register.clone());
+                    self.deleted.truncate(HISTORY);
+                }
+            }
+            Some('-') => self.small_delete = Some(register.clone()),
+            Some(c) if c.is_ascii_lowercase() => self.named[named_index(c)] = Some(register.clone()),
+            Some(c) if c.is_ascii_uppercase() => {
+                let slot = &mut self.named[named_index(c)];
+                match *slot {
+                    Some(ref mut existing) => existing.append(&register),
+                    None => *slot = Some(register.clone()),
+                }
+                // the unnamed register gets the whole of the appended register
+                self.unnamed = slot.clone();
+                return Ok(());
+            }
+            Some(c) if c == '.' || (c.is_digit(10) && c != '0') => return Err(RegisterError::ReadOnly(c)),
+            Some(c) => return Err(RegisterError::Unknown(c)),
+        }
+        self.unnamed = Some(register);
+        Ok(())
+    }
+
+    /// Keep text deleted a char at a time, which doesn't disturb the unnamed
+    /// register or the delete history.
+    pub fn deleted_chars(&mut self, text: String, before_cursor: bool) {
+        // backspacing over text just typed takes it back out of `.`
+        if before_cursor && self.typing.ends_with(&text) {
+            let len = self.typing.len() - text.len();
+            self.typing.truncate(len);
+        }
+        self.small_delete = Some(Register::new(text, false));
+    }
+
+    /// Note a char typed in, to go in `.` once typing is finished.
+    pub fn typed(&mut self, c: char) {
+        self.typing.push(c);
+    }
+
+    /// Put the text typed since this was last called into `.`.
+    pub fn finish_insert(&mut self) {
+        if !self.typing.is_empty() {
+            let text = ::std::mem::replace(&mut self.typing, String::new());
+            self.inserted = Some(Register::new(text, false));
+        }
+    }
+}
+
+/// Text cut or copied in emacs mode, most recent first, to be yanked back.
+pub struct KillRing {
+    kills: VecDeque<String>,
+    /// The kill last yanked, counting from the most recent.
+    yanking: usize,
+}
+
+impl KillRing {
+    pub fn new() -> KillRing {
+        KillRing {
+            kills: VecDeque::new(),
+            yanking: 0,
+        }
+    }
+
+    /// Add killed text to the ring.  When `join` is set it goes on the end of
+    /// the most recent kill instead, or on the front if `before` is set.
+    pub fn kill(&mut self, text: String, join: bool, before: bool) {
+        if join {
+            if let Some(last) = self.kills.front_mut() {
+                if before {
+                    last.insert_str(0, &text);
+                } else {
+                    last.push_str(&text);
+                }
+                return;
+            }
+        }
+        if !text.is_empty() {
+            self.kills.push_front(text);
+            self.kills.truncate(KILL_RING_MAX);
+        }
+    }
+
+    /// The most recent kill, to be yanked.
+    pub fn yank(&mut self) -> Option<&str> {
+        self.yanking = 0;
+        self.kills.front().map(|kill| kill.as_str())
+    }
+
+    /// The kill before the one last yanked, going round to the most recent
+    /// after the oldest.
+    pub fn yank_pop(&mut self) -> Option<&str> {
+        if self.kills.is_empty() {
+            return None;
+        }
+        self.yanking = (self.yanking + 1) % self.kills.len();
+        self.kills.get(self.yanking).map(|kill| kill.as_str())
+    }
+}
+
+/// The index into `Registers::named` of a letter, in either case.
+fn named_index(c: char) -> usize {
+    (c.to_ascii_lowercase() as u8 - b'a') as usize
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use registers::{KillRing, Register, RegisterError, Registers};
+
+    fn chars(text: &str) -> Register {
+        Register::new(String::from(text), false)
+    }
+
+    fn lines(text: &str) -> Register {
+        Register::new(String::from(text), true)
+    }
+
+    fn text(registers: &Registers, name: char) -> Option<String> {
+        registers.get(Some(name)).unwrap().map(|r| r.text.clone())
+    }
+
+    #[test]
+    fn yanks_fill_unnamed_and_named_registers() {
+        let mut registers = Registers::new();
+        registers.yanked(None, chars("one")).unwrap();
+        assert_eq!(registers.get(None).unwrap(), Some(&chars("one")));
+
+        registers.yanked(Some('a'), chars("two")).unwrap();
+        assert_eq!(text(&registers, 'a'), Some(String::from("two")));
+        assert_eq!(text(&registers, '"'), Some(String::from("two")));
+        assert_eq!(text(&registers, 'b'), None);
+        // yanks aren't deletes
+        assert_eq!(text(&registers, '1'), None);
+    }
+
+    #[test]
+    fn upper_case_appends() {
+        let mut registers = Registers::new();
+        registers.yanked(Some('a'), chars("one")).unwrap();
+        registers.yanked(Some('A'), chars(" two")).unwrap();
+        assert_eq!(text(&registers, 'a'), Some(String::from("one two")));
+
+        registers.yanked(Some('A'), lines("three\n")).unwrap();
+        assert_eq!(registers.get(Some('a')).unwrap(), Some(&lines("one two\nthree\n")));
+        assert_eq!(registers.get(None).unwrap(), Some(&lines("one two\nthree\n")));
+
+        // appending to an empty register just fills it
+        registers.yanked(Some('B'), chars("four")).unwrap();
+        assert_eq!(text(&registers, 'b'), Some(String::from("four")));
+    }
+
+    #[test]
+    fn deletes_shift_through_the_history() {
+        let mut registers = Registers::new();
+        for n in 1..12 {
+            registers.deleted(None, lines(&format!("{}\n", n))).unwrap();
+        }
+        assert_eq!(text(&registers, '1'), Some(String::from("11\n")));
+        assert_eq!(text(&registers, '9'), Some(String::from("3\n")));
+
+        // a delete into a named register leaves the history alone
+        registers.deleted(Some('z'), chars("named")).unwrap();
+        assert_eq!(text(&registers, '1'), Some(String::from("11\n")));
+        assert_eq!(text(&registers, '"'), Some(String::from("named")));
+    }
+
+    #[test]
+    fn typed_text_goes_in_dot() {
+        let mut registers = Registers::new();
+        for c in "hellp".chars() {
+            registers.typed(c);
+        }
+        registers.deleted_chars(String::from("p"), true);
+        registers.typed('o');
+        assert_eq!(text(&registers, '.'), None);
+
+        registers.finish_insert();
+        assert_eq!(text(&registers, '.'), Some(String::from("hello")));
+        assert_eq!(text(&registers, '-'), Some(String::from("p")));
+        // nothing typed leaves it be
+        registers.finish_insert();
+        assert_eq!(text(&registers, '.'), Some(String::from("hello")));
+    }
+
+    #[test]
+    fn some_registers_cannot_be_written() {
+        let mut registers = Registers::new();
+        assert_eq!(registers.yanked(Some('.'), chars("x")), Err(RegisterError::ReadOnly('.')));
+        assert_eq!(registers.deleted(Some('3'), chars("x")), Err(RegisterError::ReadOnly('3')));
+        assert_eq!(registers.yanked(Some('!'), chars("x")), Err(RegisterError::Unknown('!')));
+        assert_eq!(registers.get(Some('0')), Err(RegisterError::Unknown('0')));
+        assert_eq!(registers.get(None).unwrap(), None);
+    }
+
+    #[test]
+    fn kills_join_when_asked() {
+        let mut ring = KillRing::new();
+        ring.kill(String::from("one"), false, false);
+        ring.kill(String::from(" two"), true, false);
+        ring.kill(String::from("zero "), true, true);
+        assert_eq!(ring.yank(), Some("zero one two"));
+
+        ring.kill(String::from("three"), false, false);
+        assert_eq!(ring.yank(), Some("three"));
+    }
+
+    #[test]
+    fn yank_pop_goes_round_the_ring() {
+        let mut ring = KillRing::new();
+        assert_eq!(ring.yank(), None);
+        assert_eq!(ring.yank_pop(), None);
+
+        for kill in &["one", "two", "three"] {
+            ring.kill(kill.to_string(), false, false);
+        }
+        assert_eq!(ring.yank(), Some("three"));
+        assert_eq!(ring.yank_pop(), Some("two"));
+        assert_eq!(ring.yank_pop(), Some("one"));
+        assert_eq!(ring.yank_pop(), Some("three"));
+        // yanking starts again from the most recent
+        ring.yank_pop();
+        assert_eq!(ring.yank(), Some("three"));
+    }
+}
@@ -0,0 +1,595 @@
+//! A rope for storing the text of a `Buffer`.
+//!
+//! The text is split into small leaves held in a B-tree. Every node caches the
+//! number of chars and newlines below it, so finding a char, a line start, or
+//! the line containing an index only needs to walk from the root to a single
+//! leaf. All indexes are char indexes, never byte offsets.
+
+use std::cmp;
+use std::convert::From;
+use std::str;
+
+/// Maximum size of a leaf, in bytes.
+const MAX_LEAF: usize = 1024;
+
+/// Maximum number of children of an internal node.
+const MAX_CHILDREN: usize = 16;
+
+struct Node {
+    /// Number of chars in this subtree.
+    chars: usize,
+    /// Number of '\n' chars in this subtree.
+    newlines: usize,
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    Leaf(String),
+    Internal(Vec<Node>),
+}
+
+impl Node {
+    fn leaf(text: String) -> Node {
+        Node {
+            chars: text.chars().count(),
+            newlines: count_newlines(&text),
+            kind: NodeKind::Leaf(text),
+        }
+    }
+
+    fn internal(children: Vec<Node>) -> Node {
+        let mut node = Node {
+            chars: 0,
+            newlines: 0,
+            kind: NodeKind::Internal(children),
+        };
+        node.update_counts();
+        node
+    }
+
+    fn update_counts(&mut self) {
+        let (chars, newlines) = match self.kind {
+            NodeKind::Leaf(ref text) => (text.chars().count(), count_newlines(text)),
+            NodeKind::Internal(ref children) => {
+                children.iter().fold((0, 0), |(c, n), child| (c + child.chars, n + child.newlines))
+            }
+        };
+        self.chars = chars;
+        self.newlines = newlines;
+    }
+
+    fn char_at(&self, idx: usize) -> Option<char> {
+        match self.kind {
+            NodeKind::Leaf(ref text) => text.chars().nth(idx),
+            NodeKind::Internal(ref children) => {
+                let mut idx = idx;
+                for child in children {
+                    if idx < child.chars {
+                        return child.char_at(idx);
+                    }
+                    idx -= child.chars;
+                }
+                None
+            }
+        }
+    }
+
+    /// Number of newlines before char index `idx`.
+    fn newlines_before(&self, idx: usize) -> usize {
+        match self.kind {
+            NodeKind::Leaf(ref text) => text.chars().take(idx).filter(|c| *c == '\n').count(),
+            NodeKind::Internal(ref children) => {
+                let mut idx = idx;
+                let mut count = 0;
+                for child in children {
+                    if idx < child.chars {
+                        return count + child.newlines_before(idx);
+                    }
+                    idx -= child.chars;
+                    count += child.newlines;
+                }
+                count
+            }
+        }
+    }
+
+    /// Char index of the `n`th newline (zero based).
+    fn nth_newline(&self, n: usize) -> Option<usize> {
+        if n >= self.newlines {
+            return None;
+        }
+        match self.kind {
+            NodeKind::Leaf(ref text) => {
+                text.chars().enumerate().filter(|&(_, c)| c == '\n').nth(n).map(|(i, _)| i)
+            }
+            NodeKind::Internal(ref children) => {
+                let mut n = n;
+                let mut offset = 0;
+                for child in children {
+                    if n < child.newlines {
+                        return child.nth_newline(n).map(|i| i + offset);
+                    }
+                    n -= child.newlines;
+                    offset += child.chars;
+                }
+                None
+            }
+        }
+    }
+
+    /// Insert `s` at `idx`. If the node grows too large it is split, and the
+    /// nodes which should follow it are returned.
+    fn insert(&mut self, idx: usize, s: &str) -> Vec<Node> {
+        let overflow = match self.kind {
+            NodeKind::Leaf(ref mut text) => {
+                let byte_idx = byte_index(text, idx);
+                text.insert_str(byte_idx, s);
+                if text.len() > MAX_LEAF {
+                    let mut chunks = split_leaves(text);
+                    *text = chunks.remove(0);
+                    chunks.into_iter().map(Node::leaf).collect()
+                } else {
+                    Vec::new()
+                }
+            }
+            NodeKind::Internal(ref mut children) => {
+                let mut idx = idx;
+                let mut pos = children.len() - 1;
+                for (i, child) in children.iter().enumerate() {
+                    if idx <= child.chars {
+                        pos = i;
+                        break;
+                    }
+                    idx -= child.chars;
+                }
+                let extra = children[pos].insert(idx, s);
+                for (i, node) in extra.into_iter().enumerate() {
+                    children.insert(pos + 1 + i, node);
+                }
+                if children.len() > MAX_CHILDREN {
+                    let mut groups = group_children(children.drain(..).collect());
+                    *children = groups.remove(0);
+                    groups.into_iter().map(Node::internal).collect()
+                } else {
+                    Vec::new()
+                }
+            }
+        };
+        self.update_counts();
+        overflow
+    }
+
+    /// Remove the chars in `start..end`, pushing them on to `removed`.
+    fn remove(&mut self, start: usize, end: usize, removed: &mut String) {
+        match self.kind {
+            NodeKind::Leaf(ref mut text) => {
+                let byte_start = byte_index(text, start);
+                let byte_end = byte_index(text, end);
+                removed.push_str(&text[byte_start..byte_end]);
+                text.drain(byte_start..byte_end);
+            }
+            NodeKind::Internal(ref mut children) => {
+                let mut offset = 0;
+                for child in children.iter_mut() {
+                    let child_end = offset + child.chars;
+                    if start < child_end && end > offset {
+                        let s = start.saturating_sub(offset);
+                        let e = cmp::min(end, child_end) - offset;
+                        child.remove(s, e, removed);
+                    }
+                    offset = child_end;
+                    if offset >= end {
+                        break;
+                    }
+                }
+                children.retain(|child| child.chars > 0);
+            }
+        }
+        self.update_counts();
+    }
+}
+
+/// A rope of chars with a cached line index.
+pub struct Rope {
+    root: Node,
+}
+
+#[cfg_attr(feature="clippy", allow(len_without_is_empty))]
+impl Rope {
+    /// Create an empty rope.
+    pub fn new() -> Rope {
+        Rope {
+            root: Node::leaf(String::new()),
+        }
+    }
+
+    /// Number of chars in the rope.
+    pub fn len(&self) -> usize {
+        self.root.chars
+    }
+
+    /// Number of lines in the rope. An empty rope has one line.
+    pub fn line_count(&self) -> usize {
+        self.root.newlines + 1
+    }
+
+    /// The char at `idx`, if there is one.
+    pub fn char_at(&self, idx: usize) -> Option<char> {
+        self.root.char_at(idx)
+    }
+
+    /// The (zero based) line containing the char at `idx`.
+    ///
+    /// An index past the end of the rope belongs to the last line.
+    pub fn line_of(&self, idx: usize) -> usize {
+        self.root.newlines_before(cmp::min(idx, self.len()))
+    }
+
+    /// Index of the first char of the (zero based) `line`.
+    pub fn line_start(&self, line: usize) -> Option<usize> {
+        if line == 0 {
+            Some(0)
+        } else {
+            self.root.nth_newline(line - 1).map(|i| i + 1)
+        }
+    }
+
+    /// Index of the `n`th (zero based) newline char in the rope.
+    pub fn nth_newline(&self, n: usize) -> Option<usize> {
+        self.root.nth_newline(n)
+    }
+
+    /// Insert a single char at `idx`.
+    pub fn insert(&mut self, idx: usize, ch: char) {
+        let mut buf = [0; 4];
+        self.insert_str(idx, ch.encode_utf8(&mut buf));
+    }
+
+    /// Insert a string at `idx`.
+    pub fn insert_str(&mut self, idx: usize, s: &str) {
+        if s.is_empty() {
+            return;
+        }
+        let idx = cmp::min(idx, self.len());
+        let mut extra = self.root.insert(idx, s);
+        while !extra.is_empty() {
+            let old_root = ::std::mem::replace(&mut self.root, Node::leaf(String::new()));
+            extra.insert(0, old_root);
+            let mut groups = group_children(extra);
+            self.root = Node::internal(groups.remove(0));
+            extra = groups.into_iter().map(Node::internal).collect();
+        }
+    }
+
+    /// Remove the char at `idx`, returning it.
+    pub fn remove(&mut self, idx: usize) -> Option<char> {
+        if idx >= self.len() {
+            return None;
+        }
+        self.remove_range(idx, idx + 1).chars().next()
+    }
+
+    /// Remove the chars in `start..end`, returning them.
+    pub fn remove_range(&mut self, start: usize, end: usize) -> String {
+        let end = cmp::min(end, self.len());
+        let mut removed = String::new();
+        if start >= end {
+            return removed;
+        }
+        self.root.remove(start, end, &mut removed);
+
+        // collapse any chain of single child nodes left behind
+        loop {
+            let child = match self.root.kind {
+                NodeKind::Internal(ref mut children) if children.len() <= 1 => {
+                    children.pop().unwrap_or_else(|| Node::leaf(String::new()))
+                }
+                _ => break,
+            };
+            self.root = child;
+        }
+        removed
+    }
+
+    /// Iterate over the chars of the rope, starting at `idx`.
+    pub fn chars_at(&self, idx: usize) -> Chars {
+        let mut stack = Vec::new();
+        let mut node = &self.root;
+        let mut idx = idx;
+        loop {
+            match node.kind {
+                NodeKind::Leaf(ref text) => {
+                    let byte_idx = byte_index(text, idx);
+                    return Chars {
+                        stack: stack,
+                        current: text[byte_idx..].chars(),
+                    };
+                }
+                NodeKind::Internal(ref children) => {
+                    let mut pos = children.len();
+                    for (i, child) in children.iter().enumerate() {
+                        if idx < child.chars {
+                            pos = i;
+                            break;
+                        }
+                        idx -= child.chars;
+                    }
+                    if pos == children.len() {
+                        // past the end of the rope
+                        return Chars { stack: Vec::new(), current: "".chars() };
+                    }
+                    stack.push((node, pos + 1));
+                    node = &children[pos];
+                }
+            }
+        }
+    }
+
+    /// Iterate backwards over the chars of the rope that come before `idx`.
+    pub fn chars_before(&self, idx: usize) -> RevChars {
+        let mut stack = Vec::new();
+        let mut node = &self.root;
+        let mut idx = cmp::min(idx, self.len());
+        loop {
+            match node.kind {
+                NodeKind::Leaf(ref text) => {
+                    let byte_idx = byte_index(text, idx);
+                    return RevChars {
+                        stack: stack,
+                        current: text[..byte_idx].chars(),
+                    };
+                }
+                NodeKind::Internal(ref children) => {
+                    let mut pos = children.len() - 1;
+                    for (i, child) in children.iter().enumerate() {
+                        if idx <= child.chars {
+                            pos = i;
+                            break;
+                        }
+                        idx -= child.chars;
+                    }
+                    stack.push((node, pos));
+                    node = &children[pos];
+                }
+            }
+        }
+    }
+}
+
+impl Default for Rope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> From<&'a str> for Rope {
+    fn from(s: &'a str) -> Rope {
+        let mut nodes: Vec<Node> = split_leaves(s).into_iter().map(Node::leaf).collect();
+        if nodes.is_empty() {
+            return Rope::new();
+        }
+        while nodes.len() > 1 {
+            nodes = group_children(nodes).into_iter().map(Node::internal).collect();
+        }
+        Rope { root: nodes.remove(0) }
+    }
+}
+
+/// Forward iterator over the chars of a `Rope`.
+pub struct Chars<'a> {
+    /// Internal nodes above the current leaf, with the index of the next child to visit.
+    stack: Vec<(&'a Node, usize)>,
+    current: str::Chars<'a>,
+}
+
+impl<'a> Iterator for Chars<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        loop {
+            if let Some(ch) = self.current.next() {
+                return Some(ch);
+            }
+
+            // move on to the next leaf
+            let mut node = loop {
+                let (parent, next) = match self.stack.pop() {
+                    Some(entry) => entry,
+                    None => return None,
+                };
+                if let NodeKind::Internal(ref children) = parent.kind {
+                    if next < children.len() {
+                        self.stack.push((parent, next + 1));
+                        break &children[next];
+                    }
+                }
+            };
+            loop {
+                match node.kind {
+                    NodeKind::Leaf(ref text) => {
+                        self.current = text.chars();
+                        break;
+                    }
+                    NodeKind::Internal(ref children) => {
+                        self.stack.push((node, 1));
+                        node = &children[0];
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Backward iterator over the chars of a `Rope`.
+pub struct RevChars<'a> {
+    /// Internal nodes above the current leaf, with the index of the current child.
+    stack: Vec<(&'a Node, usize)>,
+    current: str::Chars<'a>,
+}
+
+impl<'a> Iterator for RevChars<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        loop {
+            if let Some(ch) = self.current.next_back() {
+                return Some(ch);
+            }
+
+            // move back to the previous leaf
+            let mut node = loop {
+                let (parent, current) = match self.stack.pop() {
+                    Some(entry) => entry,
+                    None => return None,
+                };
+                if let NodeKind::Internal(ref children) = parent.kind {
+                    if current > 0 {
+                        self.stack.push((parent, current - 1));
+                        break &children[current - 1];
+                    }
+                }
+            };
+            loop {
+                match node.kind {
+                    NodeKind::Leaf(ref text) => {
+                        self.current = text.chars();
+                        break;
+                    }
+                    NodeKind::Internal(ref children) => {
+                        let last = children.len() - 1;
+                        self.stack.push((node, last));
+                        node = &children[last];
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn count_newlines(s: &str) -> usize {
+    s.bytes().filter(|b| *b == b'\n').count()
+}
+
+/// Byte offset of char index `idx` within `s`, or the length of `s`.
+fn byte_index(s: &str, idx: usize) -> usize {
+    s.char_indices().nth(idx).map(|(i, _)| i).unwrap_or_else(|| s.len())
+}
+
+/// Split `s` into chunks of at most `MAX_LEAF` bytes, on char boundaries.
+fn split_leaves(s: &str) -> Vec<String> {
+    let mut leaves = Vec::new();
+    let mut start = 0;
+    while start < s.len() {
+        let mut end = cmp::min(start + MAX_LEAF / 2, s.len());
+        while !s.is_char_boundary(end) {
+            end += 1;
+        }
+        leaves.push(s[start..end].to_owned());
+        start = end;
+    }
+    leaves
+}
+
+/// Split `nodes` into groups of at most `MAX_CHILDREN` nodes.
+fn group_children(nodes: Vec<Node>) -> Vec<Vec<Node>> {
+    let mut groups = Vec::new();
+    let mut group = Vec::new();
+    for node in nodes {
+        if group.len() == MAX_CHILDREN / 2 {
+            groups.push(group);
+            group = Vec::new();
+        }
+        group.push(node);
+    }
+    if !group.is_empty() {
+        groups.push(group);
+    }
+    groups
+}
+
+#[cfg(test)]
+mod test {
+
+    use rope::Rope;
+
+    fn collect(rope: &Rope) -> String {
+        rope.chars_at(0).collect()
+    }
+
+    #[test]
+    fn test_from_str() {
+        let rope = Rope::from("Test\nA\nTest");
+
+        assert_eq!(rope.len(), 11);
+        assert_eq!(rope.line_count(), 3);
+        assert_eq!(collect(&rope), "Test\nA\nTest");
+    }
+
+    #[test]
+    fn test_insert_and_remove() {
+        let mut rope = Rope::from("Test");
+        rope.insert(0, 'ß');
+        rope.insert_str(5, "\n日本");
+
+        assert_eq!(collect(&rope), "ßTest\n日本");
+        assert_eq!(rope.remove(1), Some('T'));
+        assert_eq!(rope.remove_range(4, 6), "\n日");
+        assert_eq!(collect(&rope), "ßest本");
+        assert_eq!(rope.remove(5), None);
+    }
+
+    #[test]
+    fn test_line_index() {
+        let rope = Rope::from("Some\ntest\ncontent");
+
+        assert_eq!(rope.line_of(0), 0);
+        assert_eq!(rope.line_of(4), 0);
+        assert_eq!(rope.line_of(5), 1);
+        assert_eq!(rope.line_of(100), 2);
+        assert_eq!(rope.line_start(2), Some(10));
+        assert_eq!(rope.line_start(3), None);
+        assert_eq!(rope.nth_newline(1), Some(9));
+    }
+
+    #[test]
+    fn test_large_rope() {
+        let line = "a line of text, ünïcödé included\n";
+        let text: String = (0..5000).map(|_| line).collect();
+        let mut rope = Rope::from(&*text);
+        let line_len = line.chars().count();
+
+        assert_eq!(rope.len(), line_len * 5000);
+        assert_eq!(rope.line_count(), 5001);
+        assert_eq!(rope.line_start(4000), Some(line_len * 4000));
+        assert_eq!(rope.line_of(line_len * 2500 + 3), 2500);
+        assert_eq!(rope.char_at(line_len * 1234 + 1), Some(' '));
+
+        // insert enough text to force nodes to split
+        for i in 0..5000 {
+            rope.insert_str(i * (line_len + 1), "x");
+        }
+        assert_eq!(rope.len(), (line_len + 1) * 5000);
+        assert_eq!(rope.line_start(10), Some((line_len + 1) * 10));
+
+        let removed = rope.remove_range(0, rope.len() - 1);
+        assert_eq!(removed.chars().count(), (line_len + 1) * 5000 - 1);
+        assert_eq!(collect(&rope), "\n");
+    }
+
+    #[test]
+    fn test_chars_iterators() {
+        let text: String = (0..3000).map(|i| if i % 7 == 0 { '\n' } else { 'é' }).collect();
+        let rope = Rope::from(&*text);
+
+        let forward: String = rope.chars_at(1500).collect();
+        let expected: String = text.chars().skip(1500).collect();
+        assert_eq!(forward, expected);
+
+        let backward: String = rope.chars_before(1500).collect();
+        let expected: String = text.chars().take(1500).collect::<Vec<_>>().into_iter().rev().collect();
+        assert_eq!(backward, expected);
+
+        assert_eq!(rope.chars_at(3000).next(), None);
+        assert_eq!(rope.chars_before(0).next(), None);
+    }
+}
@@ -0,0 +1,201 @@
+//! Writing buffers out to disk.
+//!
+//! Files are saved atomically where possible: the text is written to a temporary file next to
+//! the destination, which is then renamed over it, so a failed save never leaves a half
+//! written file behind.  When that isn't possible (the directory isn't writable, or the
+//! original's permissions or owner can't be copied to the new file) the file is overwritten in
+//! place instead.
+
+use std::fmt;
+use std::fs::{self, File, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tempdir::TempDir;
+
+/// How many symlinks are followed before giving up and writing to the last one.
+const MAX_LINKS: usize = 32;
+
+/// The reasons saving a buffer can fail.
+#[derive(Debug)]
+pub enum SaveError {
+    /// The buffer has no file to be saved to.
+    NoFileName,
+    /// Writing the text out failed before the file was touched.
+    Write(io::Error),
+    /// Overwriting the file in place failed, so it may have been left incomplete.
+    InPlace(io::Error),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SaveError::NoFileName => write!(f, "No file name"),
+            SaveError::Write(ref e) => write!(f, "Could not write file: {}", e),
+            SaveError::InPlace(ref e) => write!(f, "Could not write file, it may be incomplete: {}", e),
+        }
+    }
+}
+
+/// Save to `path`, using `write` to fill in the new file.
+///
+/// `write` may be called twice, if writing to a temporary file works but it can't be moved
+/// into place.
+pub fn write_file<F>(path: &Path, write: F) -> Result<(), SaveError>
+    where F: Fn(&mut File) -> io::Result<()>
+{
+    let target = resolve_links(path);
+    let original = fs::metadata(&target).ok();
+
+    let dir = match target.parent() {
+        Some(dir) if dir != Path::new("") => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    if let Ok(tmpdir) = TempDir::new_in(&dir, ".iota") {
+        let tmppath = tmpdir.path().join("tmpfile");
+        if let Ok(mut file) = File::create(&tmppath) {
+            write(&mut file).and_then(|_| file.sync_all()).map_err(SaveError::Write)?;
+
+            let copied = match original {
+                Some(ref meta) => copy_metadata(meta, &tmppath).is_ok(),
+                None => true,
+            };
+            if copied && fs::rename(&tmppath, &target).is_ok() {
+                sync_dir(&dir);
+                return Ok(());
+            }
+        }
+    }
+
+    // couldn't replace the file, so overwrite what's there
+    let mut file = File::create(&target).map_err(SaveError::Write)?;
+    write(&mut file).and_then(|_| file.sync_all()).map_err(SaveError::InPlace)
+}
+
+/// Follow symlinks from `path`, to the file which should actually be written.
+fn resolve_links(path: &Path) -> PathBuf {
+    let mut path = path.to_path_buf();
+    for _ in 0..MAX_LINKS {
+        let link = match fs::read_link(&path) {
+            Ok(link) => link,
+            Err(_) => break,
+        };
+        // relative links are relative to the directory containing them
+        path = match path.parent() {
+            Some(dir) => dir.join(link),
+            None => link,
+        };
+    }
+    path
+}
+
+/// Give the file at `path` the permissions and owner described by `meta`.
+fn copy_metadata(meta: &Metadata, path: &Path) -> io::Result<()> {
+    fs::set_permissions(path, meta.permissions())?;
+    copy_owner(meta, path)
+}
+
+#[cfg(unix)]
+fn copy_owner(meta: &Metadata, path: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::MetadataExt;
+    use libc;
+
+    let current = fs::metadata(path)?;
+    if current.uid() == meta.uid() && current.gid() == meta.gid() {
+        return Ok(());
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    if unsafe { libc::chown(c_path.as_ptr(), meta.uid(), meta.gid()) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+fn copy_owner(_meta: &Metadata, _path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Make sure a rename into `dir` survives a crash.  This is best effort, not every platform
+/// lets directories be synced.
+fn sync_dir(dir: &Path) {
+    if cfg!(unix) {
+        let _ = File::open(dir).and_then(|d| d.sync_all());
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+
+    use tempdir::TempDir;
+
+    use save::write_file;
+
+    fn read(path: &::std::path::Path) -> String {
+        let mut text = String::new();
+        File::open(path).unwrap().read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn writes_new_file() {
+        let dir = TempDir::new("iota-save").unwrap();
+        let path = dir.path().join("new.txt");
+
+        write_file(&path, |file| file.write_all(b"hello\n")).unwrap();
+        assert_eq!(read(&path), "hello\n");
+        // nothing but the file itself is left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new("iota-save").unwrap();
+        let path = dir.path().join("script.sh");
+        File::create(&path).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o751)).unwrap();
+
+        write_file(&path, |file| file.write_all(b"#!/bin/sh\n")).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o751);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_through_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let dir = TempDir::new("iota-save").unwrap();
+        let real = dir.path().join("real.txt");
+        let link = dir.path().join("link.txt");
+        File::create(&real).unwrap();
+        symlink("real.txt", &link).unwrap();
+
+        write_file(&link, |file| file.write_all(b"through the link")).unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(read(&real), "through the link");
+    }
+
+    #[test]
+    fn failed_write_leaves_file_alone() {
+        use std::io;
+
+        let dir = TempDir::new("iota-save").unwrap();
+        let path = dir.path().join("keep.txt");
+        File::create(&path).unwrap().write_all(b"original").unwrap();
+
+        let result = write_file(&path, |_| Err(io::Error::new(io::ErrorKind::Other, "disk full")));
+        assert!(result.is_err());
+        assert_eq!(read(&path), "original");
+    }
+}
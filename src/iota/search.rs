@@ -0,0 +1,150 @@
+//! Searching text for matches of a regular expression.
+//!
+//! Matches are given as char indices, as used throughout the buffer, rather
+//! than the byte offsets the regex crate works in.
+
+use regex::{self, Captures, Regex};
+
+/// How the case of letters is matched when searching.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CaseMatching {
+    /// Upper and lower case letters only match themselves.
+    Sensitive,
+    /// Letters match in either case.
+    Insensitive,
+    /// Letters match in either case, unless the pattern has an upper case
+    /// letter in it.
+    Smart,
+}
+
+impl CaseMatching {
+    /// Whether a search for `pattern` ignores case.
+    pub fn ignores_case(&self, pattern: &str) -> bool {
+        match *self {
+            CaseMatching::Sensitive => false,
+            CaseMatching::Insensitive => true,
+            CaseMatching::Smart => !pattern.chars().any(char::is_uppercase),
+        }
+    }
+}
+
+/// Where a search found a match.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Match {
+    /// The first char matched.
+    pub start: usize,
+    /// The char after the last one matched.
+    pub end: usize,
+    /// Whether the search went round the end of the text to find it.
+    pub wrapped: bool,
+}
+
+/// The regex searched for by `pattern`, in which `^` and `$` match at the
+/// start and end of every line.
+pub fn search_regex(pattern: &str, case: CaseMatching) -> Result<Regex, regex::Error> {
+    let flags = if case.ignores_case(pattern) { "(?mi)" } else { "(?m)" };
+    Regex::new(&format!("{}{}", flags, pattern))
+}
+
+/// Every match of `regex` in `text`, as the char indices of their start and
+/// end.  Matches may be empty, such as those of `^$` or `\b`.
+pub fn find_all(regex: &Regex, text: &str) -> Vec<(usize, usize)> {
+    let mut matches = Vec::new();
+    // the matches come in order, so each offset is counted on from the last
+    let (mut byte, mut idx) = (0, 0);
+    for (start, end) in regex.find_iter(text) {
+        idx += text[byte..start].chars().count();
+        let len = text[start..end].chars().count();
+        matches.push((idx, idx + len));
+        idx += len;
+        byte = end;
+    }
+    matches
+}
+
+/// The text `replacement` stands for in place of the match in `captures`.
+///
+/// `$n` or `${n}` stand for the pattern's group `n`, or the whole match when
+/// `n` is 0, `${name}` for a named group and `$$` for a `$`.  Groups which
+/// didn't take part in the match stand for nothing.
+pub fn expand(captures: &Captures, replacement: &str) -> String {
+    let mut expanded = String::new();
+    let mut rest = replacement;
+    while let Some(idx) = rest.find('$') {
+        expanded.push_str(&rest[..idx]);
+        rest = &rest[idx + 1..];
+        let (name, len) = if rest.starts_with('$') {
+            expanded.push('$');
+            rest = &rest[1..];
+            continue;
+        } else if rest.starts_with('{') {
+            match rest.find('}') {
+                Some(end) => (&rest[1..end], end + 1),
+                None => ("", 0),
+            }
+        } else {
+            let len = rest.find(|c: char| !c.is_digit(10)).unwrap_or(rest.len());
+            (&rest[..len], len)
+        };
+        if len == 0 {
+            expanded.push('$');
+            continue;
+        }
+        let group = match name.parse() {
+            Ok(n) => captures.at(n),
+            Err(_) => captures.name(name),
+        };
+        expanded.push_str(group.unwrap_or(""));
+        rest = &rest[len..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use regex::Regex;
+
+    use search::{CaseMatching, expand, find_all, search_regex};
+
+    #[test]
+    fn smart_case_only_ignores_case_in_lower_case_patterns() {
+        assert!(CaseMatching::Smart.ignores_case("foo"));
+        assert!(!CaseMatching::Smart.ignores_case("Foo"));
+        assert!(CaseMatching::Insensitive.ignores_case("Foo"));
+        assert!(!CaseMatching::Sensitive.ignores_case("foo"));
+
+        let text = "Foo foo FOO";
+        let regex = search_regex("foo", CaseMatching::Smart).unwrap();
+        assert_eq!(find_all(&regex, text).len(), 3);
+        let regex = search_regex("Foo", CaseMatching::Smart).unwrap();
+        assert_eq!(find_all(&regex, text), vec![(0, 3)]);
+    }
+
+    #[test]
+    fn matches_are_in_chars() {
+        let regex = search_regex("b+", CaseMatching::Sensitive).unwrap();
+        assert_eq!(find_all(&regex, "äb ëbb"), vec![(1, 2), (4, 6)]);
+
+        // lines are matched on their own
+        let regex = search_regex("^x", CaseMatching::Sensitive).unwrap();
+        assert_eq!(find_all(&regex, "x\nyx\nx"), vec![(0, 1), (5, 6)]);
+
+        // empty matches are kept, to move to
+        let regex = search_regex("^$", CaseMatching::Sensitive).unwrap();
+        assert_eq!(find_all(&regex, "a\n\nä\n"), vec![(2, 2), (5, 5)]);
+    }
+
+    #[test]
+    fn replacements_refer_to_groups() {
+        let regex = Regex::new(r"(?P<word>[a-z]+)(\d)?").unwrap();
+        let captures = regex.captures("abc1").unwrap();
+        assert_eq!(expand(&captures, "${2}${1} $0 ${word}"), "1abc abc1 abc");
+        assert_eq!(expand(&captures, "$1x $$1 $ ${3}"), "abcx $1 $ ");
+
+        let captures = regex.captures("abc").unwrap();
+        assert_eq!(expand(&captures, "<$2>"), "<>");
+    }
+}
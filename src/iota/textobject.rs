@@ -2,12 +2,14 @@ use std::default::Default;
 
 use buffer::Mark;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub enum Kind {
     Char,
+    LineChar,   // a char, not going past either end of the line
     Line(Anchor),
 
     Word(Anchor),
+    Match(String, Anchor),   // a match of a regular expression
     // Sentence(Anchor),
     // Paragraph(Anchor),
 
@@ -20,14 +22,16 @@ impl Kind {
     pub fn with_anchor(&self, anchor: Anchor) -> Kind {
         match *self {
             Kind::Char => Kind::Char,
+            Kind::LineChar => Kind::LineChar,
             Kind::Line(_) => Kind::Line(anchor),
             Kind::Word(_) => Kind::Word(anchor),
+            Kind::Match(ref pattern, _) => Kind::Match(pattern.clone(), anchor),
         }
     }
     pub fn get_anchor(&self) -> Anchor {
         match *self {
-            Kind::Char => Default::default(),
-            Kind::Line(a) | Kind::Word(a) => a,
+            Kind::Char | Kind::LineChar => Default::default(),
+            Kind::Line(a) | Kind::Word(a) | Kind::Match(_, a) => a,
         }
     }
 }
@@ -77,7 +81,7 @@ impl Default for Offset {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct TextObject {
     pub kind: Kind,
     pub offset: Offset
@@ -0,0 +1,189 @@
+//! Persistent undo history.
+//!
+//! When a buffer is saved its undo tree is written to `~/.iota/undo/`, to a file named after a
+//! hash of the buffer's path.  The file also records a hash of the saved text, so the history is
+//! only restored if the file is reopened without having been changed elsewhere in the meantime.
+//!
+//! Undo files hold the text of everything edited, so only their owner may read them.  They are
+//! written to a temporary file which is then renamed into place, so a crash part way through
+//! never leaves a truncated history behind.
+
+use std::env;
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use log::Log;
+
+const HEADER: &'static str = "iota-undo 1";
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// FNV-1a hash of some bytes.  Unlike the std hashers it is guaranteed to give the same result
+/// from one run, or build, to the next.
+pub fn hash_bytes<I: IntoIterator<Item=u8>>(bytes: I) -> u64 {
+    bytes.into_iter().fold(FNV_OFFSET, |hash, byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// Hash of a text, the same as `hash_bytes` of its UTF-8 encoding.
+pub fn hash_text<I: Iterator<Item=char>>(chars: I) -> u64 {
+    let mut buf = [0; 4];
+    let mut hash = FNV_OFFSET;
+    for ch in chars {
+        for &byte in ch.encode_utf8(&mut buf).as_bytes() {
+            hash = (hash ^ byte as u64).wrapping_mul(FNV_PRIME);
+        }
+    }
+    hash
+}
+
+fn undo_dir() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".iota").join("undo"))
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn undo_file_in(dir: &Path, path: &Path) -> PathBuf {
+    let path = canonical(path);
+    dir.join(format!("{:016x}", hash_bytes(path.to_string_lossy().bytes())))
+}
+
+/// Write the history of the file at `path`, whose text hashes to `text_hash`.
+pub fn save(path: &Path, text_hash: u64, log: &Log) -> io::Result<()> {
+    match undo_dir() {
+        Some(dir) => save_in(&dir, path, text_hash, log),
+        None => Ok(()),
+    }
+}
+
+/// Read back the history of the file at `path`.
+///
+/// Returns `None` if there is no history, or if it was saved for a different text.
+pub fn load(path: &Path, text_hash: u64) -> Option<Log> {
+    undo_dir().and_then(|dir| load_in(&dir, path, text_hash))
+}
+
+fn save_in(dir: &Path, path: &Path, text_hash: u64, log: &Log) -> io::Result<()> {
+    use std::io::Write;
+
+    create_private_dir(dir)?;
+    let target = undo_file_in(dir, path);
+    // the process id keeps two editors saving the same file from sharing a temporary file
+    let tmppath = target.with_extension(format!("{}.tmp", process::id()));
+
+    let written = create_private(&tmppath).and_then(|mut file| {
+        writeln!(file, "{}", HEADER)?;
+        writeln!(file, "path {}", canonical(path).display())?;
+        writeln!(file, "hash {:016x}", text_hash)?;
+        log.write_to(&mut file)?;
+        file.sync_all()
+    });
+    match written.and_then(|_| fs::rename(&tmppath, &target)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&tmppath);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    DirBuilder::new().recursive(true).mode(0o700).create(dir)
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    DirBuilder::new().recursive(true).create(dir)
+}
+
+/// Create a new file at `path` which only its owner can read and write.
+#[cfg(unix)]
+fn create_private(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create(true).truncate(true).open(path)
+}
+
+fn load_in(dir: &Path, path: &Path, text_hash: u64) -> Option<Log> {
+    let file = File::open(undo_file_in(dir, path)).ok()?;
+    let mut lines = BufReader::new(file).lines().map(|line| line.unwrap_or_default());
+
+    let expected = vec![
+        HEADER.to_string(),
+        format!("path {}", canonical(path).display()),
+        format!("hash {:016x}", text_hash),
+    ];
+    for line in expected {
+        if lines.next() != Some(line) {
+            return None;
+        }
+    }
+    Log::read_from(lines)
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::path::Path;
+
+    use tempdir::TempDir;
+
+    use log::{Log, Change};
+    use undofile::{save_in, load_in, hash_text};
+
+    fn edited_log() -> Log {
+        let mut log = Log::new();
+        {
+            let mut transaction = log.start(0);
+            transaction.log(Change::Insert(0, 'a'), 1);
+        }
+        log
+    }
+
+    #[test]
+    fn history_is_restored_for_unchanged_text() {
+        let dir = TempDir::new("iota-undo").unwrap();
+        let path = Path::new("some/file.txt");
+        let hash = hash_text("a".chars());
+
+        save_in(dir.path(), path, hash, &edited_log()).unwrap();
+        let mut log = load_in(dir.path(), path, hash).unwrap();
+        assert!(log.undo().is_some());
+    }
+
+    #[test]
+    fn history_is_discarded_when_text_changed() {
+        let dir = TempDir::new("iota-undo").unwrap();
+        let path = Path::new("some/file.txt");
+
+        save_in(dir.path(), path, hash_text("a".chars()), &edited_log()).unwrap();
+        assert!(load_in(dir.path(), path, hash_text("ab".chars())).is_none());
+        assert!(load_in(dir.path(), Path::new("other.txt"), hash_text("a".chars())).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn history_is_only_readable_by_its_owner() {
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new("iota-undo").unwrap();
+        let path = Path::new("some/file.txt");
+        save_in(dir.path(), path, hash_text("a".chars()), &edited_log()).unwrap();
+
+        // only the undo file is left behind, not the one it was written to first
+        let files: Vec<_> = fs::read_dir(dir.path()).unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(fs::metadata(&files[0]).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+}
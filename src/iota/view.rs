@@ -1,22 +1,20 @@
 use std::cmp;
-use std::borrow::Cow;
-use std::path::Path;
-use std::path::PathBuf;
 use std::io::Write;
-use std::fs::{File, rename};
+use std::path::{Path, PathBuf};
 use std::sync::{Mutex, Arc};
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 use rustbox::{Color, RustBox, Style as RustBoxStyle};
-
-use tempdir::TempDir;
-use unicode_width::UnicodeWidthChar;
-
-use buffer::{Buffer, Mark};
-use overlay::{CommandPrompt, Overlay, OverlayType};
+use regex::Regex;
+
+use buffer::{Buffer, Gravity, Mark};
+use command::{LineAddress, LineRange, Operator, ReplaceAnswer, Substitution};
+use registers::{KillRing, Register, Registers};
+use overlay::{CommandPrompt, ExPrompt, FindFile, MessageHistory, Overlay, OverlayType, ReplacePrompt, SearchPrompt, UndoTree};
+use save::{self, SaveError};
+use search::{self, CaseMatching};
 use utils;
 use textobject::{Anchor, TextObject, Kind, Offset};
 
-
 /// A View is an abstract Window (into a Buffer).
 ///
 /// It draws a portion of a Buffer to a `UIBuffer` which in turn is drawn to the
@@ -40,6 +38,15 @@ pub struct View<'v> {
     /// The current View's cursor - a reference into the Buffer
     cursor: Mark,
 
+    /// The other end of the selection from the cursor
+    anchor: Mark,
+
+    /// How the text between `anchor` and `cursor` is selected, if it is
+    selection: Option<Selection>,
+
+    /// Typing into a block, which is copied to the block's other lines when done
+    block_insert: Option<BlockInsert>,
+
     /// Number of lines from the top/bottom of the View after which vertical
     /// scrolling begins.
     threshold: usize,
@@ -47,18 +54,113 @@ pub struct View<'v> {
     /// Message to be displayed in the status bar along with the time it
     /// was displayed.
     message: Option<(String, SystemTime)>,
+
+    /// Every message displayed, oldest first, up to `MAX_MESSAGES` of them.
+    messages: Vec<(String, SystemTime)>,
+
+    /// Text taken by yanks and deletes, to be put back.
+    registers: Registers,
+
+    /// The register chosen for the next yank, delete or put.
+    register: Option<char>,
+
+    /// Text cut or copied by the emacs kill commands.
+    kill_ring: KillRing,
+
+    /// Whether the last command was a kill, which the next one adds to.
+    killing: bool,
+
+    /// Where the text put in by the last command, if it was a yank, lies.
+    yanked: Option<(usize, usize)>,
+
+    /// How searches match the case of letters.
+    search_case: CaseMatching,
+
+    /// The last search made, to be repeated.
+    search: Option<Search>,
+
+    /// Whether the matches of the last search are highlighted.
+    highlight_matches: bool,
+
+    /// Where the cursor was, and the search before, while a search is typed.
+    search_start: Option<(usize, Option<Search>)>,
+
+    /// A substitution asking whether to replace each match.
+    replacing: Option<Replacing>,
+}
+
+/// A search for a pattern.
+#[derive(Clone)]
+struct Search {
+    pattern: String,
+    regex: Regex,
+    backward: bool,
+}
+
+/// A substitution asking whether to replace each match in turn.  Its changes
+/// are all made in one transaction, kept open until it is done.
+struct Replacing {
+    regex: Regex,
+    substitution: Substitution,
+    /// Where to look for the next match from.
+    from: usize,
+    /// The last line to replace matches on, counting from 1.
+    last: usize,
+    /// The match at the cursor, and the text to replace it with.
+    current: Option<(usize, usize, String)>,
+    /// How many matches have been replaced.
+    count: usize,
+    /// Where the last match asked about ended, as an empty match found there
+    /// is passed over, as it is when replacing without asking.
+    previous_end: Option<usize>,
+}
+
+/// How the text between the selection's anchor and the cursor is selected.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Selection {
+    /// Every char from one end to the other, both included.
+    Chars,
+    /// The whole lines from one end to the other.
+    Lines,
+    /// The rectangle with the two ends at opposite corners.
+    Block,
+    /// Every char from one end up to the other, which isn't included, as
+    /// the region between emacs' mark and point.
+    Region,
+}
+
+/// Text being typed into the first line of a block.
+struct BlockInsert {
+    /// Where the typing started.
+    start: usize,
+    /// The block's lines, the first of which is being typed into.
+    first_line: usize,
+    last_line: usize,
+    /// The column the text goes in at on the other lines.
+    column: usize,
+    /// Whether lines too short to reach `column` are padded out, rather than skipped.
+    pad: bool,
 }
 
+/// How many messages are kept for the message history.
+const MAX_MESSAGES: usize = 100;
+
+/// What a line is indented by, the same as a tab typed in insert mode.
+const INDENT: &'static str = "    ";
+
 impl<'v> View<'v> {
 
     pub fn new(buffer: Arc<Mutex<Buffer>>, width: usize, height: usize) -> View<'v> {
         let cursor = Mark::Cursor(0);
+        let anchor = Mark::Anchor(0);
         let top_line = Mark::DisplayMark(0);
 
         {
             let mut b = buffer.lock().unwrap();
 
             b.set_mark(cursor, 0);
+            // text typed at the cursor goes in before it
+            b.set_mark_gravity(cursor, Gravity::Right);
             b.set_mark(top_line, 0);
         }
 
@@ -68,9 +170,23 @@ impl<'v> View<'v> {
             top_line: top_line,
             left_col: 0,
             cursor: cursor,
+            anchor: anchor,
+            selection: None,
+            block_insert: None,
             overlay: None,
             threshold: 5,
             message: None,
+            messages: Vec::new(),
+            registers: Registers::new(),
+            register: None,
+            kill_ring: KillRing::new(),
+            killing: false,
+            yanked: None,
+            search_case: CaseMatching::Sensitive,
+            search: None,
+            highlight_matches: false,
+            search_start: None,
+            replacing: None,
             height: height,
             width: width,
         }
@@ -82,11 +198,23 @@ impl<'v> View<'v> {
         {
             let mut b = buffer.lock().unwrap();
 
-            b.set_mark(self.cursor, 0);
-            b.set_mark(self.top_line, 0);
+            // a buffer shown before keeps its cursor and scroll position
+            if b.get_mark_idx(self.cursor).is_none() {
+                b.set_mark(self.cursor, 0);
+                b.set_mark_gravity(self.cursor, Gravity::Right);
+                b.set_mark(self.top_line, 0);
+            }
         }
 
         self.buffer = buffer;
+        self.selection = None;
+        self.block_insert = None;
+    }
+
+    /// Show `buffer` in place of the current one, which is being closed.
+    pub fn replace_buffer(&mut self, buffer: Arc<Mutex<Buffer>>) {
+        self.set_buffer(buffer);
+        self.last_buffer = None;
     }
 
     pub fn switch_last_buffer(&mut self) {
@@ -142,9 +270,12 @@ impl<'v> View<'v> {
             //        This will fail if for some reason the buffer doesnt have
             //        the top_line mark
             let mut lines = buffer.lines_from(self.top_line).unwrap().take(height);
+            let top = buffer.get_mark_idx(self.top_line).map_or(0, |idx| buffer.line_of(idx));
             for y_position in 0..height {
                 let line = lines.next().unwrap_or_else(Vec::new);
-                draw_line(rb, &line, y_position, self.left_col);
+                let selected = self.selected_columns(&buffer, top + y_position);
+                let matches = self.matched_columns(&line);
+                draw_line(rb, &line, y_position, self.left_col, selected, &matches);
             }
 
         }
@@ -166,7 +297,7 @@ impl<'v> View<'v> {
         let buffer_status = buffer.status_text();
         let mut cursor_status = buffer.get_mark_display_coords(self.cursor).unwrap_or((0,0));
         cursor_status = (cursor_status.0 + 1, cursor_status.1 + 1);
-        let status_text = format!("{} ({}, {})", buffer_status, cursor_status.0, cursor_status.1).into_bytes();
+        let status_text: Vec<char> = format!("{} ({}, {})", buffer_status, cursor_status.0, cursor_status.1).chars().collect();
         let status_text_len = status_text.len();
         let width = self.get_width();
         let height = self.get_height() - 1;
@@ -174,7 +305,7 @@ impl<'v> View<'v> {
 
         for index in 0..width {
             let ch: char = if index < status_text_len {
-                status_text[index] as char
+                status_text[index]
             } else { ' ' };
             rb.print_char(index, height, RustBoxStyle::empty(), Color::Black, Color::Byte(19), ch);
 
@@ -197,7 +328,12 @@ impl<'v> View<'v> {
         let buffer = self.buffer.lock().unwrap();
         if let Some(top_line) = buffer.get_mark_display_coords(self.top_line) {
             if let Some((x, y)) = buffer.get_mark_display_coords(self.cursor) {
-                rb.set_cursor((x - self.left_col) as isize, y as isize - top_line.1 as isize);
+                // the view may have been scrolled away from the cursor
+                if y < top_line.1 || y >= top_line.1 + self.get_height() - 1 {
+                    rb.set_cursor(-1, -1);
+                } else {
+                    rb.set_cursor((x - self.left_col) as isize, y as isize - top_line.1 as isize);
+                }
             }
         }
     }
@@ -207,12 +343,52 @@ impl<'v> View<'v> {
             OverlayType::CommandPrompt => {
                 self.overlay = Some(Box::new(CommandPrompt::new()));
             }
+            OverlayType::ExCommand => {
+                // commands typed while text is selected act on its lines, as in vi
+                let prompt = match self.selection {
+                    Some(Selection::Region) | None => ExPrompt::new(),
+                    Some(_) => ExPrompt::with_data("'<,'>"),
+                };
+                self.overlay = Some(Box::new(prompt));
+            }
+            OverlayType::UndoTree => {
+                let states = self.buffer.lock().unwrap().log.states();
+                self.overlay = Some(Box::new(UndoTree::new(states)));
+            }
+            OverlayType::MessageHistory => {
+                self.overlay = Some(Box::new(MessageHistory::new(self.messages.clone())));
+            }
+            OverlayType::FindFile => {
+                let base = match self.buffer.lock().unwrap().file_path.as_ref().and_then(|p| p.parent()) {
+                    Some(dir) if dir != Path::new("") => dir.to_path_buf(),
+                    _ => PathBuf::from("."),
+                };
+                self.overlay = Some(Box::new(FindFile::new(base)));
+            }
+            OverlayType::Search => {
+                self.overlay = Some(Box::new(SearchPrompt::new(false)));
+            }
+            OverlayType::SearchBackward => {
+                self.overlay = Some(Box::new(SearchPrompt::new(true)));
+            }
+            OverlayType::ConfirmReplace => {
+                if let Some((_, _, ref replacement)) = self.replacing.as_ref().and_then(|r| r.current.as_ref()) {
+                    self.overlay = Some(Box::new(ReplacePrompt::new(replacement)));
+                }
+            }
+            // the view only knows about its own buffer, so the editor builds this one
+            OverlayType::BufferList => {}
         }
     }
 
     /// Display the given message
     pub fn show_message(&mut self, message: String) {
-        self.message = Some((message, SystemTime::now()));
+        let now = SystemTime::now();
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.remove(0);
+        }
+        self.messages.push((message.clone(), now));
+        self.message = Some((message, now));
     }
 
     /// Clear the currently displayed message if it has been there for 5 or more seconds
@@ -234,6 +410,7 @@ impl<'v> View<'v> {
     }
 
     pub fn move_mark(&mut self, mark: Mark, object: TextObject) {
+        let object = self.with_search_case(object);
         self.buffer.lock().unwrap().set_mark_to_object(mark, object);
         self.maybe_move_screen();
     }
@@ -283,187 +460,2005 @@ impl<'v> View<'v> {
 
     // Delete chars from the first index of object to the last index of object
     pub fn delete_object(&mut self, object: TextObject) {
+        let object = self.with_search_case(object);
         self.buffer.lock().unwrap().remove_object(object);
     }
 
     pub fn delete_from_mark_to_object(&mut self, mark: Mark, object: TextObject) {
+        let object = self.with_search_case(object);
         let mut buffer = self.buffer.lock().unwrap();
-        if let Some(mark_pos) = buffer.get_object_index(object) {
+        if let Some(mark_pos) = buffer.get_object_index(object.clone()) {
             if let Some(midx) = buffer.get_mark_idx(mark) {
-                buffer.remove_from_mark_to_object(mark, object);
+                if let Some(chars) = buffer.remove_from_mark_to_object(mark, object) {
+                    let text = chars.into_iter().collect();
+                    self.registers.deleted_chars(text, mark_pos.absolute < midx);
+                }
                 buffer.set_mark(mark, cmp::min(mark_pos.absolute, midx));
             }
         }
     }
 
-    /// Insert a chacter into the buffer & update cursor position accordingly.
-    pub fn insert_char(&mut self, ch: char) {
-        self.buffer.lock().unwrap().insert_char(self.cursor, ch as u8);
-        // NOTE: the last param to char_width here may not be correct
-        if let Some(ch_width) = utils::char_width(ch, false, 4, 1) {
-            let obj = TextObject {
-                kind: Kind::Char,
-                offset: Offset::Forward(ch_width, Mark::Cursor(0))
+    /// Apply `operator` to the text from `mark` to `object`.
+    ///
+    /// Moving up or down (`dj`, `yk`) takes in every line moved over, whole.
+    pub fn operate(&mut self, operator: Operator, mark: Mark, object: TextObject) {
+        let object = self.with_search_case(object);
+        let (start, end, linewise) = {
+            let buffer = self.buffer.lock().unwrap();
+            let from = match buffer.get_mark_idx(mark) {
+                Some(idx) => idx,
+                None => return,
+            };
+            let to = match buffer.get_object_index(object.clone()) {
+                Some(pos) => pos.absolute,
+                None => return,
+            };
+            let (start, end) = (cmp::min(from, to), cmp::max(from, to));
+            match object.kind {
+                Kind::Line(Anchor::Same) => {
+                    let (start, end) = buffer.line_span(buffer.line_of(start), buffer.line_of(end));
+                    (start, end, true)
+                }
+                _ => (start, end, false),
+            }
+        };
+        self.apply_operator(operator, start, end, linewise);
+    }
+
+    /// Apply `operator` to `count` whole lines, starting with the cursor's.
+    pub fn operate_on_lines(&mut self, operator: Operator, count: usize) {
+        let (start, end) = {
+            let buffer = self.buffer.lock().unwrap();
+            let line = match buffer.get_mark_idx(self.cursor) {
+                Some(idx) => buffer.line_of(idx),
+                None => return,
             };
-            self.move_mark(Mark::Cursor(0), obj)
+            buffer.line_span(line, line + cmp::max(count, 1) - 1)
+        };
+        self.apply_operator(operator, start, end, true);
+    }
+
+    fn apply_operator(&mut self, operator: Operator, start: usize, end: usize, linewise: bool) {
+        match operator {
+            Operator::Indent => self.indent_lines(start, end, true),
+            Operator::Outdent => self.indent_lines(start, end, false),
+            Operator::ToggleCase => self.toggle_case(start, end),
+            Operator::Delete | Operator::Change | Operator::Yank => self.keep_range(operator, start, end, linewise),
         }
     }
 
-    pub fn undo(&mut self) {
+    /// Keep the text from `start` to `end` in a register for `operator`, which
+    /// deletes it unless it is a yank.
+    fn keep_range(&mut self, operator: Operator, start: usize, end: usize, linewise: bool) {
+        let mut text = {
+            let mut buffer = self.buffer.lock().unwrap();
+            let text = buffer.text_range(start, end);
+            let cursor = match operator {
+                Operator::Delete => {
+                    // the last line has no newline of its own, so take the one before it
+                    let start = if linewise && !text.ends_with('\n') && start > 0 { start - 1 } else { start };
+                    if end > start {
+                        buffer.remove_range(start, end);
+                    }
+                    if linewise {
+                        // stay off the empty line after a final newline
+                        let last = cmp::max(buffer.line_count(), 1) - 1;
+                        let line = cmp::min(buffer.line_of(start), last);
+                        buffer.line_span(line, line).0
+                    } else {
+                        start
+                    }
+                }
+                Operator::Change => {
+                    // changed lines leave an empty line behind to type into
+                    let end = if linewise && text.ends_with('\n') { end - 1 } else { end };
+                    if end > start {
+                        buffer.remove_range(start, end);
+                    }
+                    start
+                }
+                // a yank leaves the text where it is
+                _ => start,
+            };
+            buffer.set_mark(self.cursor, cursor);
+            text
+        };
+        if linewise && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        self.keep(operator, Register::new(text, linewise));
+        self.maybe_move_screen();
+    }
+
+    /// Indent, or unindent, every line from the one containing `start` to the
+    /// one containing the last char before `end`.
+    fn indent_lines(&mut self, start: usize, end: usize, indent: bool) {
         {
             let mut buffer = self.buffer.lock().unwrap();
-            let point = if let Some(transaction) = buffer.undo() { transaction.end_point }
-                        else { return; };
-            buffer.set_mark(self.cursor, point);
+            let first = buffer.line_of(start);
+            let last = buffer.line_of(cmp::max(end, start + 1) - 1);
+            buffer.start_transaction();
+            for line in first..last + 1 {
+                let (line_start, line_end) = buffer.line_span(line, line);
+                let text = buffer.text_range(line_start, line_end);
+                if indent {
+                    // blank lines are left alone
+                    if !text.trim().is_empty() {
+                        buffer.insert_string_at(line_start, INDENT);
+                    }
+                } else {
+                    let width = if text.starts_with('\t') {
+                        1
+                    } else {
+                        text.chars().take(INDENT.len()).take_while(|&c| c == ' ').count()
+                    };
+                    if width > 0 {
+                        buffer.remove_range(line_start, line_start + width);
+                    }
+                }
+            }
+            buffer.commit_transaction();
+
+            // leave the cursor at the first line's text
+            let (line_start, line_end) = buffer.line_span(first, first);
+            let indentation = buffer.text_range(line_start, line_end).chars()
+                                    .take_while(|&c| c == ' ' || c == '\t').count();
+            buffer.set_mark(self.cursor, line_start + indentation);
         }
         self.maybe_move_screen();
     }
 
-    pub fn redo(&mut self) {
+    /// Swap the case of the letters from `start` to `end`.
+    fn toggle_case(&mut self, start: usize, end: usize) {
         {
             let mut buffer = self.buffer.lock().unwrap();
-            let point = if let Some(transaction) = buffer.redo() { transaction.end_point }
-                        else { return; };
-            buffer.set_mark(self.cursor, point);
+            let text = buffer.text_range(start, end);
+            let toggled = toggle_case(&text);
+            if toggled != text {
+                buffer.replace_range(start, end, &toggled);
+            }
+            buffer.set_mark(self.cursor, start);
         }
         self.maybe_move_screen();
     }
 
-    fn save_buffer(&mut self) {
-        let buffer = self.buffer.lock().unwrap();
-        let path = match buffer.file_path {
-            Some(ref p) => Cow::Borrowed(p),
-            None => {
-                // NOTE: this should never happen, as the file path
-                // should have been set inside the try_save_buffer method.
-                //
-                // If this runs, it probably means save_buffer has been called
-                // directly, rather than try_save_buffer.
-                //
-                // TODO: ask the user to submit a bug report on how they hit this.
-                Cow::Owned(PathBuf::from("untitled"))
-            },
-        };
-        let tmpdir = match TempDir::new_in(&Path::new("."), "iota") {
-            Ok(d) => d,
-            Err(e) => panic!("file error: {}", e)
-        };
-
-        let tmppath = tmpdir.path().join(Path::new("tmpfile"));
-        let mut file = match File::create(&tmppath) {
-            Ok(f) => f,
-            Err(e) => {
-                panic!("file error: {}", e)
+    /// Start selecting text, from the cursor, or change how the text is
+    /// selected if a selection is already being made.
+    pub fn select(&mut self, selection: Selection) {
+        if self.selection.is_none() {
+            let mut buffer = self.buffer.lock().unwrap();
+            if let Some(idx) = buffer.get_mark_idx(self.cursor) {
+                buffer.set_mark(self.anchor, idx);
             }
-        };
+        }
+        self.selection = Some(selection);
+    }
+
+    /// Stop selecting text.
+    pub fn clear_selection(&mut self) {
+        self.selection = None;
+    }
+
+    /// Move the cursor to the other end of the selection.
+    pub fn swap_selection_ends(&mut self) {
+        {
+            let mut buffer = self.buffer.lock().unwrap();
+            let (anchor, cursor) = match (buffer.get_mark_idx(self.anchor), buffer.get_mark_idx(self.cursor)) {
+                (Some(anchor), Some(cursor)) => (anchor, cursor),
+                _ => return,
+            };
+            buffer.set_mark(self.anchor, cursor);
+            buffer.set_mark(self.cursor, anchor);
+        }
+        self.maybe_move_screen();
+    }
+
+    /// The first and last selected chars, in order.
+    fn selection_ends(&self, buffer: &Buffer) -> Option<(usize, usize)> {
+        let anchor = buffer.get_mark_idx(self.anchor)?;
+        let cursor = buffer.get_mark_idx(self.cursor)?;
+        Some((cmp::min(anchor, cursor), cmp::max(anchor, cursor)))
+    }
 
-        //TODO (lee): Is iteration still necessary in this format?
-        for line in buffer.lines() {
-            let result = file.write_all(&*line);
+    /// The first and last lines of the selected block, and the columns from its
+    /// left edge up to just past its right edge, as shown on the screen, so that
+    /// tabs and wide chars take up all the columns they cover.
+    fn block_bounds(&self, buffer: &Buffer) -> Option<(usize, usize, usize, usize)> {
+        let anchor = buffer.get_mark_idx(self.anchor)?;
+        let cursor = buffer.get_mark_idx(self.cursor)?;
+        let (anchor_left, anchor_right) = columns_of(buffer, anchor);
+        let (cursor_left, cursor_right) = columns_of(buffer, cursor);
+        let (anchor_line, cursor_line) = (buffer.line_of(anchor), buffer.line_of(cursor));
+        Some((cmp::min(anchor_line, cursor_line), cmp::max(anchor_line, cursor_line),
+              cmp::min(anchor_left, cursor_left), cmp::max(anchor_right, cursor_right)))
+    }
 
-            if result.is_err() {
-                // TODO(greg): figure out what to do here.
-                panic!("Something went wrong while writing the file");
+    /// The columns of `line` which are selected, from the first up to just past
+    /// the last.
+    fn selected_columns(&self, buffer: &Buffer, line: usize) -> Option<(usize, usize)> {
+        let selection = self.selection?;
+        let (line_start, line_end) = buffer.line_span(line, line);
+        match selection {
+            Selection::Chars => {
+                let (start, end) = self.selection_ends(buffer)?;
+                // the char under the cursor is selected too
+                let end = end + 1;
+                if end <= line_start || start >= line_end {
+                    return None;
+                }
+                Some((cmp::max(start, line_start) - line_start, cmp::min(end, line_end) - line_start))
+            }
+            Selection::Region => {
+                let (start, end) = self.selection_ends(buffer)?;
+                if end <= line_start || start >= line_end {
+                    return None;
+                }
+                Some((cmp::max(start, line_start) - line_start, cmp::min(end, line_end) - line_start))
+            }
+            Selection::Lines => {
+                let (start, end) = self.selection_ends(buffer)?;
+                if line < buffer.line_of(start) || line > buffer.line_of(end) {
+                    return None;
+                }
+                Some((0, line_end - line_start))
+            }
+            Selection::Block => {
+                let (first, last, left, right) = self.block_bounds(buffer)?;
+                if line < first || line > last {
+                    return None;
+                }
+                let (start, end) = block_span(buffer, line, left, right)?;
+                Some((start - line_start, end - line_start))
             }
         }
+    }
 
-        if let Err(e) = rename(&tmppath, &*path) {
-            panic!("file error: {}", e);
+    /// The columns of `line` matched by the last search, if they are being
+    /// highlighted.
+    fn matched_columns(&self, line: &[char]) -> Vec<(usize, usize)> {
+        match self.search {
+            Some(ref search) if self.highlight_matches => {
+                let text: String = line.iter().take_while(|&&c| c != '\n').cloned().collect();
+                // there is nothing to show of an empty match
+                search::find_all(&search.regex, &text).into_iter().filter(|&(start, end)| start < end).collect()
+            }
+            _ => Vec::new(),
         }
     }
 
-    pub fn try_save_buffer(&mut self) {
-        let mut should_save = false;
-        {
+    /// Apply `operator` to the selected text, and stop selecting.
+    pub fn operate_on_selection(&mut self, operator: Operator) {
+        let selection = match self.selection.take() {
+            Some(selection) => selection,
+            None => return,
+        };
+        if selection == Selection::Block {
+            return self.operate_on_block(operator);
+        }
+        let (start, end, linewise) = {
             let buffer = self.buffer.lock().unwrap();
+            let (start, end) = match self.selection_ends(&buffer) {
+                Some(ends) => ends,
+                None => return,
+            };
+            match selection {
+                Selection::Lines => {
+                    let (start, end) = buffer.line_span(buffer.line_of(start), buffer.line_of(end));
+                    (start, end, true)
+                }
+                Selection::Region => (start, end, false),
+                _ => (start, cmp::min(end + 1, buffer.len() - 1), false),
+            }
+        };
+        self.apply_operator(operator, start, end, linewise);
+    }
+
+    fn operate_on_block(&mut self, operator: Operator) {
+        let (first, last, left, right) = match self.block_bounds(&self.buffer.lock().unwrap()) {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        match operator {
+            Operator::Indent | Operator::Outdent => {
+                let (start, end) = self.buffer.lock().unwrap().line_span(first, last);
+                return self.indent_lines(start, end, operator == Operator::Indent);
+            }
+            _ => {}
+        }
 
-            match buffer.file_path {
-                Some(_) => { should_save = true; }
-                None => {
-                    self.message = Some(("No file name".into(), SystemTime::now()));
+        let (rows, cursor) = {
+            let mut buffer = self.buffer.lock().unwrap();
+            buffer.start_transaction();
+            let mut rows = Vec::new();
+            // work upwards, so that changing a line doesn't move the ones still to do
+            for line in (first..last + 1).rev() {
+                let (start, end) = match block_span(&buffer, line, left, right) {
+                    Some(span) => span,
+                    None => {
+                        rows.push(String::new());
+                        continue;
+                    }
+                };
+                let text = buffer.text_range(start, end);
+                match operator {
+                    Operator::Delete | Operator::Change => { buffer.remove_range(start, end); }
+                    Operator::ToggleCase => { buffer.replace_range(start, end, &toggle_case(&text)); }
+                    _ => {}
                 }
+                rows.push(text);
+            }
+            buffer.commit_transaction();
+            rows.reverse();
+
+            let (cursor, _) = index_at_column(&buffer, first, left);
+            buffer.set_mark(self.cursor, cursor);
+            (rows, cursor)
+        };
+
+        match operator {
+            Operator::Delete | Operator::Yank => {
+                self.keep(operator, Register::new(rows.join("\n"), false));
+            }
+            Operator::Change => {
+                self.keep(operator, Register::new(rows.join("\n"), false));
+                self.block_insert = Some(BlockInsert {
+                    start: cursor,
+                    first_line: first,
+                    last_line: last,
+                    column: left,
+                    pad: false,
+                });
+            }
+            _ => {}
+        }
+        self.maybe_move_screen();
+    }
+
+    /// Start typing into the first line of the selected block, at its left edge
+    /// or just past its right edge when `append` is set.  What is typed goes
+    /// into the block's other lines too, once `finish_block_insert` is called.
+    pub fn insert_in_block(&mut self, append: bool) {
+        {
+            let mut buffer = self.buffer.lock().unwrap();
+            let (first, last, left, right) = match self.block_bounds(&buffer) {
+                Some(bounds) => bounds,
+                None => return,
+            };
+            let column = if append { right } else { left };
+            let (idx, col) = index_at_column(&buffer, first, column);
+            let start = if col < column && append {
+                // appending pads a short line out to the block
+                buffer.insert_string_at(idx, &" ".repeat(column - col));
+                idx + column - col
+            } else {
+                idx
+            };
+            buffer.set_mark(self.cursor, start);
+            self.block_insert = Some(BlockInsert {
+                start: start,
+                first_line: first,
+                last_line: last,
+                column: column,
+                pad: append,
+            });
+        }
+        self.selection = None;
+        self.maybe_move_screen();
+    }
+
+    /// How the text between the anchor and the cursor is selected, if it is.
+    pub fn selection(&self) -> Option<Selection> {
+        self.selection
+    }
+
+    /// The index of the char shown at column `x` of row `y` of the screen, or
+    /// of the end of the line shown there if `x` is past it.  Rows below the
+    /// end of the buffer are taken as its last line.
+    fn index_at(&self, buffer: &Buffer, x: usize, y: usize) -> Option<usize> {
+        if y >= self.get_height() - 1 {
+            return None;
+        }
+        let top = buffer.line_of(buffer.get_mark_idx(self.top_line)?);
+        let last = buffer.line_of(buffer.len() - 1);
+        let (start, end) = buffer.line_span(cmp::min(top + y, last), cmp::min(top + y, last));
+
+        let target = self.left_col + x;
+        let mut col = 0;
+        for idx in start..end {
+            let ch = match buffer.char_at(idx) {
+                Some('\n') | None => return Some(idx),
+                Some(ch) => ch,
+            };
+            col += utils::char_width(ch, false, 4, col).unwrap_or(1);
+            if col > target {
+                return Some(idx);
             }
         }
+        Some(end)
+    }
 
-        if should_save {
-            self.save_buffer();
+    /// Move the cursor to the char shown at `x`, `y` on the screen, and stop
+    /// selecting text.
+    pub fn click(&mut self, x: usize, y: usize) {
+        {
             let mut buffer = self.buffer.lock().unwrap();
-            buffer.dirty = false;
+            match self.index_at(&buffer, x, y) {
+                Some(idx) => buffer.set_mark(self.cursor, idx),
+                None => return,
+            }
         }
+        self.selection = None;
+        self.maybe_move_screen();
     }
 
-    /// Whether or not the current buffer has unsaved changes
-    pub fn buffer_is_dirty(&mut self) -> bool {
-        self.buffer.lock().unwrap().dirty
+    /// Move the cursor to the char shown at `x`, `y` on the screen, keeping
+    /// the other end of the selection where it is.
+    pub fn drag_to(&mut self, x: usize, y: usize) {
+        {
+            let mut buffer = self.buffer.lock().unwrap();
+            match self.index_at(&buffer, x, y) {
+                Some(idx) => buffer.set_mark(self.cursor, idx),
+                None => return,
+            }
+        }
+        self.maybe_move_screen();
     }
 
-}
+    /// Select the word shown at `x`, `y` on the screen, as `selection`, or the
+    /// char there if it isn't part of a word.
+    pub fn select_word_at(&mut self, x: usize, y: usize, selection: Selection) {
+        {
+            let mut buffer = self.buffer.lock().unwrap();
+            let idx = match self.index_at(&buffer, x, y) {
+                Some(idx) => idx,
+                None => return,
+            };
+            let is_word = |c: Option<char>| c.map_or(false, |c| c.is_alphanumeric() || c == '_');
+            let (mut start, mut end) = (idx, idx + 1);
+            if is_word(buffer.char_at(idx)) {
+                while start > 0 && is_word(buffer.char_at(start - 1)) {
+                    start -= 1;
+                }
+                while is_word(buffer.char_at(end)) {
+                    end += 1;
+                }
+            }
+            end = cmp::min(end, buffer.len() - 1);
+            buffer.set_mark(self.anchor, start);
+            // the region leaves out the char under the cursor, which the
+            // other selections take in
+            let cursor = if selection == Selection::Region { end } else { cmp::max(start, end.saturating_sub(1)) };
+            buffer.set_mark(self.cursor, cursor);
+        }
+        self.selection = Some(selection);
+        self.maybe_move_screen();
+    }
 
-pub fn draw_line(rb: &mut RustBox, line: &[u8], idx: usize, left: usize) {
-    let width = rb.width() - 1;
-    let mut x = 0;
+    /// Scroll the view by `lines`, down when positive and up when negative,
+    /// without moving the cursor.
+    pub fn scroll(&mut self, lines: isize) {
+        let mut buffer = self.buffer.lock().unwrap();
+        let top = match buffer.get_mark_idx(self.top_line) {
+            Some(idx) => buffer.line_of(idx) as isize,
+            None => return,
+        };
+        let last = buffer.line_of(buffer.len() - 1) as isize;
+        let line = cmp::max(0, cmp::min(top + lines, last)) as usize;
+        let (start, _) = buffer.line_span(line, line);
+        buffer.set_mark(self.top_line, start);
+    }
 
-    for ch in line.iter().skip(left) {
-        let ch = *ch as char;
-        match ch {
-            '\t' => {
-                let w = 4 - x % 4;
-                for _ in 0..w {
-                    rb.print_char(x, idx, RustBoxStyle::empty(), Color::White, Color::Black, ' ');
-                    x += 1;
+    /// Set how searches match the case of letters, which applies to the last
+    /// search as well.
+    pub fn set_search_case(&mut self, case: CaseMatching) {
+        self.search_case = case;
+        let search = self.search.take();
+        self.search = search.and_then(|search| self.new_search(&search.pattern, search.backward).ok());
+    }
+
+    /// `object`, made to match case the way searches do if it is a match of a
+    /// pattern.
+    fn with_search_case(&self, object: TextObject) -> TextObject {
+        let kind = match object.kind {
+            Kind::Match(ref pattern, anchor) if self.search_case.ignores_case(pattern) => {
+                Kind::Match(format!("(?i){}", pattern), anchor)
+            }
+            _ => return object,
+        };
+        TextObject { kind: kind, offset: object.offset }
+    }
+
+    fn new_search(&self, pattern: &str, backward: bool) -> Result<Search, String> {
+        match search::search_regex(pattern, self.search_case) {
+            Ok(regex) => Ok(Search { pattern: pattern.to_string(), regex: regex, backward: backward }),
+            Err(e) => Err(format!("Invalid pattern: {}", e)),
+        }
+    }
+
+    /// Move the cursor to the first match of `pattern` from where the search
+    /// being typed started, or back there if there is none.
+    pub fn search_preview(&mut self, pattern: &str, backward: bool) {
+        if self.search_start.is_none() {
+            let cursor = self.buffer.lock().unwrap().get_mark_idx(self.cursor).unwrap_or(0);
+            self.search_start = Some((cursor, self.search.clone()));
+        }
+        let origin = match self.search_start {
+            Some((origin, ref previous)) => {
+                if pattern.is_empty() {
+                    self.search = previous.clone();
+                    self.highlight_matches = false;
                 }
+                origin
             }
-            '\n' => {}
-            _ => {
-                rb.print_char(x, idx, RustBoxStyle::empty(), Color::White, Color::Black, ch);
-                x += UnicodeWidthChar::width(ch).unwrap_or(1);
+            None => return,
+        };
+        if !pattern.is_empty() {
+            // the pattern may not make sense until more is typed
+            if let Ok(search) = self.new_search(pattern, backward) {
+                self.search = Some(search);
+                self.highlight_matches = true;
             }
         }
-        if x >= width {
-            break;
+
+        let found = if pattern.is_empty() { None } else { self.find_match(origin, false) };
+        let idx = found.map_or(origin, |m| m.start);
+        self.buffer.lock().unwrap().set_mark(self.cursor, idx);
+        self.maybe_move_screen();
+    }
+
+    /// Search for `pattern`, finishing any search being typed.  An empty
+    /// pattern repeats the last search.
+    pub fn search(&mut self, pattern: &str, backward: bool) {
+        let (origin, previous) = match self.search_start.take() {
+            Some(start) => start,
+            None => {
+                let cursor = self.buffer.lock().unwrap().get_mark_idx(self.cursor).unwrap_or(0);
+                (cursor, self.search.clone())
+            }
+        };
+        let search = if pattern.is_empty() {
+            previous.clone().map(|search| Search { backward: backward, ..search })
+                .ok_or_else(|| String::from("No previous search"))
+        } else {
+            self.new_search(pattern, backward)
+        };
+        match search {
+            Ok(search) => self.search = Some(search),
+            Err(message) => {
+                self.search = previous;
+                self.buffer.lock().unwrap().set_mark(self.cursor, origin);
+                return self.show_message(message);
+            }
         }
+        self.highlight_matches = true;
+        self.move_to_match(origin, false);
     }
 
-    // Replace any cells after end of line with ' '
-    while x < width {
-        rb.print_char(x, idx, RustBoxStyle::empty(), Color::White, Color::Black, ' ');
-        x += 1;
+    /// Give up the search being typed, going back to where it started.
+    pub fn cancel_search(&mut self) {
+        if let Some((origin, previous)) = self.search_start.take() {
+            self.search = previous;
+            self.highlight_matches = false;
+            self.buffer.lock().unwrap().set_mark(self.cursor, origin);
+            self.maybe_move_screen();
+        }
     }
 
-    // If the line is too long to fit on the screen, show an indicator
-    let indicator = if line.len() > width + left { '→' } else { ' ' };
-    rb.print_char(width, idx, RustBoxStyle::empty(), Color::White, Color::Black, indicator);
-}
+    /// Move to the next match of the last search, or the previous one when
+    /// `reverse` is set.
+    pub fn search_next(&mut self, reverse: bool) {
+        let cursor = match self.buffer.lock().unwrap().get_mark_idx(self.cursor) {
+            Some(idx) => idx,
+            None => return,
+        };
+        if self.search.is_none() {
+            return self.show_message(String::from("No previous search"));
+        }
+        self.highlight_matches = true;
+        self.move_to_match(cursor, reverse);
 
-#[cfg(test)]
-mod tests {
+        // a search being typed carries on from the match moved to
+        let moved_to = self.buffer.lock().unwrap().get_mark_idx(self.cursor);
+        if let (Some(&mut (ref mut origin, _)), Some(idx)) = (self.search_start.as_mut(), moved_to) {
+            *origin = idx;
+        }
+    }
 
-    use std::sync::{Arc, Mutex};
-    use std::rc::Rc;
+    /// Move to the next match of the last search going backward when `backward`
+    /// is set, and forward otherwise, and repeat it that way from now on.
+    pub fn search_again(&mut self, backward: bool) {
+        if let Some(ref mut search) = self.search {
+            search.backward = backward;
+        }
+        self.search_next(false);
+    }
 
-    use view::View;
-    use buffer::Buffer;
+    /// Stop highlighting the matches of the last search.
+    pub fn clear_search(&mut self) {
+        self.highlight_matches = false;
+    }
 
-    fn setup_view(testcase: &'static str) -> View {
-        let buffer = Arc::new(Mutex::new(Buffer::new()));
-        let mut view = View::new(buffer.clone(), 50, 50);
-        for ch in testcase.chars() {
-            view.insert_char(ch);
+    /// The next match of the last search from `from`, going the other way
+    /// when `reverse` is set.
+    fn find_match(&self, from: usize, reverse: bool) -> Option<search::Match> {
+        let search = self.search.as_ref()?;
+        let backward = search.backward != reverse;
+        let buffer = self.buffer.lock().unwrap();
+        // when there is nothing more that way, go round from the other end
+        let found = buffer.find_match(&search.regex, Some(from), 1, backward).map(|found| (found, false))
+            .or_else(|| buffer.find_match(&search.regex, None, 1, backward).map(|found| (found, true)));
+        found.map(|((start, end), wrapped)| search::Match { start: start, end: end, wrapped: wrapped })
+    }
+
+    /// Move the cursor to the next match of the last search from `from`,
+    /// saying so if the search went round the end of the buffer.
+    fn move_to_match(&mut self, from: usize, reverse: bool) {
+        let (pattern, backward) = match self.search {
+            Some(ref search) => (search.pattern.clone(), search.backward != reverse),
+            None => return,
+        };
+        match self.find_match(from, reverse) {
+            Some(found) => {
+                self.buffer.lock().unwrap().set_mark(self.cursor, found.start);
+                if found.wrapped {
+                    let end = if backward { "bottom" } else { "top" };
+                    self.show_message(format!("Search wrapped around to the {}", end));
+                }
+            }
+            None => self.show_message(format!("Pattern not found: {}", pattern)),
         }
+        self.maybe_move_screen();
+    }
 
-        let mut buffer = buffer.lock().unwrap();
-        buffer.set_mark(view.cursor, 0);
-        view
+    /// Whether text being typed will be copied into the lines of a block.
+    pub fn in_block_insert(&self) -> bool {
+        self.block_insert.is_some()
     }
 
-    #[test]
-    fn test_insert_char() {
-        let mut view = setup_view("test\nsecond");
-        view.insert_char('t');
+    /// Copy the text typed since `insert_in_block` into the block's other lines.
+    ///
+    /// Only text typed on the first line, without moving off it, is copied.
+    pub fn finish_block_insert(&mut self) {
+        let insert = match self.block_insert.take() {
+            Some(insert) => insert,
+            None => return,
+        };
+        let mut buffer = self.buffer.lock().unwrap();
+        let cursor = match buffer.get_mark_idx(self.cursor) {
+            Some(idx) => idx,
+            None => return,
+        };
+        if cursor <= insert.start || buffer.line_of(cursor) != buffer.line_of(insert.start) {
+            return;
+        }
+        let text = buffer.text_range(insert.start, cursor);
+
+        buffer.start_transaction();
+        for line in insert.first_line + 1..insert.last_line + 1 {
+            let (_, line_end) = content_span(&buffer, line);
+            let (idx, col) = index_at_column(&buffer, line, insert.column);
+            // inserting skips lines too short to reach into the block
+            if idx < line_end || (insert.pad && col == insert.column) {
+                buffer.insert_string_at(idx, &text);
+            } else if insert.pad {
+                let padding = " ".repeat(insert.column - col);
+                buffer.insert_string_at(line_end, &format!("{}{}", padding, text));
+            }
+        }
+        buffer.commit_transaction();
+    }
 
-        {
-            let mut buffer = view.buffer.lock().unwrap();
-            assert_eq!(buffer.lines().next().unwrap(), b"ttest\n");
+    /// Choose the register for the next yank, delete or put, which otherwise
+    /// uses the unnamed register.
+    pub fn use_register(&mut self, name: char) {
+        if Registers::is_register(name) {
+            self.register = Some(name);
+        } else {
+            self.show_message(format!("Unknown register: {}", name));
+        }
+    }
+
+    /// Go back to using the unnamed register.
+    pub fn clear_register(&mut self) {
+        self.register = None;
+    }
+
+    /// Keep text taken by `operator` in the chosen register.
+    fn keep(&mut self, operator: Operator, register: Register) {
+        let kept = match operator {
+            Operator::Yank => self.registers.yanked(self.register, register),
+            _ => self.registers.deleted(self.register, register),
+        };
+        if let Err(e) = kept {
+            self.show_message(e.to_string());
+        }
+    }
+
+    /// Note that a run of typing has finished, so that it can be put back from
+    /// the `.` register.
+    pub fn finish_insert(&mut self) {
+        self.registers.finish_insert();
+    }
+
+    /// Set emacs' mark at the cursor, starting a region which runs from there
+    /// to wherever the cursor moves.
+    pub fn set_region_mark(&mut self) {
+        self.selection = None;
+        self.select(Selection::Region);
+        self.show_message(String::from("Mark set"));
+    }
+
+    /// Cut the region into the kill ring, or just copy it when `copy` is set,
+    /// and stop selecting.
+    pub fn kill_region(&mut self, copy: bool) {
+        if self.selection != Some(Selection::Region) {
+            return self.show_message(String::from("The mark is not set now, so there is no region"));
         }
+        self.selection = None;
+        let (text, before) = {
+            let mut buffer = self.buffer.lock().unwrap();
+            let cursor = match buffer.get_mark_idx(self.cursor) {
+                Some(idx) => idx,
+                None => return,
+            };
+            let (start, end) = match self.selection_ends(&buffer) {
+                Some(ends) => ends,
+                None => return,
+            };
+            let text = buffer.text_range(start, end);
+            if !copy && end > start {
+                buffer.remove_range(start, end);
+                buffer.set_mark(self.cursor, start);
+            }
+            // killing backwards adds to the front of the kill before
+            (text, cursor == start && start < end)
+        };
+        self.kill(text, before);
+        self.maybe_move_screen();
+    }
+
+    /// Cut from the cursor to the end of its line into the kill ring, or just
+    /// the newline when the cursor is already at the end.
+    pub fn kill_line(&mut self) {
+        let text = {
+            let mut buffer = self.buffer.lock().unwrap();
+            let idx = match buffer.get_mark_idx(self.cursor) {
+                Some(idx) => idx,
+                None => return,
+            };
+            let (_, line_end) = content_span(&buffer, buffer.line_of(idx));
+            let end = if idx < line_end { line_end } else { cmp::min(idx + 1, buffer.len() - 1) };
+            if end > idx {
+                let text = buffer.text_range(idx, end);
+                buffer.remove_range(idx, end);
+                Some(text)
+            } else {
+                None
+            }
+        };
+        match text {
+            Some(text) => self.kill(text, false),
+            None => self.show_message(String::from("End of buffer")),
+        }
+    }
+
+    fn kill(&mut self, text: String, before: bool) {
+        self.kill_ring.kill(text, self.killing, before);
+        self.killing = true;
+    }
+
+    /// Note that the last command wasn't a kill, so the next kill is kept
+    /// apart from the ones before it.
+    pub fn end_kill(&mut self) {
+        self.killing = false;
+    }
+
+    /// Put the most recent kill back in at the cursor, leaving the cursor
+    /// after it.
+    pub fn yank_kill(&mut self) {
+        let text = match self.kill_ring.yank() {
+            Some(text) => text.to_string(),
+            None => return self.show_message(String::from("Kill ring is empty")),
+        };
+        {
+            let mut buffer = self.buffer.lock().unwrap();
+            let idx = match buffer.get_mark_idx(self.cursor) {
+                Some(idx) => idx,
+                None => return,
+            };
+            buffer.insert_string_at(idx, &text);
+            let end = idx + text.chars().count();
+            buffer.set_mark(self.cursor, end);
+            self.yanked = Some((idx, end));
+        }
+        self.maybe_move_screen();
+    }
+
+    /// Swap the text just yanked for the kill before it in the kill ring.
+    pub fn yank_pop(&mut self) {
+        let (start, end) = match self.yanked {
+            Some(span) => span,
+            None => return self.show_message(String::from("Previous command was not a yank")),
+        };
+        let text = match self.kill_ring.yank_pop() {
+            Some(text) => text.to_string(),
+            None => return,
+        };
+        {
+            let mut buffer = self.buffer.lock().unwrap();
+            buffer.replace_range(start, end, &text);
+            let end = start + text.chars().count();
+            buffer.set_mark(self.cursor, end);
+            self.yanked = Some((start, end));
+        }
+        self.maybe_move_screen();
+    }
+
+    /// Note that the last command wasn't a yank, so there's nothing for
+    /// `yank_pop` to replace.
+    pub fn end_yank(&mut self) {
+        self.yanked = None;
+    }
+
+    /// Put back the text in the chosen register after the cursor, or before it
+    /// when `before` is set.  Whole lines go after or before the cursor's line.
+    pub fn put(&mut self, before: bool) {
+        let yank = match self.registers.get(self.register) {
+            Ok(Some(register)) => register.clone(),
+            Ok(None) => {
+                let name = self.register.unwrap_or('"');
+                return self.show_message(format!("Nothing in register {}", name));
+            }
+            Err(e) => return self.show_message(e.to_string()),
+        };
+        {
+            let mut buffer = self.buffer.lock().unwrap();
+            let idx = match buffer.get_mark_idx(self.cursor) {
+                Some(idx) => idx,
+                None => return,
+            };
+            if yank.linewise {
+                let line = buffer.line_of(idx);
+                let (start, end) = buffer.line_span(line, line);
+                if before {
+                    buffer.insert_string_at(start, &yank.text);
+                    buffer.set_mark(self.cursor, start);
+                } else if end > start && buffer.char_at(end - 1) == Some('\n') {
+                    buffer.insert_string_at(end, &yank.text);
+                    buffer.set_mark(self.cursor, end);
+                } else {
+                    // the last line has no newline to put the lines after
+                    let text = format!("\n{}", &yank.text[..yank.text.len() - 1]);
+                    buffer.insert_string_at(end, &text);
+                    buffer.set_mark(self.cursor, end + 1);
+                }
+            } else {
+                let at = match buffer.char_at(idx) {
+                    Some(c) if !before && c != '\n' => idx + 1,
+                    _ => idx,
+                };
+                buffer.insert_string_at(at, &yank.text);
+                // leave the cursor on the last char put back
+                let len = yank.text.chars().count();
+                buffer.set_mark(self.cursor, at + cmp::max(len, 1) - 1);
+            }
+        }
+        self.maybe_move_screen();
+    }
+
+    /// Start a new line below the cursor's line, or above it when `above` is
+    /// set, and move the cursor onto it.
+    pub fn open_line(&mut self, above: bool) {
+        {
+            let mut buffer = self.buffer.lock().unwrap();
+            let idx = match buffer.get_mark_idx(self.cursor) {
+                Some(idx) => idx,
+                None => return,
+            };
+            let (start, line_end) = content_span(&buffer, buffer.line_of(idx));
+            if above {
+                buffer.insert_string_at(start, "\n");
+                buffer.set_mark(self.cursor, start);
+            } else {
+                buffer.insert_string_at(line_end, "\n");
+                buffer.set_mark(self.cursor, line_end + 1);
+            }
+        }
+        self.maybe_move_screen();
+    }
+
+    /// Insert a chacter into the buffer, which the cursor moves on past.
+    pub fn insert_char(&mut self, ch: char) {
+        self.buffer.lock().unwrap().insert_char(self.cursor, ch);
+        self.registers.typed(ch);
+        self.maybe_move_screen();
+    }
+
+    /// Insert text at the cursor as it is, leaving the cursor after it.
+    pub fn insert_text(&mut self, text: &str) {
+        {
+            let mut buffer = self.buffer.lock().unwrap();
+            let idx = match buffer.get_mark_idx(self.cursor) {
+                Some(idx) => idx,
+                None => return,
+            };
+            // the cursor has right gravity, so it moves on past the text
+            buffer.insert_string_at(idx, text);
+        }
+        self.maybe_move_screen();
+    }
+
+    /// Start grouping edits to the buffer into a single undoable transaction.
+    pub fn start_transaction(&mut self) {
+        self.buffer.lock().unwrap().start_transaction();
+    }
+
+    /// Commit the transaction opened by `start_transaction`.
+    pub fn commit_transaction(&mut self) {
+        self.buffer.lock().unwrap().commit_transaction();
+    }
+
+    pub fn undo(&mut self) {
+        {
+            let mut buffer = self.buffer.lock().unwrap();
+            let point = if let Some(transaction) = buffer.undo() { transaction.end_point }
+                        else { return; };
+            buffer.set_mark(self.cursor, point);
+        }
+        self.maybe_move_screen();
+    }
+
+    pub fn redo(&mut self) {
+        {
+            let mut buffer = self.buffer.lock().unwrap();
+            let point = if let Some(transaction) = buffer.redo() { transaction.end_point }
+                        else { return; };
+            buffer.set_mark(self.cursor, point);
+        }
+        self.maybe_move_screen();
+    }
+
+    /// Switch to the next branch of the undo tree
+    pub fn undo_branch_next(&mut self) {
+        self.navigate_history(|buffer| buffer.undo_branch_next());
+    }
+
+    /// Switch to the previous branch of the undo tree
+    pub fn undo_branch_prev(&mut self) {
+        self.navigate_history(|buffer| buffer.undo_branch_prev());
+    }
+
+    /// Go back to the state the buffer was in `duration` ago
+    pub fn undo_earlier(&mut self, duration: Duration) {
+        self.navigate_history(|buffer| buffer.undo_earlier(duration));
+    }
+
+    /// Go forward to the state the buffer was in `duration` later
+    pub fn undo_later(&mut self, duration: Duration) {
+        self.navigate_history(|buffer| buffer.undo_later(duration));
+    }
+
+    /// Go to a state in the undo tree
+    pub fn undo_goto(&mut self, state: usize) {
+        self.navigate_history(|buffer| buffer.undo_goto(state));
+    }
+
+    /// The line number, counting from 1, that `address` refers to.
+    fn resolve_line(&self, buffer: &Buffer, address: LineAddress) -> usize {
+        let last = cmp::max(buffer.line_count(), 1);
+        let line = match address {
+            LineAddress::Number(n) => n,
+            LineAddress::Current => buffer.get_mark_display_coords(self.cursor).map_or(0, |(_, y)| y) + 1,
+            LineAddress::Last => last,
+            LineAddress::SelectionStart | LineAddress::SelectionEnd => {
+                let cursor = buffer.get_mark_idx(self.cursor).unwrap_or(0);
+                let (start, end) = self.selection_ends(buffer).unwrap_or((cursor, cursor));
+                let idx = if address == LineAddress::SelectionStart { start } else { end };
+                buffer.line_of(idx) + 1
+            }
+        };
+        cmp::max(1, cmp::min(line, last))
+    }
+
+    /// The first and last lines, counting from 1, that `range` covers.
+    fn resolve_range(&self, buffer: &Buffer, range: LineRange) -> (usize, usize) {
+        let start = self.resolve_line(buffer, range.start);
+        let end = self.resolve_line(buffer, range.end);
+        (cmp::min(start, end), cmp::max(start, end))
+    }
+
+    /// Move the cursor to the start of a line.
+    pub fn goto_line(&mut self, address: LineAddress) {
+        let line = self.resolve_line(&self.buffer.lock().unwrap(), address);
+        self.move_mark(self.cursor, TextObject {
+            kind: Kind::Line(Anchor::Start),
+            offset: Offset::Absolute(line),
+        });
+    }
+
+    /// Delete whole lines, leaving the cursor at the start of the line after them.
+    pub fn delete_lines(&mut self, range: LineRange) {
+        let first = {
+            let mut buffer = self.buffer.lock().unwrap();
+            let (first, last) = self.resolve_range(&buffer, range);
+            buffer.remove_lines(first, last);
+            first
+        };
+        self.goto_line(LineAddress::Number(first));
+    }
+
+    /// Replace matches of a pattern in some lines, reporting how many there were.
+    ///
+    /// A substitution which confirms each match moves the cursor to it and asks
+    /// whether to replace it, until it is answered by `answer_replace`.
+    pub fn substitute(&mut self, range: LineRange, substitution: &Substitution) {
+        let regex = match Regex::new(&substitution.pattern) {
+            Ok(regex) => regex,
+            Err(e) => {
+                self.show_message(format!("Bad pattern: {}", e));
+                return;
+            }
+        };
+        if substitution.confirm {
+            return self.start_replacing(range, regex, substitution);
+        }
+        let count = {
+            let mut buffer = self.buffer.lock().unwrap();
+            let (first, last) = self.resolve_range(&buffer, range);
+            buffer.substitute(first, last, &regex, &substitution.replacement, substitution.global)
+        };
+        if count == 0 {
+            self.show_message(format!("Pattern not found: {}", substitution.pattern));
+        } else {
+            self.show_message(format!("{} substitution(s)", count));
+        }
+    }
+
+    /// Start a substitution asking about each match, at the first of them.
+    fn start_replacing(&mut self, range: LineRange, regex: Regex, substitution: &Substitution) {
+        let (from, last) = {
+            let buffer = self.buffer.lock().unwrap();
+            let (first, last) = self.resolve_range(&buffer, range);
+            (buffer.line_span(first - 1, first - 1).0, last)
+        };
+        self.replacing = Some(Replacing {
+            regex: regex.clone(),
+            substitution: substitution.clone(),
+            from: from,
+            last: last,
+            current: None,
+            count: 0,
+            previous_end: None,
+        });
+        if !self.find_replacement() {
+            self.replacing = None;
+            return self.show_message(format!("Pattern not found: {}", substitution.pattern));
+        }
+
+        // the matches are highlighted, and can be searched for afterwards
+        self.search = Some(Search {
+            pattern: substitution.pattern.clone(),
+            regex: regex,
+            backward: false,
+        });
+        self.highlight_matches = true;
+        self.start_transaction();
+        self.ask_to_replace();
+    }
+
+    /// Carry on with the substitution asking about each match, as `answer` says.
+    pub fn answer_replace(&mut self, answer: ReplaceAnswer) {
+        if self.replacing.is_none() {
+            return;
+        }
+        match answer {
+            ReplaceAnswer::Yes => {
+                self.replace_current();
+                self.find_replacement();
+                self.ask_to_replace();
+            }
+            ReplaceAnswer::No => {
+                self.skip_current();
+                self.find_replacement();
+                self.ask_to_replace();
+            }
+            ReplaceAnswer::All => {
+                loop {
+                    self.replace_current();
+                    if !self.find_replacement() { break }
+                }
+                self.finish_replacing();
+            }
+            ReplaceAnswer::Quit => self.finish_replacing(),
+        }
+    }
+
+    /// Look for the next match to replace, returning whether there is one.
+    fn find_replacement(&mut self) -> bool {
+        let replacing = match self.replacing {
+            Some(ref mut replacing) => replacing,
+            None => return false,
+        };
+        let buffer = self.buffer.lock().unwrap();
+        loop {
+            replacing.current = buffer.find_substitution(replacing.from, replacing.last, &replacing.regex,
+                                                         &replacing.substitution.replacement);
+            match replacing.current {
+                Some((start, end, _)) if start == end && Some(start) == replacing.previous_end => {
+                    replacing.from = start + 1;
+                }
+                _ => return replacing.current.is_some(),
+            }
+        }
+    }
+
+    /// Move the cursor to the match found to replace, and ask about it, or
+    /// finish if there is none.
+    fn ask_to_replace(&mut self) {
+        let start = match self.replacing.as_ref().and_then(|r| r.current.as_ref()) {
+            Some(&(start, _, _)) => start,
+            None => return self.finish_replacing(),
+        };
+        self.buffer.lock().unwrap().set_mark(self.cursor, start);
+        self.maybe_move_screen();
+        self.set_overlay(OverlayType::ConfirmReplace);
+    }
+
+    /// Replace the match found, and look for the next one after it.
+    fn replace_current(&mut self) {
+        let replacing = match self.replacing {
+            Some(ref mut replacing) => replacing,
+            None => return,
+        };
+        let (start, end, replacement) = match replacing.current.take() {
+            Some(current) => current,
+            None => return,
+        };
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.replace_range(start, end, &replacement);
+        buffer.set_mark(self.cursor, start);
+        replacing.count += 1;
+        // lines put in by the replacement are still to be done
+        replacing.last += replacement.matches('\n').count();
+        let replaced_end = start + replacement.chars().count();
+        // step past an empty match, so it isn't found again
+        replacing.from = if replacing.substitution.global {
+            if start == end { replaced_end + 1 } else { replaced_end }
+        } else {
+            next_line_start(&buffer, cmp::max(replaced_end, start + 1) - 1)
+        };
+        replacing.previous_end = Some(replaced_end);
+    }
+
+    /// Leave the match found, and look for the next one after it.
+    fn skip_current(&mut self) {
+        let replacing = match self.replacing {
+            Some(ref mut replacing) => replacing,
+            None => return,
+        };
+        let (start, end, _) = match replacing.current.take() {
+            Some(current) => current,
+            None => return,
+        };
+        replacing.from = if replacing.substitution.global {
+            if start == end { end + 1 } else { end }
+        } else {
+            next_line_start(&self.buffer.lock().unwrap(), end)
+        };
+        replacing.previous_end = Some(end);
+    }
+
+    /// End the substitution asking about each match, so that what it replaced
+    /// is undone in one go.
+    fn finish_replacing(&mut self) {
+        let replacing = match self.replacing.take() {
+            Some(replacing) => replacing,
+            None => return,
+        };
+        self.overlay = None;
+        self.commit_transaction();
+        self.maybe_move_screen();
+        self.show_message(format!("{} substitution(s)", replacing.count));
+    }
+
+    /// Move through the buffer's history with `f`, placing the cursor at the
+    /// last change made.
+    fn navigate_history<F>(&mut self, f: F) where F: FnOnce(&mut Buffer) -> Option<usize> {
+        {
+            let mut buffer = self.buffer.lock().unwrap();
+            let point = if let Some(point) = f(&mut buffer) { point }
+                        else { return; };
+            buffer.set_mark(self.cursor, point);
+        }
+        self.maybe_move_screen();
+    }
+
+    fn save_buffer(&mut self) -> Result<(), SaveError> {
+        let buffer = self.buffer.lock().unwrap();
+        let path = match buffer.file_path {
+            Some(ref p) => p,
+            None => return Err(SaveError::NoFileName),
+        };
+
+        save::write_file(path, |file| {
+            //TODO (lee): Is iteration still necessary in this format?
+            for line in buffer.lines() {
+                let line: String = line.into_iter().collect();
+                file.write_all(line.as_bytes())?;
+            }
+            Ok(())
+        })?;
+
+        // the file is safely written, losing its history is not worth failing the save over
+        let _ = buffer.save_history();
+        Ok(())
+    }
+
+    /// Write the buffer out to its file.
+    ///
+    /// The buffer is only marked clean if the save succeeds.
+    pub fn try_save_buffer(&mut self) -> Result<(), SaveError> {
+        self.save_buffer()?;
+        self.buffer.lock().unwrap().dirty = false;
+        Ok(())
+    }
+
+    /// Whether or not the current buffer has unsaved changes
+    pub fn buffer_is_dirty(&mut self) -> bool {
+        self.buffer.lock().unwrap().dirty
+    }
+
+}
+
+/// Where the line after the one holding `idx` starts, or past the end of the
+/// buffer if it is the last line.
+fn next_line_start(buffer: &Buffer, idx: usize) -> usize {
+    let line = buffer.line_of(idx) + 1;
+    if line > buffer.line_of(buffer.len() - 1) {
+        buffer.len()
+    } else {
+        buffer.line_span(line, line).0
+    }
+}
+
+/// The span of `line`, leaving out the newline ending it.
+fn content_span(buffer: &Buffer, line: usize) -> (usize, usize) {
+    let (start, end) = buffer.line_span(line, line);
+    if end > start && buffer.char_at(end - 1) == Some('\n') {
+        (start, end - 1)
+    } else {
+        (start, end)
+    }
+}
+
+/// The part of `line` shown in the columns from `left` up to `right`, if it
+/// reaches that far.
+fn block_span(buffer: &Buffer, line: usize, left: usize, right: usize) -> Option<(usize, usize)> {
+    let (start, _) = index_at_column(buffer, line, left);
+    let (end, _) = index_at_column(buffer, line, right);
+    if start >= end {
+        None
+    } else {
+        Some((start, end))
+    }
+}
+
+/// How many columns of the screen `ch` takes up when shown at column `col`.
+/// The newline ending a line, or the end of the buffer, takes up one.
+fn width_at(ch: Option<char>, col: usize) -> usize {
+    ch.and_then(|ch| utils::char_width(ch, false, 4, col)).unwrap_or(1)
+}
+
+/// The columns the char at `idx` is shown in, from the first up to just past
+/// the last, counting from the start of its line.
+fn columns_of(buffer: &Buffer, idx: usize) -> (usize, usize) {
+    let (line_start, _) = content_span(buffer, buffer.line_of(idx));
+    let col = (line_start..idx).fold(0, |col, i| col + width_at(buffer.char_at(i), col));
+    (col, col + width_at(buffer.char_at(idx), col))
+}
+
+/// The first char of `line` shown at or past `column`, and the column it is
+/// shown at, or the end of the line and its column if the line stops short.
+fn index_at_column(buffer: &Buffer, line: usize, column: usize) -> (usize, usize) {
+    let (start, end) = content_span(buffer, line);
+    let mut col = 0;
+    for idx in start..end {
+        if col >= column {
+            return (idx, col);
+        }
+        col += width_at(buffer.char_at(idx), col);
+    }
+    (end, col)
+}
+
+/// `text` with its upper case letters made lower case, and the other way round.
+fn toggle_case(text: &str) -> String {
+    text.chars().flat_map(|c| {
+        let swapped: Vec<char> = if c.is_lowercase() {
+            c.to_uppercase().collect()
+        } else {
+            c.to_lowercase().collect()
+        };
+        swapped
+    }).collect()
+}
+
+/// Draw a single line of the buffer at row `idx` of the screen.
+///
+/// `left` is the number of display columns scrolled off the left edge.
+///
+/// The chars of the line from `selected.0` up to `selected.1` are highlighted.
+pub fn draw_line(rb: &mut RustBox, line: &[char], idx: usize, left: usize, selected: Option<(usize, usize)>,
+                 matches: &[(usize, usize)]) {
+    let width = rb.width() - 1;
+    // column within the line, and column on the screen
+    let mut col = 0;
+    let mut x = 0;
+    let mut truncated = false;
+
+    for (i, &ch) in line.iter().enumerate() {
+        if ch == '\n' {
+            break;
+        }
+        if x >= width {
+            truncated = true;
+            break;
+        }
+
+        let start = col;
+        let w = utils::char_width(ch, false, 4, col).unwrap_or(1);
+        col += w;
+        if col <= left {
+            continue;
+        }
+
+        let (fg, bg) = match selected {
+            Some((from, to)) if i >= from && i < to => (Color::Black, Color::White),
+            _ if matches.iter().any(|&(from, to)| i >= from && i < to) => (Color::Black, Color::Yellow),
+            _ => (Color::White, Color::Black),
+        };
+        match ch {
+            // partially scrolled off tabs and wide chars are padded out with spaces
+            '\t' => {
+                for _ in cmp::max(start, left)..col {
+                    rb.print_char(x, idx, RustBoxStyle::empty(), fg, bg, ' ');
+                    x += 1;
+                }
+            }
+            _ if start < left => {
+                for _ in left..col {
+                    rb.print_char(x, idx, RustBoxStyle::empty(), fg, bg, ' ');
+                    x += 1;
+                }
+            }
+            // combining characters can't be drawn in a cell of their own
+            _ if w == 0 => {}
+            _ => {
+                rb.print_char(x, idx, RustBoxStyle::empty(), fg, bg, ch);
+                x += w;
+            }
+        }
+        if x >= width {
+            truncated = line[i + 1..].iter().any(|&c| c != '\n');
+            break;
+        }
+    }
+
+    // Replace any cells after end of line with ' '
+    while x < width {
+        rb.print_char(x, idx, RustBoxStyle::empty(), Color::White, Color::Black, ' ');
+        x += 1;
+    }
+
+    // If the line is too long to fit on the screen, show an indicator
+    let indicator = if truncated { '→' } else { ' ' };
+    rb.print_char(width, idx, RustBoxStyle::empty(), Color::White, Color::Black, indicator);
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use std::rc::Rc;
+
+    use save::SaveError;
+    use view::{Selection, View, MAX_MESSAGES};
+    use buffer::{Buffer, Mark};
+    use command::{LineAddress, LineRange, Operator, ReplaceAnswer, Substitution};
+    use search::CaseMatching;
+    use textobject::{Anchor, Kind, Offset, TextObject};
+
+    fn setup_view(testcase: &'static str) -> View {
+        let buffer = Arc::new(Mutex::new(Buffer::new()));
+        let mut view = View::new(buffer.clone(), 50, 50);
+        for ch in testcase.chars() {
+            view.insert_char(ch);
+        }
+
+        let mut buffer = buffer.lock().unwrap();
+        buffer.set_mark(view.cursor, 0);
+        view
+    }
+
+    #[test]
+    fn test_insert_char() {
+        let mut view = setup_view("test\nsecond");
+        view.insert_char('t');
+
+        {
+            let mut buffer = view.buffer.lock().unwrap();
+            assert_eq!(buffer.lines().next().unwrap(), ['t', 't', 'e', 's', 't', '\n']);
+        }
+    }
+
+    #[test]
+    fn test_insert_multibyte_char() {
+        let mut view = setup_view("");
+        view.insert_char('日');
+        view.insert_char('é');
+
+        {
+            let buffer = view.buffer.lock().unwrap();
+            assert_eq!(buffer.lines().next().unwrap(), ['日', 'é']);
+            assert_eq!(buffer.get_mark_idx(view.cursor), Some(2));
+        }
+    }
+
+    #[test]
+    fn failed_save_leaves_buffer_dirty() {
+        let mut view = setup_view("test");
+        match view.try_save_buffer() {
+            Err(SaveError::NoFileName) => {}
+            _ => panic!("expected the save to fail without a file name"),
+        }
+
+        view.buffer.lock().unwrap().file_path = Some(PathBuf::from("no/such/dir/file.txt"));
+        assert!(view.try_save_buffer().is_err());
+        assert!(view.buffer_is_dirty());
+    }
+
+    #[test]
+    fn switching_back_keeps_cursor() {
+        let mut view = setup_view("first buffer");
+        let first = view.buffer.clone();
+        first.lock().unwrap().set_mark(view.cursor, 5);
+
+        view.set_buffer(Arc::new(Mutex::new(Buffer::new())));
+        view.switch_last_buffer();
+        assert_eq!(view.buffer.lock().unwrap().get_mark_idx(view.cursor), Some(5));
+
+        view.set_buffer(Arc::new(Mutex::new(Buffer::new())));
+        view.set_buffer(first);
+        assert_eq!(view.buffer.lock().unwrap().get_mark_idx(view.cursor), Some(5));
+    }
+
+    #[test]
+    fn message_history_is_capped() {
+        let mut view = setup_view("");
+        for i in 0..MAX_MESSAGES + 5 {
+            view.show_message(format!("message {}", i));
+        }
+        assert_eq!(view.messages.len(), MAX_MESSAGES);
+        assert_eq!(view.messages[0].0, "message 5");
+    }
+
+    fn text(view: &View) -> String {
+        let buffer = view.buffer.lock().unwrap();
+        buffer.text_range(0, buffer.len() - 1)
+    }
+
+    fn cursor_at(view: &View, idx: usize) {
+        view.buffer.lock().unwrap().set_mark(view.cursor, idx);
+    }
+
+    #[test]
+    fn delete_lines_and_put_them_back() {
+        let mut view = setup_view("one\ntwo\nthree\n");
+        cursor_at(&view, 5);
+        view.operate_on_lines(Operator::Delete, 2);
+        assert_eq!(text(&view), "one\n");
+
+        view.put(true);
+        assert_eq!(text(&view), "two\nthree\none\n");
+        assert_eq!(view.buffer.lock().unwrap().get_mark_idx(view.cursor), Some(0));
+    }
+
+    #[test]
+    fn delete_last_line_takes_newline_before() {
+        let mut view = setup_view("one\ntwo");
+        cursor_at(&view, 5);
+        view.operate_on_lines(Operator::Delete, 1);
+        assert_eq!(text(&view), "one");
+
+        // and putting it after the last line adds the newline back
+        view.put(false);
+        assert_eq!(text(&view), "one\ntwo");
+    }
+
+    #[test]
+    fn line_motions_take_whole_lines() {
+        let mut view = setup_view("one\ntwo\nthree\n");
+        cursor_at(&view, 1);
+        let down = TextObject { kind: Kind::Line(Anchor::Same), offset: Offset::Forward(1, Mark::Cursor(0)) };
+        view.operate(Operator::Delete, Mark::Cursor(0), down);
+        assert_eq!(text(&view), "three\n");
+    }
+
+    #[test]
+    fn operators_apply_up_to_a_match() {
+        let mut view = setup_view("let x = foo(1);\nfoo(2);\n");
+        cursor_at(&view, 4);
+        let to_foo = |offset| TextObject { kind: Kind::Match(String::from("foo"), Anchor::Start), offset: offset };
+        view.operate(Operator::Delete, Mark::Cursor(0), to_foo(Offset::Forward(1, Mark::Cursor(0))));
+        assert_eq!(text(&view), "let foo(1);\nfoo(2);\n");
+
+        view.move_mark(Mark::Cursor(0), to_foo(Offset::Forward(1, Mark::Cursor(0))));
+        assert_eq!(cursor(&view), Some(12));
+        view.operate(Operator::Delete, Mark::Cursor(0), to_foo(Offset::Backward(1, Mark::Cursor(0))));
+        assert_eq!(text(&view), "let foo(2);\n");
+    }
+
+    #[test]
+    fn deleting_chars_keeps_to_the_line() {
+        let mut view = setup_view("a\n\nbcd\n");
+        let chars = |offset| TextObject { kind: Kind::LineChar, offset: offset };
+
+        // `x` on an empty line, and `X` at the start of one, leave the lines apart
+        cursor_at(&view, 2);
+        view.operate(Operator::Delete, Mark::Cursor(0), chars(Offset::Forward(1, Mark::Cursor(0))));
+        assert_eq!(text(&view), "a\n\nbcd\n");
+        cursor_at(&view, 3);
+        view.operate(Operator::Delete, Mark::Cursor(0), chars(Offset::Backward(1, Mark::Cursor(0))));
+        assert_eq!(text(&view), "a\n\nbcd\n");
+
+        // a count goes no further than either end of the line
+        cursor_at(&view, 5);
+        view.operate(Operator::Delete, Mark::Cursor(0), chars(Offset::Backward(5, Mark::Cursor(0))));
+        assert_eq!(text(&view), "a\n\nd\n");
+        view.operate(Operator::Delete, Mark::Cursor(0), chars(Offset::Forward(5, Mark::Cursor(0))));
+        assert_eq!(text(&view), "a\n\n\n");
+    }
+
+    #[test]
+    fn change_word_and_yank_put() {
+        let mut view = setup_view("some test content");
+        let word = TextObject { kind: Kind::Word(Anchor::Start), offset: Offset::Forward(1, Mark::Cursor(0)) };
+        view.operate(Operator::Yank, Mark::Cursor(0), word.clone());
+        assert_eq!(text(&view), "some test content");
+
+        view.put(false);
+        assert_eq!(text(&view), "ssome ome test content");
+
+        cursor_at(&view, 0);
+        view.operate(Operator::Change, Mark::Cursor(0), word);
+        assert_eq!(text(&view), "ome test content");
+        assert_eq!(view.buffer.lock().unwrap().get_mark_idx(view.cursor), Some(0));
+    }
+
+    #[test]
+    fn change_line_leaves_empty_line() {
+        let mut view = setup_view("one\ntwo\nthree");
+        cursor_at(&view, 5);
+        view.operate_on_lines(Operator::Change, 1);
+        assert_eq!(text(&view), "one\n\nthree");
+        assert_eq!(view.buffer.lock().unwrap().get_mark_idx(view.cursor), Some(4));
+    }
+
+    #[test]
+    fn open_lines() {
+        let mut view = setup_view("one\ntwo");
+        cursor_at(&view, 1);
+        view.open_line(false);
+        assert_eq!(text(&view), "one\n\ntwo");
+        assert_eq!(view.buffer.lock().unwrap().get_mark_idx(view.cursor), Some(4));
+
+        view.open_line(true);
+        assert_eq!(text(&view), "one\n\n\ntwo");
+        assert_eq!(view.buffer.lock().unwrap().get_mark_idx(view.cursor), Some(4));
+    }
+
+    fn select(view: &mut View, selection: Selection, from: usize, to: usize) {
+        cursor_at(view, from);
+        view.select(selection);
+        cursor_at(view, to);
+    }
+
+    #[test]
+    fn selection_includes_char_under_cursor() {
+        let mut view = setup_view("some test content");
+        select(&mut view, Selection::Chars, 8, 5);
+        view.operate_on_selection(Operator::Delete);
+        assert_eq!(text(&view), "some  content");
+
+        view.put(true);
+        assert_eq!(text(&view), "some test content");
+    }
+
+    #[test]
+    fn line_selection_indents_and_outdents() {
+        let mut view = setup_view("one\n\ntwo\nthree\n");
+        select(&mut view, Selection::Lines, 1, 6);
+        view.operate_on_selection(Operator::Indent);
+        assert_eq!(text(&view), "    one\n\n    two\nthree\n");
+        assert_eq!(view.buffer.lock().unwrap().get_mark_idx(view.cursor), Some(4));
+
+        select(&mut view, Selection::Lines, 0, 9);
+        view.operate_on_selection(Operator::Outdent);
+        assert_eq!(text(&view), "one\n\ntwo\nthree\n");
+    }
+
+    #[test]
+    fn selection_toggles_case() {
+        let mut view = setup_view("Some Test");
+        select(&mut view, Selection::Chars, 0, 6);
+        view.operate_on_selection(Operator::ToggleCase);
+        assert_eq!(text(&view), "sOME tEst");
+    }
+
+    #[test]
+    fn block_delete_takes_columns() {
+        let mut view = setup_view("abcd\nef\nghij\n");
+        // from column 1 of the first line to column 2 of the last
+        select(&mut view, Selection::Block, 1, 10);
+        view.operate_on_selection(Operator::Delete);
+        assert_eq!(text(&view), "ad\ne\ngj\n");
+        assert_eq!(view.buffer.lock().unwrap().get_mark_idx(view.cursor), Some(1));
+    }
+
+    #[test]
+    fn blocks_are_in_screen_columns() {
+        let mut view = setup_view("日本語\nabcdef\n\tx\n");
+        // from the second wide char, at columns 2 and 3, down to the c below it
+        select(&mut view, Selection::Block, 1, 6);
+        let selected = {
+            let buffer = view.buffer.lock().unwrap();
+            (0..3).map(|line| view.selected_columns(&buffer, line)).collect::<Vec<_>>()
+        };
+        // the tab covers the block's columns without starting in them
+        assert_eq!(selected, vec![Some((1, 2)), Some((2, 4)), None]);
+
+        view.operate_on_selection(Operator::Delete);
+        assert_eq!(text(&view), "日語\nabef\n\tx\n");
+        assert_eq!(cursor(&view), Some(1));
+    }
+
+    #[test]
+    fn block_insert_copies_to_every_line() {
+        let mut view = setup_view("abc\nd\nefg\n");
+        select(&mut view, Selection::Block, 1, 8);
+        view.insert_in_block(false);
+        view.insert_char('X');
+        view.insert_char('Y');
+        view.finish_block_insert();
+        // the short middle line doesn't reach the block, so is skipped
+        assert_eq!(text(&view), "aXYbc\nd\neXYfg\n");
+    }
+
+    #[test]
+    fn block_append_pads_short_lines() {
+        let mut view = setup_view("abc\nd\nefg\n");
+        select(&mut view, Selection::Block, 1, 8);
+        view.insert_in_block(true);
+        view.insert_char('|');
+        view.finish_block_insert();
+        assert_eq!(text(&view), "abc|\nd  |\nefg|\n");
+    }
+
+    #[test]
+    fn registers_keep_text_apart() {
+        let mut view = setup_view("one\ntwo\nthree\n");
+        view.use_register('a');
+        view.operate_on_lines(Operator::Yank, 1);
+        view.clear_register();
+        cursor_at(&view, 4);
+        view.operate_on_lines(Operator::Delete, 1);
+        assert_eq!(text(&view), "one\nthree\n");
+
+        view.use_register('a');
+        view.put(false);
+        view.clear_register();
+        assert_eq!(text(&view), "one\nthree\none\n");
+
+        // putting from a named register leaves the unnamed one be
+        view.put(true);
+        assert_eq!(text(&view), "one\nthree\ntwo\none\n");
+    }
+
+    #[test]
+    fn typed_text_can_be_put_back() {
+        let mut view = setup_view("");
+        view.insert_char('h');
+        view.insert_char('i');
+        view.finish_insert();
+        view.use_register('.');
+        view.put(false);
+        assert_eq!(text(&view), "hihi");
+    }
+
+    #[test]
+    fn pasted_text_is_inserted_as_it_is() {
+        let mut view = setup_view("ab\n");
+        cursor_at(&view, 1);
+        view.start_transaction();
+        view.insert_text("\tx\ny");
+        view.commit_transaction();
+        assert_eq!(text(&view), "a\tx\nyb\n");
+        assert_eq!(view.buffer.lock().unwrap().get_mark_idx(view.cursor), Some(5));
+
+        view.undo();
+        assert_eq!(text(&view), "ab\n");
+    }
+
+    fn cursor(view: &View) -> Option<usize> {
+        view.buffer.lock().unwrap().get_mark_idx(view.cursor)
+    }
+
+    #[test]
+    fn clicks_land_on_the_char_drawn_there() {
+        let mut view = setup_view("one\n\ttwo\nthree\n");
+        view.buffer.lock().unwrap().set_mark(view.top_line, 0);
+        view.click(1, 0);
+        assert_eq!(cursor(&view), Some(1));
+        // the tab covers the first four columns
+        view.click(2, 1);
+        assert_eq!(cursor(&view), Some(4));
+        view.click(5, 1);
+        assert_eq!(cursor(&view), Some(6));
+        // past the end of a line, and below the end of the buffer
+        view.click(30, 0);
+        assert_eq!(cursor(&view), Some(3));
+        view.click(2, 10);
+        assert_eq!(cursor(&view), Some(15));
+
+        // scrolled sideways
+        view.left_col = 2;
+        view.click(0, 2);
+        assert_eq!(cursor(&view), Some(11));
+    }
+
+    #[test]
+    fn dragging_and_double_clicks_select() {
+        let mut view = setup_view("one two\nthree\n");
+        view.buffer.lock().unwrap().set_mark(view.top_line, 0);
+        view.click(1, 0);
+        view.select(Selection::Chars);
+        view.drag_to(1, 1);
+        view.operate_on_selection(Operator::Delete);
+        assert_eq!(text(&view), "oree\n");
+
+        let mut view = setup_view("one two_2 three\n");
+        view.buffer.lock().unwrap().set_mark(view.top_line, 0);
+        view.select_word_at(6, 0, Selection::Region);
+        view.kill_region(false);
+        assert_eq!(text(&view), "one  three\n");
+
+        view.select_word_at(4, 0, Selection::Chars);
+        view.operate_on_selection(Operator::Delete);
+        assert_eq!(text(&view), "one three\n");
+    }
+
+    #[test]
+    fn scrolling_leaves_the_cursor() {
+        let mut view = setup_view("1\n2\n3\n4\n5\n");
+        view.buffer.lock().unwrap().set_mark(view.top_line, 0);
+        view.scroll(3);
+        assert_eq!(view.buffer.lock().unwrap().get_mark_idx(view.top_line), Some(6));
+        assert_eq!(cursor(&view), Some(0));
+        view.scroll(10);
+        assert_eq!(view.buffer.lock().unwrap().get_mark_idx(view.top_line), Some(10));
+        view.scroll(-10);
+        assert_eq!(view.buffer.lock().unwrap().get_mark_idx(view.top_line), Some(0));
+    }
+
+    #[test]
+    fn substitutions_can_ask_about_each_match() {
+        let mut view = setup_view("a a\nb a\na\n");
+        let every_line = LineRange::parse("%").unwrap();
+        view.substitute(every_line, &Substitution::parse("/a/(&)/gc").unwrap());
+        assert_eq!(cursor(&view), Some(0));
+        assert!(view.overlay.is_some());
+
+        view.answer_replace(ReplaceAnswer::Yes);
+        assert_eq!(cursor(&view), Some(4));
+        view.answer_replace(ReplaceAnswer::No);
+        assert_eq!(cursor(&view), Some(8));
+        view.answer_replace(ReplaceAnswer::All);
+        assert_eq!(text(&view), "(a) a\nb (a)\n(a)\n");
+        assert!(view.overlay.is_none());
+        assert_eq!(message(&view), Some(String::from("3 substitution(s)")));
+
+        // everything replaced is undone at once
+        view.undo();
+        assert_eq!(text(&view), "a a\nb a\na\n");
+
+        // without the g flag, only the first match on each line is asked about
+        view.substitute(every_line, &Substitution::parse("/a/x/c").unwrap());
+        view.answer_replace(ReplaceAnswer::Yes);
+        assert_eq!(cursor(&view), Some(6));
+        view.answer_replace(ReplaceAnswer::Quit);
+        assert_eq!(text(&view), "x a\nb a\na\n");
+        assert!(view.overlay.is_none());
+
+        view.substitute(every_line, &Substitution::parse("/z/x/c").unwrap());
+        assert!(view.overlay.is_none());
+        assert_eq!(message(&view), Some(String::from("Pattern not found: z")));
+    }
+
+    #[test]
+    fn substitutions_can_ask_about_empty_matches() {
+        let every_line = LineRange::parse("%").unwrap();
+        for &(start, substitution) in &[("a\n\nb\n", "/^/# /"), ("baab\nb", "/a*/-/g"), ("a\nb", "/$/;/")] {
+            let mut asking = setup_view(start);
+            let confirming = format!("{}c", substitution);
+            asking.substitute(every_line, &Substitution::parse(&confirming).unwrap());
+            asking.answer_replace(ReplaceAnswer::All);
+            assert!(asking.overlay.is_none());
+
+            let mut replacing = setup_view(start);
+            replacing.substitute(every_line, &Substitution::parse(substitution).unwrap());
+            assert_eq!(text(&asking), text(&replacing));
+        }
+        let mut view = setup_view("a\n\nb\n");
+        view.substitute(every_line, &Substitution::parse("/^/# /c").unwrap());
+        view.answer_replace(ReplaceAnswer::Yes);
+        view.answer_replace(ReplaceAnswer::No);
+        view.answer_replace(ReplaceAnswer::Yes);
+        assert_eq!(text(&view), "# a\n\n# b\n");
+        assert!(view.overlay.is_none());
+    }
+
+    #[test]
+    fn substitutions_can_cover_the_selection() {
+        let mut view = setup_view("a\na\na\na\n");
+        view.buffer.lock().unwrap().set_mark(view.cursor, 2);
+        view.select(Selection::Lines);
+        view.buffer.lock().unwrap().set_mark(view.cursor, 4);
+        let selected = LineRange { start: LineAddress::SelectionStart, end: LineAddress::SelectionEnd };
+        view.substitute(selected, &Substitution::parse("/a/b/").unwrap());
+        assert_eq!(text(&view), "a\nb\nb\na\n");
+    }
+
+    fn message(view: &View) -> Option<String> {
+        view.message.as_ref().map(|&(ref message, _)| message.clone())
+    }
+
+    #[test]
+    fn search_moves_as_it_is_typed() {
+        let mut view = setup_view("one two\ntwo one\n");
+        view.search_preview("o", false);
+        assert_eq!(cursor(&view), Some(6));
+        view.search_preview("on", false);
+        assert_eq!(cursor(&view), Some(12));
+        // no match, or a pattern which doesn't make sense yet, stays put
+        view.search_preview("onx", false);
+        assert_eq!(cursor(&view), Some(0));
+        view.search_preview("on(", false);
+        assert_eq!(cursor(&view), Some(0));
+
+        view.search_preview("two", false);
+        view.cancel_search();
+        assert_eq!(cursor(&view), Some(0));
+        assert!(view.search.is_none());
+        assert!(view.matched_columns(&['t', 'w', 'o']).is_empty());
+
+        view.search_preview("two", false);
+        view.search("two", false);
+        assert_eq!(cursor(&view), Some(4));
+        assert_eq!(view.matched_columns(&['a', 't', 'w', 'o', '\n']), vec![(1, 4)]);
+    }
+
+    #[test]
+    fn searches_repeat_and_wrap() {
+        let mut view = setup_view("one two\ntwo one\n");
+        view.search_next(false);
+        assert_eq!(message(&view), Some(String::from("No previous search")));
+
+        view.search("one", false);
+        assert_eq!(cursor(&view), Some(12));
+        view.search_next(false);
+        assert_eq!(cursor(&view), Some(0));
+        assert_eq!(message(&view), Some(String::from("Search wrapped around to the top")));
+        view.search_next(true);
+        assert_eq!(cursor(&view), Some(12));
+        assert_eq!(message(&view), Some(String::from("Search wrapped around to the bottom")));
+
+        // backward searches repeat backward, and an empty pattern repeats the last
+        view.search("", true);
+        assert_eq!(cursor(&view), Some(0));
+        view.search("three", false);
+        assert_eq!(message(&view), Some(String::from("Pattern not found: three")));
+        assert_eq!(cursor(&view), Some(0));
+    }
+
+    #[test]
+    fn searches_can_turn_round() {
+        let mut view = setup_view("one two\ntwo one\n");
+        view.search_preview("o", false);
+        assert_eq!(cursor(&view), Some(6));
+        // as with C-r pressed twice while typing a search
+        view.search_again(true);
+        assert_eq!(cursor(&view), Some(0));
+        view.search_again(true);
+        assert_eq!(cursor(&view), Some(12));
+        view.search("o", true);
+        assert_eq!(cursor(&view), Some(10));
+    }
+
+    #[test]
+    fn empty_matches_can_be_moved_to() {
+        let mut view = setup_view("one\n\ntwo three\n");
+        view.search("^$", false);
+        assert_eq!(cursor(&view), Some(4));
+        view.search("\\b", false);
+        assert_eq!(cursor(&view), Some(5));
+        view.search_next(false);
+        assert_eq!(cursor(&view), Some(8));
+        // but there is nothing to highlight
+        assert!(view.matched_columns(&"two three".chars().collect::<Vec<_>>()).is_empty());
+    }
+
+    #[test]
+    fn search_case_can_be_ignored() {
+        let mut view = setup_view("One one\n");
+        view.search("one", false);
+        assert_eq!(cursor(&view), Some(4));
+
+        view.set_search_case(CaseMatching::Insensitive);
+        view.search_next(false);
+        assert_eq!(cursor(&view), Some(0));
+
+        view.set_search_case(CaseMatching::Smart);
+        view.search("One", false);
+        assert_eq!(cursor(&view), Some(0));
+        assert_eq!(message(&view), Some(String::from("Search wrapped around to the top")));
+
+        // operators going up to a match follow the same setting
+        view.set_search_case(CaseMatching::Insensitive);
+        let to_one = TextObject { kind: Kind::Match(String::from("ONE"), Anchor::Start), offset: Offset::Forward(1, Mark::Cursor(0)) };
+        view.operate(Operator::Delete, Mark::Cursor(0), to_one);
+        assert_eq!(text(&view), "one\n");
+    }
+
+    #[test]
+    fn kill_lines_join_until_something_else_happens() {
+        let mut view = setup_view("one\ntwo\n");
+        view.kill_line();
+        view.kill_line();
+        assert_eq!(text(&view), "two\n");
+
+        view.end_kill();
+        view.yank_kill();
+        assert_eq!(text(&view), "one\ntwo\n");
+        assert_eq!(view.buffer.lock().unwrap().get_mark_idx(view.cursor), Some(4));
+    }
+
+    #[test]
+    fn region_kills_and_yank_pop() {
+        let mut view = setup_view("one two\n");
+        view.set_region_mark();
+        cursor_at(&view, 3);
+        view.kill_region(true);
+        assert_eq!(text(&view), "one two\n");
+        assert_eq!(view.selection, None);
+        view.end_kill();
+
+        cursor_at(&view, 4);
+        view.set_region_mark();
+        cursor_at(&view, 7);
+        view.kill_region(false);
+        assert_eq!(text(&view), "one \n");
+        view.end_kill();
+
+        view.yank_kill();
+        assert_eq!(text(&view), "one two\n");
+        view.yank_pop();
+        assert_eq!(text(&view), "one one\n");
+        view.yank_pop();
+        assert_eq!(text(&view), "one two\n");
+
+        view.end_yank();
+        view.yank_pop();
+        assert_eq!(text(&view), "one two\n");
     }
 }
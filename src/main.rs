@@ -7,6 +7,7 @@ extern crate docopt;
 extern crate iota;
 
 use std::io::stdin;
+use std::time::Duration;
 use docopt::Docopt;
 use iota::{
     Editor, Input,
@@ -15,21 +16,24 @@ use iota::{
 };
 use rustbox::{InitOptions, RustBox, InputMode, OutputMode};
 static USAGE: &'static str = "
-Usage: iota [<filename>] [options]
+Usage: iota [<filename>...] [options]
        iota --help
 
 Options:
     --emacs                        Start Iota with emacs-like mode
     --vi                           Start Iota with vi-like modes
+    --esc-timeout=<ms>             How long to wait after Esc for the rest of a key
+                                   sequence, such as Alt-x [default: 50]
     -h, --help                     Show this message.
 ";
 
 
 #[derive(RustcDecodable, Debug)]
 struct Args {
-    arg_filename: Option<String>,
+    arg_filename: Vec<String>,
     flag_emacs: bool,
     flag_vi: bool,
+    flag_esc_timeout: u64,
     flag_help: bool,
 }
 
@@ -49,7 +53,7 @@ fn main() {
 
     // editor source - either a filename or stdin
     let source = if stdin_is_atty {
-        Input::Filename(args.arg_filename)
+        Input::Filenames(args.arg_filename)
     } else {
         Input::Stdin(stdin())
     };
@@ -58,7 +62,7 @@ fn main() {
     // initialise rustbox
     let rb = match RustBox::init(InitOptions{
         buffer_stderr: stderr_is_atty,
-        input_mode: InputMode::Esc,
+        input_mode: InputMode::EscMouse,
         output_mode: OutputMode::EightBit,
     }) {
         Result::Ok(v) => v,
@@ -76,5 +80,6 @@ fn main() {
 
     // start the editor
     let mut editor = Editor::new(source, mode, rb);
+    editor.set_escape_timeout(Duration::from_millis(args.flag_esc_timeout));
     editor.start();
 }